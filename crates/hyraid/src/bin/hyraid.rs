@@ -16,15 +16,40 @@
     51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 */
 
-use hyraid_mapper;
-use hyraid_utils::is_root;
+use hyraid_mapper::Backend;
+use hyraid_utils::{
+    is_root,
+    parse_size,
+    format_size,
+    HyraidError
+};
+use hyraid_types::{
+    HyraidPlan,
+    LvmPlan,
+    CapacityEstimate,
+    CapacityReport,
+    ArrayStatus,
+    MdHealth,
+    HyraidArray,
+    ScanReport,
+    UpgradeReport,
+    RedundancyPolicy,
+    RedundancyReport,
+    ScrubAction,
+    ScrubSchedule,
+    ScrubStatus,
+    Journal
+};
+use hyraid_monitor::Monitor;
+use hyraid_mdstat::SyncProgress;
 
 #[cfg(feature = "unittest")]
 use std::fs::File;
 
 use std::{
     io::{self, Write}, 
-    process
+    process,
+    sync::Arc
 };
 use clap::{Parser, Subcommand};
 
@@ -46,8 +71,20 @@ enum Commands {
         #[arg(long, value_name = "RAID level")]
         raid_level: usize,
 
+        /// Redundancy policy: raid-level, or two-disk to survive any two disk failures (needs RAID level 6)
+        #[arg(long, value_name = "Policy", default_value = "raid-level")]
+        policy: RedundancyPolicy,
+
         /// Disks to use
-        disks: Vec<String>
+        disks: Vec<String>,
+
+        /// Only show what would be done, without touching any disk
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Use the layout even if some slice groups survive fewer disk failures than requested
+        #[arg(long)]
+        allow_degraded_layout: bool
     },
     Fail {
         /// Name of the HyRAID array
@@ -70,10 +107,278 @@ enum Commands {
         name: String,
 
         /// Disks to use
+        disks: Vec<String>,
+
+        /// Only show what would be done, without touching any disk
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Use the layout even if some slice groups survive fewer disk failures than requested
+        #[arg(long)]
+        allow_degraded_layout: bool
+    },
+    /// Replace a disk of a HyRAID array with a new one of at least the same size
+    Replace {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String,
+
+        /// Disk to replace
+        old: String,
+
+        /// Disk to replace it with
+        new: String,
+
+        /// Grow into the new disk even if some slice groups survive fewer disk failures than requested
+        #[arg(long)]
+        allow_degraded_layout: bool
+    },
+    /// Move the data off a disk and remove it from a HyRAID array for good
+    Evacuate {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String,
+
+        /// Disk to remove
+        disk: String
+    },
+    /// Put free space on the disks of a HyRAID array to use, e.g. after swapping in larger disks
+    Grow {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String,
+
+        /// Use the layout even if some slice groups survive fewer disk failures than requested
+        #[arg(long)]
+        allow_degraded_layout: bool
+    },
+    /// Remove a HyRAID array along with its md devices and partitions
+    Destroy {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String
+    },
+    /// List all HyRAID arrays
+    List,
+    /// Show the health of a HyRAID array
+    Status {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String
+    },
+    /// Print gauges of every HyRAID array in the Prometheus text format,
+    /// e.g. for the textfile collector of node_exporter
+    Metrics,
+    /// Find HyRAID arrays from the metadata on the disks, without writing anything
+    Scan,
+    /// Write HyRAID arrays found on the disks to the state file
+    Import {
+        /// Only import the array of this volume group
+        #[arg(long, value_name = "Volume group")]
+        volume_group: Option<String>,
+
+        /// Name to give the imported array, requires --volume-group
+        #[arg(long = "array-name", value_name = "Array name", requires = "volume_group")]
+        name: Option<String>
+    },
+    /// Assemble the md devices of every HyRAID array, or of one, and activate their logical volumes
+    Assemble {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: Option<String>
+    },
+    /// Manage spare disks of a HyRAID array, or the global spare pool
+    Spare {
+        #[command(subcommand)]
+        command: SpareCommands,
+    },
+    /// Resume or roll back a create or add that was interrupted by a crash or a failed step
+    Recover {
+        /// Undo the steps it took instead of carrying on
+        #[arg(long)]
+        rollback: bool,
+
+        /// Only show what the operation got through
+        #[arg(long, conflicts_with = "rollback")]
+        status: bool
+    },
+    /// Check the parity and mirrors of a HyRAID array, one md device at a time
+    Scrub {
+        #[command(subcommand)]
+        command: ScrubCommands,
+    },
+    /// Generate configuration files for assembling HyRAID arrays at boot
+    Generate {
+        #[command(subcommand)]
+        command: GenerateCommands,
+    },
+    /// Compare the capacity of HyRAID with classic RAID for disks of the given sizes
+    Capacity {
+        /// Intended RAID level
+        #[arg(long, value_name = "RAID level")]
+        raid_level: usize,
+
+        /// Redundancy policy: raid-level, or two-disk to survive any two disk failures (needs RAID level 6)
+        #[arg(long, value_name = "Policy", default_value = "raid-level")]
+        policy: RedundancyPolicy,
+
+        /// Disk sizes (e.g. 4T 4T 8T)
+        #[arg(required = true)]
+        sizes: Vec<String>
+    },
+    /// Show the layout HyRAID would use for disks of the given sizes
+    Plan {
+        #[command(subcommand)]
+        command: PlanCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum PlanCommands {
+    Create {
+        /// Intended RAID level
+        #[arg(long, value_name = "RAID level")]
+        raid_level: usize,
+
+        /// Redundancy policy: raid-level, or two-disk to survive any two disk failures (needs RAID level 6)
+        #[arg(long, value_name = "Policy", default_value = "raid-level")]
+        policy: RedundancyPolicy,
+
+        /// Disk sizes, either as SIZE or DISK=SIZE (e.g. 4T or /dev/sda=4T)
+        #[arg(required = true)]
         disks: Vec<String>
     },
+    Add {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String,
+
+        /// Disk sizes, either as SIZE or DISK=SIZE (e.g. 4T or /dev/sda=4T)
+        #[arg(required = true)]
+        disks: Vec<String>
+    },
+}
+
+#[derive(Subcommand)]
+enum SpareCommands {
+    /// Partition a disk to match an array and attach it as a spare, or put it in the global spare pool
+    Add {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name", required_unless_present = "global")]
+        name: Option<String>,
+
+        /// Add to the global spare pool, for any array that degrades
+        #[arg(long, conflicts_with = "name")]
+        global: bool,
+
+        /// Disk to use
+        disk: String
+    },
+    /// Detach a spare from an array, or take it out of the global spare pool
+    Remove {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name", required_unless_present = "global")]
+        name: Option<String>,
+
+        /// Remove from the global spare pool
+        #[arg(long, conflicts_with = "name")]
+        global: bool,
+
+        /// Spare disk
+        disk: String
+    },
+}
+
+#[derive(Subcommand)]
+enum ScrubCommands {
+    /// Start a scrub, or carry on with a stopped one
+    Start {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String,
+
+        /// Rewrite parity or mirrors where they're inconsistent instead of only counting them
+        #[arg(long)]
+        repair: bool,
+
+        /// Speed limit per second (e.g. 50M)
+        #[arg(long, value_name = "Size")]
+        speed_limit: Option<String>,
+
+        /// Wait for the scrub to finish instead of leaving it to hyraidd
+        #[arg(long)]
+        wait: bool
+    },
+    /// Stop a scrub, keeping where it got to
+    Stop {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String
+    },
+    /// Show how far a scrub got and the mismatches found
+    Status {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String
+    },
+    /// Set how often hyraidd scrubs an array
+    Schedule {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: String,
+
+        /// weekly, monthly or off
+        schedule: String
+    },
+}
+
+#[derive(Subcommand)]
+enum GenerateCommands {
+    /// ARRAY lines for /etc/mdadm/mdadm.conf
+    MdadmConf {
+        /// Name of the HyRAID array
+        #[arg(long = "array-name", value_name = "Array name")]
+        name: Option<String>
+    },
+    /// systemd unit running `hyraid assemble` at boot
+    SystemdUnit,
+    /// systemd unit running the `hyraidd` monitor
+    MonitorUnit,
 }
 
+/// Unit assembling the arrays once udev has seen the disks, before local file systems are mounted
+const SYSTEMD_UNIT: &str = "\
+[Unit]
+Description=Assemble HyRAID arrays
+DefaultDependencies=no
+Wants=systemd-udev-settle.service
+After=systemd-udev-settle.service
+Before=local-fs-pre.target
+Conflicts=shutdown.target
+
+[Service]
+Type=oneshot
+RemainAfterExit=yes
+ExecStart={exe} assemble
+
+[Install]
+WantedBy=local-fs-pre.target
+";
+
+/// Unit keeping `hyraidd` running once the arrays are assembled, logging to the journal
+const MONITOR_UNIT: &str = "\
+[Unit]
+Description=Monitor HyRAID arrays
+After=local-fs.target
+
+[Service]
+ExecStart={exe}
+Restart=on-failure
+
+[Install]
+WantedBy=multi-user.target
+";
+
 fn cli_input(prompt: &str) -> String {
     print!("{}",prompt);
     io::stdout().flush().unwrap();
@@ -88,14 +393,14 @@ fn cli_input(prompt: &str) -> String {
 
 fn confirm() {
     let mut answer = " ".to_string();
-    if !(answer == "" || answer == "y" || answer == "n"){
-        while !(answer == "" || answer == "y" || answer == "n") {
+    if !(answer.is_empty() || answer == "y" || answer == "n"){
+        while !(answer.is_empty() || answer == "y" || answer == "n") {
             answer = cli_input("All data on the disks will be lost. Are you sure? [y/N]: ").to_lowercase();
         }
     }
     
-    if answer == "n" || answer == "" {
-        if answer == "" {
+    if answer == "n" || answer.is_empty() {
+        if answer.is_empty() {
             print!("n");
         }
         println!("Cancelled.");
@@ -109,6 +414,253 @@ fn log_logical_volume(lv_path: String) {
     file.write_all(lv_path.as_bytes()).unwrap();
 }
 
+/// Parses disks given as SIZE or DISK=SIZE
+fn parse_disk_sizes(disks: &[String]) -> Result<Vec<(String,usize)>,HyraidError> {
+    disks
+        .iter()
+        .enumerate()
+        .map(|(i,disk)| {
+            let (name,size) = match disk.split_once('=') {
+                Some((name,size)) => (name.to_string(),size),
+                None => (format!("disk{}",i),disk.as_str())
+            };
+            Ok((name,parse_size(size)?))
+        })
+        .collect()
+}
+
+/// Reads the free space every disk would have once HyRAID clears it
+fn usable_disk_sizes(backend: &Backend, disks: &[String]) -> Result<Vec<(String,usize)>,HyraidError> {
+    disks
+        .iter()
+        .map(|disk| Ok((disk.to_string(),backend.disks.usable_space(disk)?)))
+        .collect()
+}
+
+fn print_plan(plan: &HyraidPlan) {
+    println!("Slices:");
+    for slice in &plan.slices {
+        println!("  {}",format_size(*slice));
+    }
+
+    println!("Partitions:");
+    let mut disks: Vec<&String> = plan.part_map.keys().collect();
+    disks.sort();
+    for disk in disks {
+        println!("  {}",disk);
+        for part in &plan.part_map[disk] {
+            println!("    {}: {}",part.path.clone().unwrap_or_default(),format_size(part.size));
+        }
+    }
+
+    for (action,mds) in [("create",&plan.md_create),("grow",&plan.md_grow)] {
+        for md in mds {
+            println!("Will {} {} (RAID{}) with:",action,md.device,md.raid_level);
+            for part in &md.partitions {
+                println!("  {}: {}",part.path.clone().unwrap_or_default(),format_size(part.size));
+            }
+            for step in &md.migration {
+                let level = step.raid_level.map(|x| format!("to RAID{}, ",x)).unwrap_or_default();
+                println!(
+                    "  then reshaped {}to {} devices, surviving {} disk failure(s)",
+                    level,
+                    step.raid_devices,
+                    step.failures_tolerated
+                );
+            }
+        }
+    }
+
+    print_redundancy_report(&plan.redundancy);
+
+    match &plan.lvm {
+        LvmPlan::Create { physical_volumes } => {
+            println!("Will create an LVM volume group and logical volume on: {}",physical_volumes.join(", "));
+        },
+        LvmPlan::Extend { volume_group, created, resized } => {
+            if !created.is_empty() {
+                println!("Will extend volume group {} with: {}",volume_group,created.join(", "));
+            }
+            if !resized.is_empty() {
+                println!("Will resize physical volumes: {}",resized.join(", "));
+            }
+        }
+    }
+
+    println!("Dry run, no disks were modified.");
+}
+
+fn print_redundancy_report(report: &RedundancyReport) {
+    println!("Redundancy (requested: survives {} disk failure(s)):",report.target);
+    for group in &report.groups {
+        println!(
+            "  {}: RAID{} of {} x {}, survives {} disk failure(s){}",
+            group.device,
+            group.raid_level,
+            group.members,
+            format_size(group.size),
+            group.failures_tolerated,
+            if group.failures_tolerated < report.target { " (below requested)" } else { "" }
+        );
+    }
+    for (disk,size) in &report.unused {
+        println!("  {}: {} unused",disk,format_size(*size));
+    }
+}
+
+fn print_capacity_report(report: &CapacityReport) {
+    println!("Raw capacity: {}",format_size(report.raw));
+    println!("{:<8} {:>12} {:>12} {:>12}","Layout","Usable","Redundancy","Unused");
+
+    let layouts: [(&str,&Option<CapacityEstimate>); 4] = [
+        ("HyRAID",&Some(report.hyraid.clone())),
+        ("RAID1",&report.raid1),
+        ("RAID5",&report.raid5),
+        ("RAID6",&report.raid6),
+    ];
+    for (layout,estimate) in layouts {
+        match estimate {
+            Some(estimate) => println!(
+                "{:<8} {:>12} {:>12} {:>12}",
+                layout,
+                format_size(estimate.usable),
+                format_size(estimate.redundancy),
+                format_size(estimate.unused)
+            ),
+            None => println!("{:<8} {:>12}",layout,"not enough disks")
+        }
+    }
+}
+
+fn print_upgrade_report(report: &UpgradeReport) {
+    println!("Usable capacity: {}",format_size(report.usable));
+    if report.usable_after_swap > report.usable {
+        println!(
+            "Swapping {} for a {} disk would raise it to {}",
+            report.next_swap,
+            format_size(report.swap_size),
+            format_size(report.usable_after_swap)
+        );
+    }
+}
+
+fn print_status(status: &ArrayStatus) {
+    println!("Array: {}",status.name);
+    match status.lv_size {
+        Some(size) => println!("Logical volume: {} ({})",status.lvm_lv_path,format_size(size)),
+        None => println!("Logical volume: {} (unavailable)",status.lvm_lv_path)
+    }
+    println!("Capacity: {} usable of {} raw",format_size(status.usable_capacity),format_size(status.raw_capacity));
+
+    println!("MD devices:");
+    for md in &status.md_devices {
+        let health = match &md.health {
+            MdHealth::Clean => "clean".to_string(),
+            MdHealth::Degraded => "degraded".to_string(),
+            MdHealth::Syncing { action, percent, finish } => match finish {
+                Some(finish) => format!("{} {:.1}% (finish={})",action,percent,finish),
+                None => format!("{} {:.1}%",action,percent)
+            },
+            MdHealth::Inactive => "inactive".to_string(),
+            MdHealth::Missing => "missing".to_string()
+        };
+        println!(
+            "  {} ({}) RAID{}: {}",
+            md.device,
+            md.kernel_name.clone().unwrap_or("?".to_string()),
+            md.raid_level,
+            health
+        );
+        for member in &md.members {
+            println!("    {}: {}",member.path.clone().unwrap_or_default(),format_size(member.size));
+        }
+    }
+
+    println!("Disks:");
+    let mut disks: Vec<&String> = status.part_map.keys().collect();
+    disks.sort();
+    for disk in disks {
+        println!("  {}",disk);
+        for part in &status.part_map[disk] {
+            println!("    {}: {}",part.path.clone().unwrap_or_default(),format_size(part.size));
+        }
+    }
+}
+
+fn print_array(array: &HyraidArray) {
+    println!("Array: {} (RAID{}, {} policy)",array.name,array.raid_level,array.policy);
+    println!("Logical volume: {}",array.lvm_lv_path);
+
+    let mut md_devices: Vec<&String> = array.raid_map.keys().collect();
+    md_devices.sort();
+    for md in md_devices {
+        println!("  {}",md);
+        for part in &array.raid_map[md] {
+            println!("    {}: {}",part.path.clone().unwrap_or_default(),format_size(part.size));
+        }
+    }
+
+    let mut disks: Vec<&String> = array.part_map.keys().collect();
+    disks.sort();
+    println!("Disks: {}",disks.iter().map(|x| x.as_str()).collect::<Vec<&str>>().join(", "));
+
+    if !array.spares.is_empty() {
+        let mut spares: Vec<&String> = array.spares.keys().collect();
+        spares.sort();
+        println!("Spares: {}",spares.iter().map(|x| x.as_str()).collect::<Vec<&str>>().join(", "));
+    }
+}
+
+fn print_scrub_status(status: &ScrubStatus) {
+    println!("Array: {}",status.name);
+    println!("Schedule: {}",status.schedule.map(|x| x.to_string()).unwrap_or("off".to_string()));
+
+    let Some(scrub) = &status.scrub else {
+        println!("Never scrubbed.");
+        return;
+    };
+    let state = match (scrub.running,scrub.finished) {
+        (_,Some(_)) => "finished",
+        (true,None) => "running",
+        (false,None) => "stopped"
+    };
+    println!("Scrub: {} ({})",scrub.action,state);
+    if let Some(limit) = scrub.speed_limit {
+        println!("Speed limit: {}/s",format_size(limit));
+    }
+    for md in &scrub.devices {
+        let progress = match (md.mismatches,&status.current) {
+            (Some(mismatches),_) => format!("{} mismatched sectors",mismatches),
+            (None,Some((device,percent))) if *device == md.device => format!("{} {:.1}%",scrub.action,percent),
+            (None,_) if md.resume_from > 0 => format!("stopped at sector {}",md.resume_from),
+            (None,_) => "pending".to_string()
+        };
+        println!("  {}: {}",md.device,progress);
+    }
+}
+
+fn print_journal(journal: &Journal) {
+    println!("Unfinished {} of array {}",journal.operation,journal.name);
+    for entry in &journal.steps {
+        println!("  [{}] {}",entry.state,entry.step);
+    }
+}
+
+fn print_scan_report(report: &ScanReport) {
+    if report.arrays.is_empty() {
+        println!("No HyRAID arrays found.");
+    }
+    for array in &report.arrays {
+        print_array(array);
+    }
+    if !report.orphan_md_devices.is_empty() {
+        println!("md devices without a volume group: {}",report.orphan_md_devices.join(", "));
+    }
+    if !report.orphan_partitions.is_empty() {
+        println!("HyRAID partitions without an md superblock: {}",report.orphan_partitions.join(", "));
+    }
+}
+
 fn root_check() {
     if !is_root() {
         println!("Action requires root. Quitting.");
@@ -116,12 +668,31 @@ fn root_check() {
     }
 }
 
-fn main() {
-    let cli = Cli::parse();
+/// Prints the progress of an md device an operation waits for
+fn print_progress(name: &str, progress: &SyncProgress) {
+    println!(
+        "{}: {} {:.1}%, finishing in {}",
+        name,
+        progress.action,
+        progress.percent,
+        progress.finish.as_deref().unwrap_or("unknown")
+    );
+}
+
+fn run(cli: Cli) -> Result<(),HyraidError> {
+    let backend = Backend {
+        progress: Arc::new(print_progress),
+        ..Backend::default()
+    };
 
-    println!("THIS PROGRAM IS IN ALPHA RUNNING IT MAY RESULT IN UNDEFINED BEHAVIOUR!!!");
     match &cli.command {
-        Commands::Create { disks, raid_level, name } => {
+        Commands::Create { disks, raid_level, policy, dry_run: true, .. } => {
+            let disks = usable_disk_sizes(&backend,disks)?;
+            let disks: Vec<(&str,usize)> = disks.iter().map(|(disk,size)| (disk.as_str(),*size)).collect();
+
+            print_plan(&hyraid_mapper::plan_create(&disks,*raid_level,*policy)?);
+        },
+        Commands::Create { disks, raid_level, policy, name, allow_degraded_layout, .. } => {
             root_check();
             confirm();
             
@@ -130,7 +701,7 @@ fn main() {
                 .map(|s| s.as_str())
                 .collect::<Vec<&str>>();
 
-            let logical_volume = hyraid_mapper::create_hyraid_array(name.to_string(),slice,*raid_level);
+            let logical_volume = hyraid_mapper::create_hyraid_array(&backend,name.to_string(),slice,*raid_level,*policy,*allow_degraded_layout)?;
             println!("Created logical volume: {}",logical_volume);
 
             // for unit testing
@@ -145,9 +716,16 @@ fn main() {
                 .map(|s| s.as_str())
                 .collect::<Vec<&str>>();
 
-            hyraid_mapper::fail_from_hyraid_array(name.to_string(),slice);
+            hyraid_mapper::fail_from_hyraid_array(&backend,name.to_string(),slice)?;
+            println!("Marked {} as faulty on array {}",disks.join(", "),name);
+        },
+        Commands::Add { name, disks, dry_run: true, .. } => {
+            let disks = usable_disk_sizes(&backend,disks)?;
+            let disks: Vec<(&str,usize)> = disks.iter().map(|(disk,size)| (disk.as_str(),*size)).collect();
+
+            print_plan(&hyraid_mapper::plan_add(&backend,name.to_string(),&disks)?);
         },
-        Commands::Add { name, disks } => {
+        Commands::Add { name, disks, allow_degraded_layout, .. } => {
             root_check();
             confirm();
 
@@ -156,7 +734,7 @@ fn main() {
                 .map(|s| s.as_str())
                 .collect::<Vec<&str>>();
 
-            hyraid_mapper::add_disk_to_hyraid_array(name.to_string(),slice);
+            hyraid_mapper::add_disk_to_hyraid_array(&backend,name.to_string(),slice,*allow_degraded_layout)?;
         },
         Commands::Remove { name, disks } => {
             root_check();
@@ -166,7 +744,248 @@ fn main() {
                 .map(|s| s.as_str())
                 .collect::<Vec<&str>>();
 
-            hyraid_mapper::remove_disk_from_array(name.to_string(),slice);
+            hyraid_mapper::remove_disk_from_array(&backend,name.to_string(),slice)?;
+            println!("Removed {} from array {}",disks.join(", "),name);
+        },
+        Commands::Replace { name, old, new, allow_degraded_layout } => {
+            root_check();
+            confirm();
+
+            hyraid_mapper::replace_disk(&backend,name.to_string(),old,new,*allow_degraded_layout)?;
+            println!("Replaced {} with {}",old,new);
+            print_upgrade_report(&hyraid_mapper::upgrade_report(&backend,name.to_string())?);
+        },
+        Commands::Evacuate { name, disk } => {
+            root_check();
+            confirm();
+
+            hyraid_mapper::evacuate_disk(&backend,name.to_string(),disk)?;
+            println!("Evacuated {} from array {}",disk,name);
+        },
+        Commands::Grow { name, allow_degraded_layout } => {
+            root_check();
+
+            if hyraid_mapper::grow_hyraid_array(&backend,name.to_string(),*allow_degraded_layout)? {
+                println!("Grew array {}",name);
+            } else {
+                println!("Array {} has no free space to grow into",name);
+            }
+            print_upgrade_report(&hyraid_mapper::upgrade_report(&backend,name.to_string())?);
+        },
+        Commands::Destroy { name } => {
+            root_check();
+            confirm();
+
+            hyraid_mapper::destroy_hyraid_array(&backend,name.to_string())?;
+            println!("Destroyed array {}",name);
+        },
+        Commands::List => {
+            let arrays = hyraid_mapper::list_hyraid_arrays(&backend)?;
+            if arrays.is_empty() {
+                println!("No HyRAID arrays.");
+                return Ok(());
+            }
+
+            println!("{:<16} {:>5} {:>5} {:>6} {:>4}  Logical volume","Name","Level","Disks","Spares","MDs");
+            for array in arrays {
+                println!(
+                    "{:<16} {:>5} {:>5} {:>6} {:>4}  {}",
+                    array.name,
+                    array.raid_level,
+                    array.part_map.len(),
+                    array.spares.len(),
+                    array.raid_map.len(),
+                    array.lvm_lv_path
+                );
+            }
+
+            let global_spares = hyraid_mapper::list_global_spares(&backend)?;
+            if !global_spares.is_empty() {
+                println!("Global spares: {}",global_spares.join(", "));
+            }
+        },
+        Commands::Spare { command: SpareCommands::Add { name, global, disk } } => {
+            root_check();
+
+            match name {
+                Some(name) => {
+                    confirm();
+                    hyraid_mapper::add_spare(&backend,name.to_string(),disk)?;
+                    println!("Added {} as a spare of array {}",disk,name);
+                },
+                None if *global => {
+                    hyraid_mapper::add_global_spare(&backend,disk)?;
+                    println!("Added {} to the global spare pool",disk);
+                },
+                None => unreachable!("clap requires --array-name or --global")
+            }
+        },
+        Commands::Spare { command: SpareCommands::Remove { name, global, disk } } => {
+            root_check();
+
+            match name {
+                Some(name) => {
+                    hyraid_mapper::remove_spare(&backend,name.to_string(),disk)?;
+                    println!("Removed spare {} from array {}",disk,name);
+                },
+                None if *global => {
+                    hyraid_mapper::remove_global_spare(&backend,disk)?;
+                    println!("Removed {} from the global spare pool",disk);
+                },
+                None => unreachable!("clap requires --array-name or --global")
+            }
+        },
+        Commands::Status { name } => {
+            print_status(&hyraid_mapper::hyraid_array_status(&backend,name.to_string())?);
+        },
+        Commands::Metrics => {
+            print!("{}",Monitor::new(backend).metrics()?);
+        },
+        Commands::Recover { rollback, status } => {
+            if !*status {
+                root_check();
+            }
+
+            let Some(journal) = hyraid_mapper::pending_operation(&backend)? else {
+                println!("No unfinished operation.");
+                return Ok(());
+            };
+            print_journal(&journal);
+            if *status {
+                return Ok(());
+            }
+            if *rollback {
+                hyraid_mapper::rollback_operation(&backend)?;
+                println!("Rolled back the {} of array {}",journal.operation,journal.name);
+            } else {
+                hyraid_mapper::resume_operation(&backend)?;
+                println!("Finished the {} of array {}",journal.operation,journal.name);
+            }
+        },
+        Commands::Scrub { command: ScrubCommands::Start { name, repair, speed_limit, wait } } => {
+            root_check();
+
+            let monitor = Monitor::new(backend);
+            let action = if *repair { ScrubAction::Repair } else { ScrubAction::Check };
+            let speed_limit = speed_limit.as_deref().map(parse_size).transpose()?;
+            monitor.start_scrub(name,action,speed_limit)?;
+            println!("Started scrubbing array {}",name);
+
+            if *wait {
+                while monitor.advance_scrub(name)?.is_some_and(|x| x.running) {
+                    std::thread::sleep(std::time::Duration::from_secs(5));
+                }
+                print_scrub_status(&monitor.scrub_status(name)?);
+            }
+        },
+        Commands::Scrub { command: ScrubCommands::Stop { name } } => {
+            root_check();
+
+            Monitor::new(backend).stop_scrub(name)?;
+            println!("Stopped scrubbing array {}",name);
+        },
+        Commands::Scrub { command: ScrubCommands::Status { name } } => {
+            print_scrub_status(&Monitor::new(backend).scrub_status(name)?);
+        },
+        Commands::Scrub { command: ScrubCommands::Schedule { name, schedule } } => {
+            root_check();
+
+            let schedule = match schedule.as_str() {
+                "off" => None,
+                schedule => Some(schedule.parse::<ScrubSchedule>().map_err(HyraidError::Validation)?)
+            };
+            Monitor::new(backend).set_scrub_schedule(name,schedule)?;
+            println!("Scrub schedule of array {}: {}",name,schedule.map(|x| x.to_string()).unwrap_or("off".to_string()));
+        },
+        Commands::Scan => {
+            root_check();
+
+            print_scan_report(&hyraid_mapper::scan_hyraid_arrays(&backend)?);
+        },
+        Commands::Import { volume_group, name } => {
+            root_check();
+
+            let arrays = hyraid_mapper::import_hyraid_arrays(&backend,volume_group.as_deref(),name.clone())?;
+            if arrays.is_empty() {
+                println!("No new HyRAID arrays to import.");
+            }
+            for array in &arrays {
+                print_array(array);
+            }
+        },
+        Commands::Assemble { name } => {
+            root_check();
+
+            let names = match name {
+                Some(name) => vec![name.to_string()],
+                None => hyraid_mapper::list_hyraid_arrays(&backend)?
+                    .into_iter()
+                    .map(|x| x.name)
+                    .collect()
+            };
+
+            // keep going so one broken array doesn't hold back the others at boot
+            let mut result = Ok(());
+            for name in names {
+                match hyraid_mapper::assemble_hyraid_array(&backend,name.to_string()) {
+                    Ok(lv) => println!("Assembled array {}: {}",name,lv),
+                    Err(err) => {
+                        eprintln!("Failed to assemble array {}: {}",name,err);
+                        result = Err(err);
+                    }
+                }
+            }
+            return result;
+        },
+        Commands::Generate { command: GenerateCommands::MdadmConf { name } } => {
+            root_check();
+
+            print!("{}",hyraid_mapper::generate_mdadm_conf(&backend,name.clone())?);
+        },
+        Commands::Generate { command: GenerateCommands::SystemdUnit } => {
+            let exe = std::env::current_exe()
+                .map_err(|err| HyraidError::System(format!("Failed to find the hyraid binary: {}",err)))?;
+            print!("{}",SYSTEMD_UNIT.replace("{exe}",&exe.to_string_lossy()));
+        },
+        Commands::Generate { command: GenerateCommands::MonitorUnit } => {
+            // installed next to hyraid
+            let exe = std::env::current_exe()
+                .map_err(|err| HyraidError::System(format!("Failed to find the hyraid binary: {}",err)))?
+                .with_file_name("hyraidd");
+            print!("{}",MONITOR_UNIT.replace("{exe}",&exe.to_string_lossy()));
+        },
+        Commands::Capacity { raid_level, policy, sizes } => {
+            let sizes: Vec<usize> = parse_disk_sizes(sizes)?
+                .into_iter()
+                .map(|(_,size)| size)
+                .collect();
+
+            print_capacity_report(&hyraid_mapper::estimate_capacity(&sizes,*raid_level,*policy)?);
+        },
+        Commands::Plan { command: PlanCommands::Create { raid_level, policy, disks } } => {
+            let disks = parse_disk_sizes(disks)?;
+            let disks: Vec<(&str,usize)> = disks.iter().map(|(disk,size)| (disk.as_str(),*size)).collect();
+
+            print_plan(&hyraid_mapper::plan_create(&disks,*raid_level,*policy)?);
+        },
+        Commands::Plan { command: PlanCommands::Add { name, disks } } => {
+            let disks = parse_disk_sizes(disks)?;
+            let disks: Vec<(&str,usize)> = disks.iter().map(|(disk,size)| (disk.as_str(),*size)).collect();
+
+            print_plan(&hyraid_mapper::plan_add(&backend,name.to_string(),&disks)?);
         },
     }
-}
\ No newline at end of file
+
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    // on stderr, so generated files can be redirected
+    eprintln!("THIS PROGRAM IS IN ALPHA RUNNING IT MAY RESULT IN UNDEFINED BEHAVIOUR!!!");
+    if let Err(err) = run(cli) {
+        eprintln!("Error: {}",err);
+        process::exit(1);
+    }
+}
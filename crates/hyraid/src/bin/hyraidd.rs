@@ -0,0 +1,159 @@
+/*
+    HyRAID monitoring daemon.
+
+    Copyright (C) 2025 LIZARD-OFFICIAL-77
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use hyraid_api::Server;
+use hyraid_mapper::Backend;
+use hyraid_monitor::Monitor;
+use hyraid_types::HyraidEvent;
+use hyraid_utils::is_root;
+
+use std::{
+    process,
+    thread,
+    time::Duration
+};
+use clap::Parser;
+
+/// Watches every HyRAID array and reports failures, rebuilds and low space,
+/// and serves the HyRAID API
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Seconds between polls
+    #[arg(long, default_value_t = 10)]
+    interval: u64,
+    /// Directory of executables run for every event
+    #[arg(long = "hooks-dir", value_name = "Directory")]
+    hooks_dir: Option<String>,
+    /// Send CapacityLow once a file system is fuller than this, in percent
+    #[arg(long = "capacity-threshold", value_name = "Percent")]
+    capacity_threshold: Option<u8>,
+    /// Kill a hook still running after this many seconds
+    #[arg(long = "hook-timeout", value_name = "Seconds")]
+    hook_timeout: Option<u64>,
+    /// Unix socket of the JSON-RPC API
+    #[arg(long, value_name = "Path", default_value = hyraid_api::SOCKET_PATH)]
+    socket: String,
+    /// Poll once and exit, like `mdadm --monitor --oneshot`, without serving the API
+    #[arg(long)]
+    oneshot: bool,
+}
+
+/// Priorities of sd-daemon(3), journald reads them from the start of every line on stderr
+const LOG_ERR: u8 = 3;
+const LOG_WARNING: u8 = 4;
+const LOG_NOTICE: u8 = 5;
+const LOG_INFO: u8 = 6;
+
+fn log(priority: u8, message: &str) {
+    eprintln!("<{}>{}",priority,message);
+}
+
+fn handle_event(monitor: &Monitor, event: &HyraidEvent, events: &[HyraidEvent]) {
+    log(if event.is_critical() { LOG_WARNING } else { LOG_NOTICE },&event.to_string());
+
+    for err in monitor.run_hooks(event) {
+        log(LOG_ERR,&err.to_string());
+    }
+
+    // arrays with a spare of their own are already rebuilding onto it
+    let HyraidEvent::Degraded { array,.. } = event else { return };
+    let spare_activated = events
+        .iter()
+        .any(|x| matches!(x,HyraidEvent::SpareActivated {..}) && x.array() == array);
+    if spare_activated {
+        return;
+    }
+    match hyraid_mapper::attach_global_spare(&monitor.backend,array.to_string()) {
+        Ok(Some(disk)) => log(LOG_NOTICE,&format!("{}: attached global spare {}",array,disk)),
+        Ok(None) => (),
+        Err(err) => log(LOG_ERR,&format!("{}: failed to attach a global spare: {}",array,err)),
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if !is_root() {
+        log(LOG_ERR,"hyraidd must run as root");
+        process::exit(1);
+    }
+
+    let mut monitor = Monitor::new(Backend::default());
+    if let Some(hooks_dir) = cli.hooks_dir {
+        monitor.hooks_dir = hooks_dir;
+    }
+    if let Some(capacity_threshold) = cli.capacity_threshold {
+        monitor.capacity_threshold = capacity_threshold;
+    }
+    if let Some(hook_timeout) = cli.hook_timeout {
+        monitor.hook_timeout = hook_timeout;
+    }
+
+    if !cli.oneshot {
+        let listener = match hyraid_api::listen(&cli.socket) {
+            Ok(listener) => listener,
+            Err(err) => {
+                log(LOG_ERR,&err.to_string());
+                process::exit(1);
+            }
+        };
+        let server = Server::new(Backend::default());
+        thread::spawn(move || hyraid_api::serve(server,listener));
+    }
+
+    log(LOG_INFO,"hyraidd started");
+    match hyraid_mapper::pending_operation(&monitor.backend) {
+        Ok(Some(journal)) => log(LOG_WARNING,&format!(
+            "Unfinished {} of array {} found, run `hyraid recover` to resume or roll it back",
+            journal.operation,
+            journal.name
+        )),
+        Ok(None) => (),
+        Err(err) => log(LOG_ERR,&err.to_string()),
+    }
+    loop {
+        match monitor.poll() {
+            Ok((events,errors)) => {
+                for event in &events {
+                    handle_event(&monitor,event,&events);
+                }
+                for (array,err) in errors {
+                    log(LOG_ERR,&format!("{}: {}",array,err));
+                }
+            },
+            Err(err) => log(LOG_ERR,&err.to_string()),
+        }
+        match monitor.advance_scrubs() {
+            Ok((finished,errors)) => {
+                for (array,scrub) in finished {
+                    let mismatches: u64 = scrub.devices.iter().filter_map(|x| x.mismatches).sum();
+                    let priority = if mismatches > 0 { LOG_WARNING } else { LOG_INFO };
+                    log(priority,&format!("{}: scrub finished with {} mismatched sectors",array,mismatches));
+                }
+                for (array,err) in errors {
+                    log(LOG_ERR,&format!("{}: scrub failed: {}",array,err));
+                }
+            },
+            Err(err) => log(LOG_ERR,&err.to_string()),
+        }
+        if cli.oneshot {
+            break;
+        }
+        thread::sleep(Duration::from_secs(cli.interval));
+    }
+}
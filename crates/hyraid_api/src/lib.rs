@@ -0,0 +1,442 @@
+/*!
+    JSON-RPC API for HyRAID on a Unix socket
+
+    Every line sent to the socket is a JSON-RPC 2.0 request and gets a
+    response on one line, e.g.
+    `{"jsonrpc":"2.0","id":1,"method":"status","params":{"name":"storage"}}`.
+    Create, add and replace run as jobs, one at a time, and answer with
+    the queued job right away. Requests that change an array wait for
+    the ones already changing it.
+
+    Copyright (C) 2025 LIZARD-OFFICIAL-77
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream}
+    },
+    sync::{Arc, Mutex, Weak, mpsc},
+    thread
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use hyraid_mapper::Backend;
+use hyraid_types::{MdDeviceStatus, MdHealth, RedundancyPolicy};
+use hyraid_utils::{CancelToken, HyraidError};
+
+pub static SOCKET_PATH: &str = "/run/hyraid.sock";
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+const METHODS: [&str; 10] = ["create","add","fail","remove","replace","status","list","jobs","job","cancel"];
+
+/// Method and params of a request
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Request {
+    /// Queues a job creating an array, its result is the logical volume
+    Create {
+        name: String,
+        disks: Vec<String>,
+        raid_level: usize,
+        #[serde(default)]
+        policy: RedundancyPolicy,
+        #[serde(default)]
+        allow_degraded_layout: bool,
+    },
+    /// Queues a job adding disks to an array
+    Add {
+        name: String,
+        disks: Vec<String>,
+        #[serde(default)]
+        allow_degraded_layout: bool,
+    },
+    Fail { name: String, disks: Vec<String> },
+    Remove { name: String, disks: Vec<String> },
+    /// Queues a job replacing a disk of an array
+    Replace {
+        name: String,
+        old: String,
+        new: String,
+        #[serde(default)]
+        allow_degraded_layout: bool,
+    },
+    /// `ArrayStatus` of an array
+    Status { name: String },
+    /// Every `HyraidArray` in the state file
+    List,
+    Jobs,
+    Job { id: u64 },
+    Cancel { id: u64 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+/// Long operation run in the background
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Job {
+    pub id: u64,
+    /// Method that queued it, e.g. `add`
+    pub method: String,
+    pub array: String,
+    pub state: JobState,
+    /// Cancelling a running job only stops it where the array is left consistent
+    pub cancel_requested: bool,
+    /// md devices of the array resyncing, recovering or reshaping while the job runs
+    pub progress: Vec<MdDeviceStatus>,
+    /// Logical volume of a created array
+    pub result: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    params: Option<Value>,
+}
+
+/// Error code of a failed operation, in the range JSON-RPC leaves to servers
+fn error_code(err: &HyraidError) -> i32 {
+    match err {
+        HyraidError::Validation(_) => -32000,
+        HyraidError::Gpt(_) => -32001,
+        HyraidError::Mdadm(_) => -32002,
+        HyraidError::Lvm(_) => -32003,
+        HyraidError::StateFile(_) => -32004,
+        HyraidError::System(_) => -32005,
+        HyraidError::Cancelled => -32006,
+    }
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value,HyraidError> {
+    serde_json::to_value(value).map_err(|err| HyraidError::System(err.to_string()))
+}
+
+fn as_strs(disks: &[String]) -> Vec<&str> {
+    disks.iter().map(|x| x.as_str()).collect()
+}
+
+struct JobEntry {
+    job: Job,
+    request: Request,
+    cancel: CancelToken,
+}
+
+/// Answers requests and runs the queued jobs on a worker thread
+pub struct Server {
+    backend: Backend,
+    jobs: Mutex<Vec<JobEntry>>,
+    queue: mpsc::Sender<u64>,
+    /// Held while a request changes an array, by array name
+    array_locks: Mutex<HashMap<String,Arc<Mutex<()>>>>,
+}
+
+impl Server {
+    pub fn new(backend: Backend) -> Arc<Self> {
+        let (queue,queued) = mpsc::channel();
+        let server = Arc::new(Self {
+            backend,
+            jobs: Mutex::new(vec![]),
+            queue,
+            array_locks: Mutex::new(HashMap::new())
+        });
+
+        // stops once the server is dropped
+        let worker: Weak<Server> = Arc::downgrade(&server);
+        thread::spawn(move || {
+            while let Ok(id) = queued.recv() {
+                let Some(server) = worker.upgrade() else { break };
+                server.run_job(id);
+            }
+        });
+        server
+    }
+
+    /// Lock of an array, so that only one request changes it at a time
+    fn array_lock(&self, name: &str) -> Arc<Mutex<()>> {
+        self.array_locks
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    fn queue_job(&self, request: Request) -> Result<Value,HyraidError> {
+        let (method,array) = match &request {
+            Request::Create { name,.. } => ("create",name),
+            Request::Add { name,.. } => ("add",name),
+            Request::Replace { name,.. } => ("replace",name),
+            _ => return Err(HyraidError::Validation("Not a long operation".to_string()))
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = Job {
+            id: jobs.len() as u64 + 1,
+            method: method.to_string(),
+            array: array.to_string(),
+            state: JobState::Queued,
+            cancel_requested: false,
+            progress: vec![],
+            result: None,
+            error: None
+        };
+        jobs.push(JobEntry {
+            job: job.clone(),
+            request,
+            cancel: CancelToken::new()
+        });
+        self.queue
+            .send(job.id)
+            .map_err(|_| HyraidError::System("Job worker stopped".to_string()))?;
+        to_value(job)
+    }
+
+    fn run_job(&self, id: u64) {
+        let (request,cancel,array) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let Some(entry) = jobs.iter_mut().find(|x| x.job.id == id) else { return };
+            if entry.job.state != JobState::Queued {
+                return;
+            }
+            entry.job.state = JobState::Running;
+            (entry.request.clone(),entry.cancel.clone(),entry.job.array.to_string())
+        };
+        let lock = self.array_lock(&array);
+        // a panicked request left nothing that needs guarding
+        let _guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+        let backend = Backend {
+            cancel,
+            ..self.backend.clone()
+        };
+
+        let result = match request {
+            Request::Create { name, disks, raid_level, policy, allow_degraded_layout } => {
+                hyraid_mapper::create_hyraid_array(&backend,name,&as_strs(&disks),raid_level,policy,allow_degraded_layout)
+                    .map(Some)
+            },
+            Request::Add { name, disks, allow_degraded_layout } => {
+                hyraid_mapper::add_disk_to_hyraid_array(&backend,name,&as_strs(&disks),allow_degraded_layout)
+                    .map(|_| None)
+            },
+            Request::Replace { name, old, new, allow_degraded_layout } => {
+                hyraid_mapper::replace_disk(&backend,name,&old,&new,allow_degraded_layout).map(|_| None)
+            },
+            _ => Ok(None)
+        };
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(entry) = jobs.iter_mut().find(|x| x.job.id == id) else { return };
+        match result {
+            Ok(result) => {
+                entry.job.state = JobState::Finished;
+                entry.job.result = result;
+            },
+            Err(HyraidError::Cancelled) => entry.job.state = JobState::Cancelled,
+            Err(err) => {
+                entry.job.state = JobState::Failed;
+                entry.job.error = Some(err.to_string());
+            }
+        }
+    }
+
+    /// A job, with the progress of its array if it's running
+    fn job(&self, id: u64) -> Result<Job,HyraidError> {
+        let mut job = self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|x| x.job.id == id)
+            .map(|x| x.job.clone())
+            .ok_or(HyraidError::Validation(format!("No such job: {}",id)))?;
+
+        if job.state == JobState::Running {
+            // a created array isn't in the state file until its job is done
+            job.progress = hyraid_mapper::hyraid_array_status(&self.backend,job.array.to_string())
+                .map(|status| status.md_devices)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|x| matches!(x.health,MdHealth::Syncing {..}))
+                .collect();
+        }
+        Ok(job)
+    }
+
+    fn cancel(&self, id: u64) -> Result<Job,HyraidError> {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let entry = jobs
+                .iter_mut()
+                .find(|x| x.job.id == id)
+                .ok_or(HyraidError::Validation(format!("No such job: {}",id)))?;
+            match entry.job.state {
+                JobState::Queued => entry.job.state = JobState::Cancelled,
+                JobState::Running => {
+                    entry.cancel.cancel();
+                    entry.job.cancel_requested = true;
+                },
+                _ => return Err(HyraidError::Validation(format!("Job {} already ended",id)))
+            }
+        }
+        self.job(id)
+    }
+
+    /// Runs a request, long operations are queued as jobs.
+    /// Fail and remove wait for a running job on the same array.
+    pub fn handle(&self, request: Request) -> Result<Value,HyraidError> {
+        let backend = &self.backend;
+        match request {
+            Request::Create {..} | Request::Add {..} | Request::Replace {..} => self.queue_job(request),
+            Request::Fail { name, disks } => {
+                let lock = self.array_lock(&name);
+                let _guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+                hyraid_mapper::fail_from_hyraid_array(backend,name,&as_strs(&disks))?;
+                Ok(Value::Null)
+            },
+            Request::Remove { name, disks } => {
+                let lock = self.array_lock(&name);
+                let _guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+                hyraid_mapper::remove_disk_from_array(backend,name,&as_strs(&disks))?;
+                Ok(Value::Null)
+            },
+            Request::Status { name } => to_value(hyraid_mapper::hyraid_array_status(backend,name)?),
+            Request::List => to_value(hyraid_mapper::list_hyraid_arrays(backend)?),
+            Request::Jobs => {
+                let ids: Vec<u64> = self.jobs.lock().unwrap().iter().map(|x| x.job.id).collect();
+                to_value(ids.into_iter().map(|id| self.job(id)).collect::<Result<Vec<Job>,HyraidError>>()?)
+            },
+            Request::Job { id } => to_value(self.job(id)?),
+            Request::Cancel { id } => to_value(self.cancel(id)?),
+        }
+    }
+
+    /// Answers one line sent to the socket
+    pub fn handle_line(&self, line: &str) -> RpcResponse {
+        let response = |id: Value, result: Result<Value,RpcError>| {
+            let (result,error) = match result {
+                Ok(result) => (Some(result),None),
+                Err(error) => (None,Some(error))
+            };
+            RpcResponse { jsonrpc: "2.0".to_string(), id, result, error }
+        };
+        let error = |code: i32, message: String| RpcError { code, message };
+
+        let rpc: RpcRequest = match serde_json::from_str(line) {
+            Ok(rpc) => rpc,
+            Err(err) if err.is_syntax() || err.is_eof() => {
+                return response(Value::Null,Err(error(PARSE_ERROR,err.to_string())));
+            },
+            Err(err) => return response(Value::Null,Err(error(INVALID_REQUEST,err.to_string())))
+        };
+        if rpc.jsonrpc != "2.0" {
+            return response(rpc.id,Err(error(INVALID_REQUEST,"jsonrpc must be \"2.0\"".to_string())));
+        }
+        if !METHODS.contains(&rpc.method.as_str()) {
+            return response(rpc.id,Err(error(METHOD_NOT_FOUND,format!("No such method: {}",rpc.method))));
+        }
+
+        let mut call = serde_json::Map::new();
+        call.insert("method".to_string(),Value::String(rpc.method));
+        if let Some(params) = rpc.params {
+            call.insert("params".to_string(),params);
+        }
+        let request: Request = match serde_json::from_value(Value::Object(call)) {
+            Ok(request) => request,
+            Err(err) => return response(rpc.id,Err(error(INVALID_PARAMS,err.to_string())))
+        };
+
+        let result = self.handle(request).map_err(|err| error(error_code(&err),err.to_string()));
+        response(rpc.id,result)
+    }
+}
+
+/// Binds the socket, replacing a stale one, and lets only root connect.
+/// Refuses if a server still answers on it, or the path isn't a socket.
+pub fn listen(path: &str) -> Result<UnixListener,HyraidError> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(HyraidError::System(format!("{} exists and isn't a socket",path)));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(HyraidError::System(format!("Another server is listening on {}",path)));
+        }
+        fs::remove_file(path)
+            .map_err(|err| HyraidError::System(format!("Failed to remove stale socket {}: {}",path,err)))?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|err| HyraidError::System(format!("Failed to bind {}: {}",path,err)))?;
+    fs::set_permissions(path,fs::Permissions::from_mode(0o600))
+        .map_err(|err| HyraidError::System(format!("Failed to set permissions of {}: {}",path,err)))?;
+    Ok(listener)
+}
+
+fn handle_connection(server: &Server, stream: UnixStream) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = serde_json::to_string(&server.handle_line(&line))?;
+        writeln!(writer,"{}",response)?;
+    }
+    Ok(())
+}
+
+/// Answers every connection on its own thread, never returns
+pub fn serve(server: Arc<Server>, listener: UnixListener) {
+    for stream in listener.incoming().flatten() {
+        let server = server.clone();
+        thread::spawn(move || {
+            // the client went away
+            let _ = handle_connection(&server,stream);
+        });
+    }
+}
@@ -0,0 +1,143 @@
+/*!
+    Tests for the JSON-RPC API, using fake disks and a recording command runner.
+*/
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    sync::Arc,
+    thread,
+    time::Duration
+};
+
+use hyraid_api::{Job, JobState, RpcResponse, Server};
+use hyraid_mapper::Backend;
+use hyraid_types::{ArrayStatus, HyraidArray};
+use serde_json::{Value, json};
+
+const DISK_SIZE: usize = 4_000_000;
+const DISKS: [&str; 3] = ["/dev/sda","/dev/sdb","/dev/sdc"];
+
+fn server(test: &str) -> (Arc<Server>,Backend) {
+    let state_file = std::env::temp_dir()
+        .join(format!("hyraid-api-{}-{}.json",test,std::process::id()))
+        .to_string_lossy()
+        .to_string();
+
+    let (backend,_,disks) = Backend::fake(&state_file);
+    for disk in DISKS {
+        disks.add_disk(disk,DISK_SIZE);
+    }
+    (Server::new(backend.clone()),backend)
+}
+
+fn call(server: &Server, method: &str, params: Value) -> RpcResponse {
+    let request = json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": params });
+    server.handle_line(&request.to_string())
+}
+
+fn result<T: serde::de::DeserializeOwned>(response: RpcResponse) -> T {
+    assert_eq!(response.error,None);
+    serde_json::from_value(response.result.unwrap()).unwrap()
+}
+
+/// Waits for a job to end
+fn wait(server: &Server, id: u64) -> Job {
+    for _ in 0..100 {
+        let job: Job = result(call(server,"job",json!({ "id": id })));
+        if !matches!(job.state,JobState::Queued | JobState::Running) {
+            return job;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("job {} didn't end",id);
+}
+
+#[test]
+fn create_runs_as_a_job() {
+    let (server,backend) = server("create");
+
+    let job: Job = result(call(&server,"create",json!({
+        "name": "storage",
+        "disks": DISKS,
+        "raid_level": 5
+    })));
+    assert_eq!((job.method.as_str(),job.array.as_str()),("create","storage"));
+
+    let job = wait(&server,job.id);
+    assert_eq!(job.state,JobState::Finished);
+    assert!(job.result.is_some());
+
+    let arrays: Vec<HyraidArray> = result(call(&server,"list",Value::Null));
+    assert_eq!(arrays.len(),1);
+    let status: ArrayStatus = result(call(&server,"status",json!({ "name": "storage" })));
+    assert_eq!(status.lvm_lv_path,arrays[0].lvm_lv_path);
+
+    let jobs: Vec<Job> = result(call(&server,"jobs",Value::Null));
+    assert_eq!(jobs.len(),1);
+    let _ = fs::remove_file(&backend.state_file);
+}
+
+#[test]
+fn failed_job_keeps_its_error() {
+    let (server,_) = server("failed-job");
+
+    let job: Job = result(call(&server,"add",json!({ "name": "missing", "disks": ["/dev/sda"] })));
+    let job = wait(&server,job.id);
+
+    assert_eq!(job.state,JobState::Failed);
+    assert_eq!(job.error.as_deref(),Some("No such HyRAID array: missing"));
+    // an ended job can't be cancelled
+    let response = call(&server,"cancel",json!({ "id": job.id }));
+    assert_eq!(response.error.unwrap().code,-32000);
+}
+
+#[test]
+fn malformed_requests_get_json_rpc_errors() {
+    let (server,_) = server("errors");
+
+    let response = server.handle_line("{\"jsonrpc\":");
+    assert_eq!((response.id,response.error.unwrap().code),(Value::Null,-32700));
+    assert_eq!(call(&server,"format",Value::Null).error.unwrap().code,-32601);
+    assert_eq!(call(&server,"status",json!({ "array": "storage" })).error.unwrap().code,-32602);
+
+    let response = call(&server,"status",json!({ "name": "storage" }));
+    assert_eq!(response.id,json!(7));
+    assert_eq!(response.error.unwrap().message,"No such HyRAID array: storage");
+}
+
+#[test]
+fn answers_on_the_socket() {
+    let (server,backend) = server("socket");
+    let path = format!("{}.sock",backend.state_file);
+    let listener = hyraid_api::listen(&path).unwrap();
+    thread::spawn(move || hyraid_api::serve(server,listener));
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    writeln!(stream,"{}",json!({ "jsonrpc": "2.0", "id": 1, "method": "list" })).unwrap();
+    let mut line = String::new();
+    BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+
+    let response: RpcResponse = serde_json::from_str(&line).unwrap();
+    assert_eq!(response.result,Some(json!([])));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn listen_replaces_only_stale_sockets() {
+    let (_,backend) = server("listen");
+    let path = format!("{}.sock",backend.state_file);
+
+    let listener = hyraid_api::listen(&path).unwrap();
+    assert!(hyraid_api::listen(&path).is_err());
+    assert!(fs::metadata(&path).is_ok());
+
+    drop(listener);
+    hyraid_api::listen(&path).unwrap();
+    let _ = fs::remove_file(&path);
+
+    fs::write(&path,"").unwrap();
+    assert!(hyraid_api::listen(&path).is_err());
+    fs::remove_file(&path).unwrap();
+}
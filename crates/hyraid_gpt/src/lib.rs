@@ -17,22 +17,109 @@
     51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 */
 
-use hyraid_utils::{
-    unwrap_or_exit,
-    error_exit
-};
+use hyraid_utils::{HyraidError, CommandRunner};
 use std::{
-    io::Write, 
+    collections::HashMap,
+    io::{self, Write},
     fs,
-    process::{exit, Command, Stdio},
+    process::Command,
+    sync::Mutex,
     thread, 
     time::Duration,
     path::Path
 };
 use regex::Regex;
 use gpt::{
-    self, partition::Partition, GptDisk
+    self, partition::Partition, GptDisk,
+    partition_types::{OperatingSystem, Type}
 };
+use uuid::Uuid;
+
+/// GPT name of partitions created before HyRAID stored an identity in them
+pub const HYRAID_PARTITION_NAME: &str = "hyraid_partition";
+
+/// Partition type GUID of HyRAID partitions
+pub const HYRAID_PARTITION_TYPE: &str = "7407d9af-9acb-4d33-98e4-d21524462f2a";
+
+/// First of the GPT attribute bits reserved for the partition type (48-63).
+/// Bits 48-55 hold the slice index and bits 56-63 the layout generation.
+const IDENTITY_ATTRIBUTE_SHIFT: u32 = 48;
+
+/// Which array, slice and layout generation a HyRAID partition belongs to.
+///
+/// The array UUID is stored as the GPT partition name, which fits exactly
+/// in its 36 characters, the slice and generation in the type specific
+/// attribute bits. Both are kept modulo 256.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartitionIdentity {
+    pub array_uuid: String,
+    pub slice: usize,
+    pub generation: usize,
+}
+
+impl PartitionIdentity {
+    /// GPT partition name holding the identity
+    pub fn name(&self) -> String {
+        self.array_uuid.to_string()
+    }
+
+    /// GPT attribute bits holding the identity
+    pub fn attributes(&self) -> u64 {
+        let slice = (self.slice % 256) as u64;
+        let generation = (self.generation % 256) as u64;
+        (slice | generation << 8) << IDENTITY_ATTRIBUTE_SHIFT
+    }
+}
+
+/// Reads the identity of a HyRAID partition back from its GPT name and attributes
+pub fn partition_identity(name: &str, attributes: u64) -> Option<PartitionIdentity> {
+    let array_uuid = Uuid::parse_str(name).ok()?;
+    let bits = attributes >> IDENTITY_ATTRIBUTE_SHIFT;
+
+    Some(PartitionIdentity {
+        array_uuid: array_uuid.hyphenated().to_string(),
+        slice: (bits & 0xff) as usize,
+        generation: (bits >> 8 & 0xff) as usize
+    })
+}
+
+/// The HyRAID partition type
+pub fn hyraid_partition_type() -> Type {
+    Type {
+        guid: Uuid::parse_str(HYRAID_PARTITION_TYPE).unwrap(),
+        os: OperatingSystem::Custom("HyRAID".to_string())
+    }
+}
+
+/// Partition on a disk
+#[derive(Clone, Debug, PartialEq)]
+pub struct GptPartition {
+    pub path: String,
+    /// Size in bytes
+    pub size: usize,
+    /// GPT partition name
+    pub name: String,
+    /// Partition type GUID
+    pub type_guid: String,
+    /// GPT attribute bits
+    pub attributes: u64,
+}
+
+impl GptPartition {
+    /// Whether HyRAID created the partition, going by its type or, for older
+    /// partitions, its name
+    pub fn is_hyraid(&self) -> bool {
+        self.type_guid == HYRAID_PARTITION_TYPE || self.name == HYRAID_PARTITION_NAME
+    }
+
+    /// Identity of a HyRAID partition, `None` for other and older partitions
+    pub fn identity(&self) -> Option<PartitionIdentity> {
+        if self.type_guid != HYRAID_PARTITION_TYPE {
+            return None;
+        }
+        partition_identity(&self.name,self.attributes)
+    }
+}
 
 pub fn get_path_of_partition(partition: &Partition) -> String {
     "/dev/disk/by-partuuid/".to_string()+&partition.part_guid.to_string()
@@ -45,87 +132,438 @@ pub fn validate_partition(partition: Partition) {
     };
 }
 
-/// Ensures that the partition table of the disk is GPT
-pub fn ensure_gpt(disk: &str) {
-    let cmd = unwrap_or_exit!(
-        Command::new("sfdisk")
-            .arg("-d")
-            .arg(disk)
-            .output(),
-        format!("Incorrect device: {}",disk)
-    );
+/// Opens the GPT of a disk
+pub fn open_disk(disk: &str, writable: bool) -> Result<GptDisk<fs::File>,HyraidError> {
+    gpt::GptConfig::new()
+        .writable(writable)
+        .open(Path::new(disk))
+        .map_err(|err| HyraidError::Gpt(format!("Failed to open disk {}: {}",disk,err)))
+}
+
+/// Writes the GPT of a disk back to it
+pub fn write_disk(disk: &str, gptdisk: GptDisk<fs::File>) -> Result<(),HyraidError> {
+    gptdisk
+        .write()
+        .map_err(|err| HyraidError::Gpt(format!("Failed to write partition table of {}: {}",disk,err)))?;
+    Ok(())
+}
+
+/// Ensures that the partition table of the disk is GPT, relabeling it with sfdisk otherwise
+pub fn ensure_gpt(runner: &dyn CommandRunner, disk: &str) -> Result<(),HyraidError> {
+    // sfdisk fails to dump a blank disk, which gets labeled like any other
+    let dump = runner
+        .run(Command::new("sfdisk").arg("-d").arg(disk))
+        .map(|output| output.stdout)
+        .unwrap_or_default();
 
-    let stdout = String::from_utf8(cmd.stdout).unwrap();
     let regex = Regex::new("label: (?<table>.+)").unwrap();
-    let table = regex.captures(&stdout).unwrap()
-        .name("table")
-        .unwrap()
-        .as_str();
+    let table = regex
+        .captures(&dump)
+        .and_then(|captures| captures.name("table"))
+        .map(|table| table.as_str());
 
-    if table == "gpt" {
-        return; // disk is already gpt
+    if table == Some("gpt") {
+        return Ok(()); // disk is already gpt
     }
-    
-    let mut process = unwrap_or_exit!(
-        Command::new("sfdisk")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::null())
-            .arg(disk)
-            .spawn(),
-        format!("Incorrect disk: {}",disk)
-    );
-
-    process.stdin
-        .as_mut()
-        .unwrap()
-        .write_all(b"label: gpt\n\twrite\n")
-        .unwrap(); // this is kind of a hack but works
-
-    process.wait().unwrap();
-    println!("Converted disk {} to GPT partition table",disk);
-}
-
-pub fn get_sector_size(disk: &str) -> usize {
+
+    let (script,mut writer) = io::pipe()
+        .map_err(|err| HyraidError::Gpt(format!("Failed to write to sfdisk for {}: {}",disk,err)))?;
+    writer
+        .write_all(b"label: gpt\n")
+        .map_err(|err| HyraidError::Gpt(format!("Failed to write to sfdisk for {}: {}",disk,err)))?;
+    drop(writer); // sfdisk writes the table once its script ends
+
+    runner
+        .run(Command::new("sfdisk").arg(disk).stdin(script))
+        .map_err(|err| HyraidError::Gpt(format!("Failed to convert {} to GPT: {}",disk,err)))?;
+
+    Ok(())
+}
+
+/// Reads a number from the sysfs directory of a block device
+fn read_sysfs_block(disk: &str, attribute: &str) -> Result<usize,HyraidError> {
     // To account for /dev/disk/by-*/*
-    let dev_path = fs::canonicalize(disk).unwrap().to_string_lossy().to_string();
+    let dev_path = fs::canonicalize(disk)
+        .map_err(|err| HyraidError::Gpt(format!("No such device {}: {}",disk,err)))?
+        .to_string_lossy()
+        .to_string();
 
     let dev_path = dev_path.trim_start_matches("/dev/").trim_end_matches("/");
-    let sector_size = fs::read_to_string(format!("/sys/block/{}/queue/logical_block_size", dev_path)).unwrap();
+    let mut path = format!("/sys/class/block/{}/{}", dev_path, attribute);
+    if !Path::new(&path).exists() {
+        // partitions share the queue attributes of their disk
+        path = format!("/sys/class/block/{}/../{}", dev_path, attribute);
+    }
+    let value = fs::read_to_string(&path)
+        .map_err(|err| HyraidError::Gpt(format!("Failed to read {}: {}",path,err)))?;
 
-    sector_size
+    value
         .trim()
         .parse::<usize>()
-        .unwrap()
+        .map_err(|err| HyraidError::Gpt(format!("Failed to read {}: {}",path,err)))
+}
+
+pub fn get_sector_size(disk: &str) -> Result<usize,HyraidError> {
+    read_sysfs_block(disk,"queue/logical_block_size")
+}
+
+/// Estimates the free space on a disk in bytes once its partitions are cleared.
+///
+/// Only reads the disk size from sysfs, the disk itself is never opened.
+pub fn get_usable_space(disk: &str) -> Result<usize,HyraidError> {
+    // sysfs always reports the size in 512 byte units
+    let size = read_sysfs_block(disk,"size")? * 512;
+
+    // protective MBR, GPT headers and both partition entry arrays
+    let sector_size = get_sector_size(disk)?;
+    let reserved = sector_size * 3 + 2 * 16384;
+
+    Ok(size.saturating_sub(reserved))
 }
 
 /// Deletes all partitions on disk
-pub fn clear_partitions(disk: &str) {
-    let diskpath = std::path::Path::new(disk);
-    let mut gptdisk: GptDisk<std::fs::File> = unwrap_or_exit!(
-        gpt::GptConfig::new()
-            .writable(true)
-            .open(diskpath),
-        "Failed to open disk."
-    );
+pub fn clear_partitions(disk: &str) -> Result<(),HyraidError> {
+    let mut gptdisk = open_disk(disk,true)?;
     let parts = gptdisk.partitions().clone();
     for part in parts {
         gptdisk.remove_partition(part.0);
     }
-    gptdisk.write().unwrap();
+    write_disk(disk,gptdisk)
+}
+
+/// Deletes the partitions with the given paths from a disk
+pub fn remove_partitions(disk: &str, paths: &[&str]) -> Result<(),HyraidError> {
+    let mut gptdisk = open_disk(disk,true)?;
+    let parts = gptdisk.partitions().clone();
+    for (id,part) in parts {
+        if paths.contains(&get_path_of_partition(&part).as_str()) {
+            gptdisk.remove_partition(id);
+        }
+    }
+    write_disk(disk,gptdisk)
+}
+
+/// Whether a device is mounted, going by /proc/mounts
+pub fn is_mounted(path: &str) -> Result<bool,HyraidError> {
+    let Ok(device) = fs::canonicalize(path) else {
+        return Ok(false);
+    };
+    let mounts = fs::read_to_string("/proc/mounts")
+        .map_err(|err| HyraidError::System(format!("Failed to read /proc/mounts: {}",err)))?;
+
+    Ok(mounts
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .any(|source| fs::canonicalize(source).is_ok_and(|x| x == device)))
 }
 
 /// Gets free space on a disk in bytes
-pub fn get_free_space(dev: &str) -> usize {
-    let diskpath = std::path::Path::new(dev);
-    let gptdisk: GptDisk<std::fs::File> = unwrap_or_exit!(
-        gpt::GptConfig::new()
-            .open(diskpath),
-        "Failed to open disk."
-    );
-    let sectors = gptdisk.find_free_sectors()[0];
+pub fn get_free_space(dev: &str) -> Result<usize,HyraidError> {
+    let gptdisk = open_disk(dev,false)?;
+    let sectors = *gptdisk
+        .find_free_sectors()
+        .first()
+        .ok_or(HyraidError::Gpt(format!("No free space on {}",dev)))?;
 
     // what the fuck is this bullshit
-    let sectors: usize = (sectors.1-sectors.0).try_into().unwrap();
+    let sectors = (sectors.1-sectors.0) as usize;
     
-    sectors*get_sector_size(dev)
-}
\ No newline at end of file
+    Ok(sectors*get_sector_size(dev)?)
+}
+
+/// Lists the partitions on a disk
+pub fn get_partitions(disk: &str) -> Result<Vec<GptPartition>,HyraidError> {
+    let sector_size = get_sector_size(disk)?;
+    let gptdisk = open_disk(disk,false)?;
+
+    gptdisk
+        .partitions()
+        .values()
+        .map(|partition| {
+            let sectors = partition
+                .sectors_len()
+                .map_err(|err| HyraidError::Gpt(format!("Invalid partition on {}: {}",disk,err)))?;
+            Ok(GptPartition {
+                path: get_path_of_partition(partition),
+                size: sectors as usize * sector_size,
+                name: partition.name.to_string(),
+                type_guid: partition.part_type_guid.guid.hyphenated().to_string(),
+                attributes: partition.flags
+            })
+        })
+        .collect()
+}
+
+/// Adds HyRAID partitions of the given sizes in bytes to a disk and waits until they show up.
+///
+/// `identity` is that of the first partition, the others taking the following slices.
+/// Returns every partition on the disk.
+pub fn add_partitions(disk: &str, sizes: &[usize], identity: &PartitionIdentity) -> Result<Vec<GptPartition>,HyraidError> {
+    let mut gptdisk = open_disk(disk,true)?;
+    for (i,size) in sizes.iter().enumerate() {
+        let identity = PartitionIdentity {
+            slice: identity.slice + i,
+            ..identity.clone()
+        };
+        gptdisk.add_partition(
+            &identity.name(),
+            *size as u64,
+            hyraid_partition_type(),
+            identity.attributes(),
+            None
+        ).map_err(|err| HyraidError::Gpt(format!("Failed to add partition to {}: {}",disk,err)))?;
+    }
+    write_disk(disk,gptdisk)?;
+
+    let gptdisk = open_disk(disk,false)?;
+    for partition in gptdisk.partitions().values() {
+        validate_partition(partition.clone());
+    }
+
+    get_partitions(disk)
+}
+
+/// Lists the whole disks of the system, leaving out loop, RAM, md,
+/// device mapper and optical devices
+pub fn list_disks() -> Result<Vec<String>,HyraidError> {
+    let entries = fs::read_dir("/sys/block")
+        .map_err(|err| HyraidError::System(format!("Failed to read /sys/block: {}",err)))?;
+
+    let mut disks: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| !["loop","ram","zram","md","dm-","sr","fd"].iter().any(|x| name.starts_with(x)))
+        .map(|name| format!("/dev/{}",name))
+        .collect();
+    disks.sort();
+
+    Ok(disks)
+}
+
+/// Block devices and their partition tables.
+///
+/// Lets the callers be tested without root by swapping in `FakeDisks`.
+pub trait BlockDevice: Send + Sync {
+    fn sector_size(&self, disk: &str) -> Result<usize,HyraidError>;
+    /// Free space in bytes once the partitions of the disk are cleared
+    fn usable_space(&self, disk: &str) -> Result<usize,HyraidError>;
+    /// Free space in bytes
+    fn free_space(&self, disk: &str) -> Result<usize,HyraidError>;
+    /// Relabels the disk as GPT if it isn't already, running sfdisk through `runner`
+    fn ensure_gpt(&self, runner: &dyn CommandRunner, disk: &str) -> Result<(),HyraidError>;
+    fn clear_partitions(&self, disk: &str) -> Result<(),HyraidError>;
+    /// Deletes the partitions with the given paths
+    fn remove_partitions(&self, disk: &str, paths: &[&str]) -> Result<(),HyraidError>;
+    /// Adds HyRAID partitions of the given sizes in bytes, returning every partition on the disk.
+    ///
+    /// `identity` is that of the first partition, the others taking the following slices.
+    fn add_partitions(&self, disk: &str, sizes: &[usize], identity: &PartitionIdentity) -> Result<Vec<GptPartition>,HyraidError>;
+    fn partitions(&self, disk: &str) -> Result<Vec<GptPartition>,HyraidError>;
+    fn device_exists(&self, path: &str) -> bool;
+    /// Kernel name of a device, e.g. `md127` for `/dev/md/name`
+    fn kernel_name(&self, path: &str) -> Option<String>;
+    fn is_mounted(&self, path: &str) -> Result<bool,HyraidError>;
+    /// Every whole disk of the system
+    fn list_disks(&self) -> Result<Vec<String>,HyraidError>;
+}
+
+/// Disks of the system, read through sysfs and their GPT
+pub struct SystemDisks;
+
+impl BlockDevice for SystemDisks {
+    fn sector_size(&self, disk: &str) -> Result<usize,HyraidError> {
+        get_sector_size(disk)
+    }
+
+    fn usable_space(&self, disk: &str) -> Result<usize,HyraidError> {
+        get_usable_space(disk)
+    }
+
+    fn free_space(&self, disk: &str) -> Result<usize,HyraidError> {
+        get_free_space(disk)
+    }
+
+    fn ensure_gpt(&self, runner: &dyn CommandRunner, disk: &str) -> Result<(),HyraidError> {
+        ensure_gpt(runner,disk)
+    }
+
+    fn clear_partitions(&self, disk: &str) -> Result<(),HyraidError> {
+        clear_partitions(disk)
+    }
+
+    fn remove_partitions(&self, disk: &str, paths: &[&str]) -> Result<(),HyraidError> {
+        remove_partitions(disk,paths)
+    }
+
+    fn add_partitions(&self, disk: &str, sizes: &[usize], identity: &PartitionIdentity) -> Result<Vec<GptPartition>,HyraidError> {
+        add_partitions(disk,sizes,identity)
+    }
+
+    fn partitions(&self, disk: &str) -> Result<Vec<GptPartition>,HyraidError> {
+        get_partitions(disk)
+    }
+
+    fn device_exists(&self, path: &str) -> bool {
+        Path::new(path).exists()
+    }
+
+    fn kernel_name(&self, path: &str) -> Option<String> {
+        let path = fs::canonicalize(path).ok()?;
+        Some(path.file_name()?.to_string_lossy().to_string())
+    }
+
+    fn is_mounted(&self, path: &str) -> Result<bool,HyraidError> {
+        is_mounted(path)
+    }
+
+    fn list_disks(&self) -> Result<Vec<String>,HyraidError> {
+        list_disks()
+    }
+}
+
+struct FakeDisk {
+    size: usize,
+    partitions: Vec<GptPartition>,
+    /// number of the next partition, never reused
+    next: usize,
+}
+
+/// Fake `BlockDevice` keeping disks in memory and recording every change made to them.
+///
+/// Sector size is always 512 and every device path exists.
+#[derive(Default)]
+pub struct FakeDisks {
+    disks: Mutex<HashMap<String,FakeDisk>>,
+    operations: Mutex<Vec<Vec<String>>>,
+    mounted: Mutex<Vec<String>>,
+    kernel_names: Mutex<HashMap<String,String>>,
+}
+
+impl FakeDisks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an empty disk of `size` bytes
+    pub fn add_disk(&self, disk: &str, size: usize) {
+        self.disks.lock().unwrap().insert(disk.to_string(),FakeDisk {
+            size,
+            partitions: vec![],
+            next: 1
+        });
+    }
+
+    /// Marks a device as mounted
+    pub fn mount(&self, path: &str) {
+        self.mounted.lock().unwrap().push(path.to_string());
+    }
+
+    /// Sets the kernel name of a device, e.g. `md127` for an md device,
+    /// which is otherwise the last component of its path
+    pub fn set_kernel_name(&self, path: &str, kernel_name: &str) {
+        self.kernel_names.lock().unwrap().insert(path.to_string(),kernel_name.to_string());
+    }
+
+    /// Every change made to the disks so far, e.g. `["clear_partitions","/dev/sda"]`
+    pub fn operations(&self) -> Vec<Vec<String>> {
+        self.operations.lock().unwrap().clone()
+    }
+
+    fn record(&self, operation: &[String]) {
+        self.operations.lock().unwrap().push(operation.to_vec());
+    }
+
+    fn with_disk<T>(&self, disk: &str, f: impl FnOnce(&mut FakeDisk) -> T) -> Result<T,HyraidError> {
+        let mut disks = self.disks.lock().unwrap();
+        let fake = disks
+            .get_mut(disk)
+            .ok_or(HyraidError::Gpt(format!("No such device {}",disk)))?;
+        Ok(f(fake))
+    }
+}
+
+impl BlockDevice for FakeDisks {
+    fn sector_size(&self, disk: &str) -> Result<usize,HyraidError> {
+        self.with_disk(disk,|_| 512)
+    }
+
+    fn usable_space(&self, disk: &str) -> Result<usize,HyraidError> {
+        self.with_disk(disk,|fake| fake.size)
+    }
+
+    fn free_space(&self, disk: &str) -> Result<usize,HyraidError> {
+        self.with_disk(disk,|fake| fake.size - fake.partitions.iter().map(|x| x.size).sum::<usize>())
+    }
+
+    fn ensure_gpt(&self, _runner: &dyn CommandRunner, disk: &str) -> Result<(),HyraidError> {
+        self.with_disk(disk,|_| ())?;
+        self.record(&["ensure_gpt".to_string(),disk.to_string()]);
+        Ok(())
+    }
+
+    fn clear_partitions(&self, disk: &str) -> Result<(),HyraidError> {
+        self.with_disk(disk,|fake| fake.partitions.clear())?;
+        self.record(&["clear_partitions".to_string(),disk.to_string()]);
+        Ok(())
+    }
+
+    fn remove_partitions(&self, disk: &str, paths: &[&str]) -> Result<(),HyraidError> {
+        self.with_disk(disk,|fake| fake.partitions.retain(|x| !paths.contains(&x.path.as_str())))?;
+        let mut operation = vec!["remove_partitions".to_string(),disk.to_string()];
+        operation.extend(paths.iter().map(|x| x.to_string()));
+        self.record(&operation);
+        Ok(())
+    }
+
+    fn add_partitions(&self, disk: &str, sizes: &[usize], identity: &PartitionIdentity) -> Result<Vec<GptPartition>,HyraidError> {
+        let name = disk.trim_start_matches("/dev/").replace('/',"-");
+        let partitions = self.with_disk(disk,|fake| {
+            for (i,size) in sizes.iter().enumerate() {
+                let identity = PartitionIdentity {
+                    slice: identity.slice + i,
+                    ..identity.clone()
+                };
+                fake.partitions.push(GptPartition {
+                    path: format!("/dev/disk/by-partuuid/{}-part{}",name,fake.next),
+                    size: *size,
+                    name: identity.name(),
+                    type_guid: HYRAID_PARTITION_TYPE.to_string(),
+                    attributes: identity.attributes()
+                });
+                fake.next += 1;
+            }
+            fake.partitions.clone()
+        })?;
+
+        let mut operation = vec!["add_partitions".to_string(),disk.to_string()];
+        operation.extend(sizes.iter().map(|x| x.to_string()));
+        self.record(&operation);
+
+        Ok(partitions)
+    }
+
+    fn partitions(&self, disk: &str) -> Result<Vec<GptPartition>,HyraidError> {
+        self.with_disk(disk,|fake| fake.partitions.clone())
+    }
+
+    fn device_exists(&self, _path: &str) -> bool {
+        true
+    }
+
+    fn kernel_name(&self, path: &str) -> Option<String> {
+        if let Some(kernel_name) = self.kernel_names.lock().unwrap().get(path) {
+            return Some(kernel_name.to_string());
+        }
+        Path::new(path).file_name().map(|x| x.to_string_lossy().to_string())
+    }
+
+    fn is_mounted(&self, path: &str) -> Result<bool,HyraidError> {
+        Ok(self.mounted.lock().unwrap().iter().any(|x| x == path))
+    }
+
+    fn list_disks(&self) -> Result<Vec<String>,HyraidError> {
+        let mut disks: Vec<String> = self.disks.lock().unwrap().keys().cloned().collect();
+        disks.sort();
+        Ok(disks)
+    }
+}
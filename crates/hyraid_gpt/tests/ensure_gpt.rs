@@ -0,0 +1,50 @@
+/*!
+    Tests for relabeling disks as GPT through sfdisk.
+*/
+
+use hyraid_gpt::ensure_gpt;
+use hyraid_utils::{CommandOutput, HyraidError, RecordingRunner};
+
+fn argv(args: &[&str]) -> Vec<String> {
+    args.iter().map(|x| x.to_string()).collect()
+}
+
+fn dump(label: &str) -> CommandOutput {
+    CommandOutput {
+        stdout: format!("label: {}\nlabel-id: 0x1234\ndevice: /dev/sda\nunit: sectors\n",label),
+        stderr: String::new()
+    }
+}
+
+#[test]
+fn leaves_gpt_disk_alone() {
+    let runner = RecordingRunner::new();
+    runner.respond(&["sfdisk","-d"],Ok(dump("gpt")));
+
+    ensure_gpt(&runner,"/dev/sda").unwrap();
+
+    assert_eq!(runner.commands(),vec![argv(&["sfdisk","-d","/dev/sda"])]);
+}
+
+#[test]
+fn relabels_other_and_blank_disks() {
+    for response in [Ok(dump("dos")),Err(Some(1))] {
+        let runner = RecordingRunner::new();
+        runner.respond(&["sfdisk","-d"],response);
+
+        ensure_gpt(&runner,"/dev/sda").unwrap();
+
+        assert_eq!(runner.commands(),vec![
+            argv(&["sfdisk","-d","/dev/sda"]),
+            argv(&["sfdisk","/dev/sda"])
+        ]);
+    }
+}
+
+#[test]
+fn reports_failed_relabel() {
+    let runner = RecordingRunner::new();
+    runner.respond(&["sfdisk"],Err(Some(1)));
+
+    assert!(matches!(ensure_gpt(&runner,"/dev/sda"),Err(HyraidError::Gpt(_))));
+}
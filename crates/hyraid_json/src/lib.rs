@@ -17,55 +17,351 @@
 */
 
 use std::{
-    fs, io::Write, path::Path
+    fs,
+    io::{self, Write},
+    path::Path,
+    process,
+    sync::atomic::{AtomicUsize, Ordering}
 };
-use hyraid_types::HyraidArray;
+use hyraid_types::{HyraidArray, Journal, ScrubSchedule, ScrubState, StateDocument};
+use hyraid_utils::HyraidError;
+use serde_json::{json, Value};
 
-fn ensure_json_file_exists(path: &str) {
-    if !(Path::new(path).exists()) {
-        let mut file = fs::File::create(path).unwrap();
-        file.write(b"[]").unwrap();
+/// Schema version written by this release
+pub const SCHEMA_VERSION: u32 = 5;
+
+/// Migrations upgrading a document from the version they're indexed by to the next one
+const MIGRATIONS: [fn(Value) -> Value; SCHEMA_VERSION as usize] = [
+    migrate_v0,
+    migrate_v1,
+    migrate_v2,
+    migrate_v3,
+    migrate_v4,
+];
+
+/// Number of rotated backups kept next to the state file, `hyraid.json.1` being the newest
+const BACKUPS: usize = 5;
+
+fn state_file_error(path: &str, err: impl std::fmt::Display) -> HyraidError {
+    HyraidError::StateFile(format!("{}: {}",path,err))
+}
+
+/// Version 0 is the bare array of arrays written before the schema was versioned
+fn migrate_v0(document: Value) -> Value {
+    json!({
+        "schema_version": 1,
+        "arrays": document
+    })
+}
+
+/// Version 2 added the array UUID and layout generation
+fn migrate_v1(mut document: Value) -> Value {
+    if let Some(arrays) = document["arrays"].as_array_mut() {
+        for array in arrays.iter_mut().filter_map(|x| x.as_object_mut()) {
+            array.insert("uuid".to_string(),Value::Null);
+            array.insert("generation".to_string(),json!(0));
+        }
+    }
+    document["schema_version"] = json!(2);
+    document
+}
+
+/// Version 3 added the redundancy policy, older arrays follow their RAID level
+fn migrate_v2(mut document: Value) -> Value {
+    if let Some(arrays) = document["arrays"].as_array_mut() {
+        for array in arrays.iter_mut().filter_map(|x| x.as_object_mut()) {
+            array.insert("policy".to_string(),json!("RaidLevel"));
+        }
+    }
+    document["schema_version"] = json!(3);
+    document
+}
+
+/// Version 4 added spares of every array and the global spare pool
+fn migrate_v3(mut document: Value) -> Value {
+    if let Some(arrays) = document["arrays"].as_array_mut() {
+        for array in arrays.iter_mut().filter_map(|x| x.as_object_mut()) {
+            array.insert("spares".to_string(),json!({}));
+        }
+    }
+    document["global_spares"] = json!([]);
+    document["schema_version"] = json!(4);
+    document
+}
+
+/// Version 5 added the scrub progress and schedule of every array
+fn migrate_v4(mut document: Value) -> Value {
+    if let Some(arrays) = document["arrays"].as_array_mut() {
+        for array in arrays.iter_mut().filter_map(|x| x.as_object_mut()) {
+            array.insert("scrub".to_string(),Value::Null);
+            array.insert("scrub_schedule".to_string(),Value::Null);
+        }
+    }
+    document["schema_version"] = json!(5);
+    document
+}
+
+fn schema_version(path: &str, document: &Value) -> Result<u32,HyraidError> {
+    match document {
+        Value::Array(_) => Ok(0),
+        Value::Object(object) => object
+            .get("schema_version")
+            .and_then(|x| x.as_u64())
+            .map(|x| x as u32)
+            .ok_or(state_file_error(path,"missing schema_version")),
+        _ => Err(state_file_error(path,"not a HyRAID state file"))
+    }
+}
+
+/// Upgrades a document to the current schema version
+fn migrate(path: &str, mut document: Value) -> Result<Value,HyraidError> {
+    let mut version = schema_version(path,&document)?;
+    if version > SCHEMA_VERSION {
+        return Err(state_file_error(path,format!(
+            "schema version {} was written by a newer HyRAID, this release supports up to {}",
+            version,
+            SCHEMA_VERSION
+        )));
+    }
+
+    while version < SCHEMA_VERSION {
+        document = MIGRATIONS[version as usize](document);
+        version += 1;
+    }
+    Ok(document)
+}
+
+/// Runs `f` while holding an exclusive `flock` on `<path>.lock`.
+///
+/// The state file itself is replaced on every write, so it can't carry the lock.
+fn with_lock<T>(path: &str, f: impl FnOnce() -> Result<T,HyraidError>) -> Result<T,HyraidError> {
+    let lock_path = format!("{}.lock",path);
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|err| state_file_error(&lock_path,err))?;
+    lock.lock().map_err(|err| state_file_error(&lock_path,err))?;
+
+    // released when `lock` is dropped
+    f()
+}
+
+/// Shifts `path.1` .. `path.N-1` up by one and copies the current file to `path.1`
+fn rotate_backups(path: &str) -> Result<(),HyraidError> {
+    if !Path::new(path).exists() {
+        return Ok(());
+    }
+    for i in (1..BACKUPS).rev() {
+        let from = format!("{}.{}",path,i);
+        if Path::new(&from).exists() {
+            let to = format!("{}.{}",path,i + 1);
+            fs::rename(&from,&to).map_err(|err| state_file_error(&to,err))?;
+        }
+    }
+    let backup = format!("{}.1",path);
+    fs::copy(path,&backup).map_err(|err| state_file_error(&backup,err))?;
+    Ok(())
+}
+
+/// Writes to a temporary file, syncs it and renames it over `path`,
+/// so readers see either the old or the new contents and never a partial write.
+fn replace_file(path: &str, json: &str) -> Result<(),HyraidError> {
+    let tmp_path = format!("{}.tmp",path);
+    write_durably(&tmp_path,json)?;
+    fs::rename(&tmp_path,path).map_err(|err| state_file_error(path,err))?;
+    sync_parent(path)
+}
+
+/// Writes a new file and flushes it to disk
+fn write_durably(path: &str, json: &str) -> Result<(),HyraidError> {
+    let mut file = fs::File::create(path).map_err(|err| state_file_error(path,err))?;
+    file.write_all(json.as_bytes()).map_err(|err| state_file_error(path,err))?;
+    file.sync_all().map_err(|err| state_file_error(path,err))
+}
+
+/// Makes a rename or link to `path` itself durable
+fn sync_parent(path: &str) -> Result<(),HyraidError> {
+    let dir = Path::new(path)
+        .parent()
+        .filter(|x| !x.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::File::open(dir)
+        .and_then(|x| x.sync_all())
+        .map_err(|err| state_file_error(path,err))
+}
+
+fn write_document(path: &str, document: &StateDocument) -> Result<(),HyraidError> {
+    let document = StateDocument {
+        schema_version: SCHEMA_VERSION,
+        ..document.clone()
     };
+    let json = serde_json::to_string_pretty(&document).map_err(|err| state_file_error(path,err))?;
+
+    rotate_backups(path)?;
+    replace_file(path,&json)
 }
 
-pub fn read_arrays(path: &str) -> Vec<HyraidArray> {
-    ensure_json_file_exists(path);
+/// Reads the state file, upgrading it in memory if it was written by an older release.
+/// A missing state file has no arrays.
+///
+/// Needs no lock since writes replace the file atomically.
+pub fn read_document(path: &str) -> Result<StateDocument,HyraidError> {
+    if !Path::new(path).exists() {
+        return Ok(StateDocument {
+            schema_version: SCHEMA_VERSION,
+            arrays: vec![],
+            global_spares: vec![]
+        });
+    }
 
-    let data = fs::read_to_string(path).unwrap();
-    let entries: Vec<HyraidArray> = serde_json::from_str(&data).unwrap();
+    let data = fs::read_to_string(path).map_err(|err| state_file_error(path,err))?;
+    let document: Value = serde_json::from_str(&data).map_err(|err| state_file_error(path,err))?;
+    let document = migrate(path,document)?;
+
+    serde_json::from_value(document).map_err(|err| state_file_error(path,err))
+}
 
-    entries
+/// Reads every array entry
+pub fn read_arrays(path: &str) -> Result<Vec<HyraidArray>,HyraidError> {
+    Ok(read_document(path)?.arrays)
 }
 
-/// Replaces an entry with the given entry
-pub fn modify(path: &str,name: String,hyraid_array: HyraidArray) {
-    ensure_json_file_exists(path);
+/// Changes one array with `f`, leaving the rest of its entry as it's on disk.
+///
+/// `f` gets the entry as read under the lock, so fields it doesn't touch keep
+/// whatever other writers stored in the meantime.
+pub fn update_array(path: &str, name: &str, f: impl FnOnce(&mut HyraidArray)) -> Result<(),HyraidError> {
+    with_lock(path,|| {
+        let mut document = read_document(path)?;
+        let entry = document.arrays
+            .iter_mut()
+            .find(|x| x.name == name)
+            .ok_or(HyraidError::Validation(format!("No such HyRAID array: {}",name)))?;
+        f(entry);
+
+        write_document(path,&document)
+    })
+}
+
+/// Records the scrub of an array
+pub fn set_scrub(path: &str, name: &str, scrub: Option<ScrubState>) -> Result<(),HyraidError> {
+    update_array(path,name,|entry| entry.scrub = scrub)
+}
 
-    let entries = read_arrays(path);
-    let entries: Vec<&HyraidArray> = entries
-        .iter()
-        .map(|x| {
-            if x.name == name {
-                &hyraid_array
-            } else {
-                x
-            }
+/// Sets how often an array is scrubbed, `None` for never
+pub fn set_scrub_schedule(path: &str, name: &str, schedule: Option<ScrubSchedule>) -> Result<(),HyraidError> {
+    update_array(path,name,|entry| entry.scrub_schedule = schedule)
+}
+
+/// Add array entry to json file, unless an entry of the same name exists
+pub fn write_array(path: &str, hyraid_array: HyraidArray) -> Result<(),HyraidError> {
+    with_lock(path,|| {
+        let mut document = read_document(path)?;
+        if document.arrays.iter().any(|x| x.name == hyraid_array.name) {
+            return Err(HyraidError::Validation(format!("Array \"{}\" already exists",hyraid_array.name)));
         }
-    ).collect();
-    
-    
-    let json = serde_json::to_string_pretty(&entries).unwrap();
-    fs::write(path,json).unwrap();
+        document.arrays.push(hyraid_array);
+
+        write_document(path,&document)
+    })
 }
 
-/// Add array entry to json file
-pub fn write_array(path: &str, hyraid_array: HyraidArray) {
-    ensure_json_file_exists(path);
+/// Remove array entry from json file
+pub fn remove_array(path: &str, name: &str) -> Result<(),HyraidError> {
+    with_lock(path,|| {
+        let mut document = read_document(path)?;
+        document.arrays.retain(|x| x.name != name);
+
+        write_document(path,&document)
+    })
+}
 
-    let mut entries = read_arrays(path);
-    entries.push(hyraid_array);
+/// Add disk to the global spare pool, unless it's in there already
+pub fn add_global_spare(path: &str, disk: &str) -> Result<(),HyraidError> {
+    with_lock(path,|| {
+        let mut document = read_document(path)?;
+        if document.global_spares.iter().any(|x| x == disk) {
+            return Err(HyraidError::Validation(format!("{} already is a global spare",disk)));
+        }
+        document.global_spares.push(disk.to_string());
 
-    let json = serde_json::to_string_pretty(&entries).unwrap();
-    fs::write(path,json).unwrap();
+        write_document(path,&document)
+    })
 }
 
+/// Remove disk from the global spare pool
+pub fn remove_global_spare(path: &str, disk: &str) -> Result<(),HyraidError> {
+    with_lock(path,|| {
+        let mut document = read_document(path)?;
+        if !document.global_spares.iter().any(|x| x == disk) {
+            return Err(HyraidError::Validation(format!("{} is not a global spare",disk)));
+        }
+        document.global_spares.retain(|x| x != disk);
+
+        write_document(path,&document)
+    })
+}
+
+/// Journal of the state file at `path`
+fn journal_path(path: &str) -> String {
+    format!("{}.journal",path)
+}
+
+/// Reads the journal of an operation that hasn't finished, if there is one
+pub fn read_journal(path: &str) -> Result<Option<Journal>,HyraidError> {
+    let path = journal_path(path);
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+    read_journal_file(&path).map(Some)
+}
+
+fn read_journal_file(path: &str) -> Result<Journal,HyraidError> {
+    let data = fs::read_to_string(path).map_err(|err| state_file_error(path,err))?;
+    serde_json::from_str(&data).map_err(|err| state_file_error(path,err))
+}
+
+/// Starts the journal of a new operation, refusing while another operation has one.
+///
+/// The journal is written aside and hard linked into place, which fails if it
+/// already exists, so of two operations starting at once only one goes ahead.
+pub fn create_journal(path: &str, journal: &Journal) -> Result<(),HyraidError> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let path = journal_path(path);
+    let json = serde_json::to_string_pretty(journal).map_err(|err| state_file_error(&path,err))?;
+    let tmp_path = format!("{}.{}.{}.tmp",path,process::id(),NEXT.fetch_add(1,Ordering::Relaxed));
+    write_durably(&tmp_path,&json)?;
+    let linked = fs::hard_link(&tmp_path,&path);
+    let _ = fs::remove_file(&tmp_path);
+    match linked {
+        Ok(()) => sync_parent(&path),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            let other = read_journal_file(&path)?;
+            Err(HyraidError::Validation(format!(
+                "Unfinished {} of array {} found, run `hyraid recover` first",
+                other.operation,
+                other.name
+            )))
+        },
+        Err(err) => Err(state_file_error(&path,err))
+    }
+}
+
+/// Writes the journal durably, so it survives a power loss right after
+pub fn write_journal(path: &str, journal: &Journal) -> Result<(),HyraidError> {
+    let path = journal_path(path);
+    let json = serde_json::to_string_pretty(journal).map_err(|err| state_file_error(&path,err))?;
+    replace_file(&path,&json)
+}
+
+/// Removes the journal once its operation finished or was rolled back
+pub fn remove_journal(path: &str) -> Result<(),HyraidError> {
+    let path = journal_path(path);
+    if !Path::new(&path).exists() {
+        return Ok(());
+    }
+    fs::remove_file(&path).map_err(|err| state_file_error(&path,err))
+}
@@ -0,0 +1,93 @@
+/*!
+    Tests for reading state files written by older releases.
+*/
+
+use std::fs;
+
+use hyraid_json::{
+    SCHEMA_VERSION,
+    read_arrays,
+    read_document,
+    remove_array
+};
+use hyraid_types::RedundancyPolicy;
+use serde_json::Value;
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}",env!("CARGO_MANIFEST_DIR"),name)
+}
+
+/// Copies a fixture to a temporary state file, since writes modify it
+fn state_file(test: &str, fixture_name: &str) -> String {
+    let path = std::env::temp_dir()
+        .join(format!("hyraid-schema-{}-{}.json",test,std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    fs::copy(fixture(fixture_name),&path).unwrap();
+    path
+}
+
+#[test]
+fn reads_unversioned_state_file() {
+    let document = read_document(&fixture("unversioned.json")).unwrap();
+
+    assert_eq!(document.schema_version,SCHEMA_VERSION);
+    assert_eq!(document.arrays.len(),2);
+
+    let media = &document.arrays[0];
+    assert_eq!(media.name,"media");
+    assert_eq!(media.lvm_lv_path,"/dev/hyraid_vg_Qm3kX9aLp2Zr8TwE/lvol0");
+    assert_eq!(media.raid_level,5);
+    assert_eq!(media.disks.len(),3);
+    assert_eq!(media.raid_map["/dev/md/hyraid_md_Vt7pR2nJ5sKc1YhB"].len(),3);
+    assert_eq!(media.slices,vec![4000752599040,4000785104896]);
+    assert_eq!(media.part_map["/dev/sdc"].len(),2);
+    assert_eq!(media.uuid,None);
+    assert_eq!(media.generation,0);
+    assert_eq!(media.policy,RedundancyPolicy::RaidLevel);
+    assert!(media.spares.is_empty());
+    assert!(document.global_spares.is_empty());
+    assert_eq!(media.scrub,None);
+    assert_eq!(media.scrub_schedule,None);
+
+    assert_eq!(document.arrays[1].name,"backup");
+}
+
+#[test]
+fn reads_empty_unversioned_state_file() {
+    let path = state_file("empty","unversioned.json");
+    fs::write(&path,"[]").unwrap();
+
+    assert!(read_arrays(&path).unwrap().is_empty());
+}
+
+#[test]
+fn writes_upgrade_unversioned_state_file() {
+    let path = state_file("upgrade","unversioned.json");
+
+    remove_array(&path,"backup").unwrap();
+
+    let raw: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(raw["schema_version"],SCHEMA_VERSION);
+    assert_eq!(raw["arrays"].as_array().unwrap().len(),1);
+    assert_eq!(raw["arrays"][0]["name"],"media");
+
+    // the old file is kept as a backup, untouched
+    let backup = fs::read_to_string(format!("{}.1",path)).unwrap();
+    assert_eq!(backup,fs::read_to_string(fixture("unversioned.json")).unwrap());
+}
+
+#[test]
+fn missing_state_file_has_no_arrays() {
+    let path = state_file("missing","unversioned.json");
+    fs::remove_file(&path).unwrap();
+
+    let document = read_document(&path).unwrap();
+    assert_eq!(document.schema_version,SCHEMA_VERSION);
+    assert!(document.arrays.is_empty());
+}
+
+#[test]
+fn rejects_newer_schema() {
+    assert!(read_document(&fixture("future.json")).is_err());
+}
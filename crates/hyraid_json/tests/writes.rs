@@ -0,0 +1,105 @@
+/*!
+    Tests for writing the state file: atomic replacement, locking, backups
+    and starting the journal.
+*/
+
+use std::{fs, path::Path, thread};
+
+use hyraid_json::{create_journal, read_arrays, read_journal, remove_journal, set_scrub_schedule, update_array};
+use hyraid_types::{Journal, JournalOperation, ScrubSchedule};
+
+/// Copies the unversioned fixture, with arrays `media` and `backup`, to a temporary state file
+fn state_file(test: &str) -> String {
+    let path = std::env::temp_dir()
+        .join(format!("hyraid-writes-{}-{}.json",test,std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    fs::copy(format!("{}/tests/fixtures/unversioned.json",env!("CARGO_MANIFEST_DIR")),&path).unwrap();
+    path
+}
+
+fn generation(path: &str, name: &str) -> usize {
+    read_arrays(path).unwrap().into_iter().find(|x| x.name == name).unwrap().generation
+}
+
+#[test]
+fn replaces_state_file_through_temporary_file() {
+    let path = state_file("replace");
+    let tmp_path = format!("{}.tmp",path);
+    // left over by a crash half way through a write
+    fs::write(&tmp_path,"{\"schema_ver").unwrap();
+
+    set_scrub_schedule(&path,"media",Some(ScrubSchedule::Weekly)).unwrap();
+
+    assert!(!Path::new(&tmp_path).exists());
+    let media = read_arrays(&path).unwrap().into_iter().find(|x| x.name == "media").unwrap();
+    assert_eq!(media.scrub_schedule,Some(ScrubSchedule::Weekly));
+}
+
+#[test]
+fn concurrent_updates_keep_each_other() {
+    let path = state_file("concurrent");
+    set_scrub_schedule(&path,"media",Some(ScrubSchedule::Monthly)).unwrap();
+
+    let writers: Vec<_> = ["media","backup"]
+        .into_iter()
+        .map(|name| {
+            let path = path.to_string();
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    update_array(&path,name,|x| x.generation += 1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert_eq!(generation(&path,"media"),20);
+    assert_eq!(generation(&path,"backup"),20);
+    let media = read_arrays(&path).unwrap().into_iter().find(|x| x.name == "media").unwrap();
+    assert_eq!(media.scrub_schedule,Some(ScrubSchedule::Monthly));
+}
+
+#[test]
+fn rotates_backups() {
+    let path = state_file("rotate");
+
+    for i in 1..=7 {
+        update_array(&path,"media",|x| x.generation = i).unwrap();
+    }
+
+    assert_eq!(generation(&path,"media"),7);
+    for i in 1..=5 {
+        assert_eq!(generation(&format!("{}.{}",path,i),"media"),7 - i);
+    }
+    assert!(!Path::new(&format!("{}.6",path)).exists());
+}
+
+#[test]
+fn only_one_operation_creates_the_journal() {
+    let path = state_file("journal");
+    let _ = remove_journal(&path);
+
+    let created: Vec<bool> = (0..8)
+        .map(|i| {
+            let path = path.to_string();
+            thread::spawn(move || create_journal(&path,&Journal {
+                name: format!("array{}",i),
+                operation: JournalOperation::Add { disks: vec![], allow_degraded: false },
+                previous: None,
+                entry: None,
+                steps: vec![]
+            }).is_ok())
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|x| x.join().unwrap())
+        .collect();
+
+    assert_eq!(created.iter().filter(|x| **x).count(),1);
+    let winner = created.iter().position(|x| *x).unwrap();
+    assert_eq!(read_journal(&path).unwrap().unwrap().name,format!("array{}",winner));
+    remove_journal(&path).unwrap();
+}
@@ -19,30 +19,44 @@
 */
 
 use std::{process::Command};
-use hyraid_utils::run_cmd;
+use hyraid_utils::{CommandRunner, CommandError, HyraidError, command_argv};
 
 pub enum SizeFormat {
     EXTENTS,
     SIZE
 }
 
+/// Physical Volume of a Volume Group, as reported by `pvs`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhysicalVolume {
+    pub name: String,
+    /// Offset of the first extent in bytes
+    pub pe_start: usize,
+    pub pe_count: usize,
+    pub pe_alloc_count: usize,
+    /// Size of an extent in bytes
+    pub extent_size: usize,
+}
+
 /// Initialize LVM Physical Volume
-pub fn lvm_pv_create(partitions: &[&str]) -> Result<(),String> {
+pub fn lvm_pv_create(runner: &dyn CommandRunner, partitions: &[&str]) -> Result<(),HyraidError> {
     let mut output = Command::new("pvcreate");    
     output.args(partitions);
-    run_cmd!(output)
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
 }
 
 /// Create LVM Volume Group
-pub fn lvm_vg_create(group_name: &str, partitions: &[&str]) -> Result<(),String> {
+pub fn lvm_vg_create(runner: &dyn CommandRunner, group_name: &str, partitions: &[&str]) -> Result<(),HyraidError> {
     let mut output = Command::new("vgcreate");
     output.arg(group_name);
     output.args(partitions);
-    run_cmd!(output)
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
 }
 
 /// Create LVM Logical Volume
-pub fn lvm_lv_create(group_name: &str, partitions: &[&str], size_type: SizeFormat, size: &str) -> Result<(),String> {
+pub fn lvm_lv_create(runner: &dyn CommandRunner, group_name: &str, partitions: &[&str], size_type: SizeFormat, size: &str) -> Result<(),HyraidError> {
     let mut output = Command::new("lvcreate");
     output.arg(group_name);
     output.args(partitions);
@@ -51,18 +65,20 @@ pub fn lvm_lv_create(group_name: &str, partitions: &[&str], size_type: SizeForma
         SizeFormat::SIZE => output.arg("-L")
     };
     output.arg(size);
-    run_cmd!(output)
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
 }
 
 /// Resize Physical Volume
-pub fn lvm_pv_resize(partitions: &[&str]) -> Result<(),String> {
+pub fn lvm_pv_resize(runner: &dyn CommandRunner, partitions: &[&str]) -> Result<(),HyraidError> {
     let mut output = Command::new("pvresize");
     output.args(partitions);
-    run_cmd!(output)
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
 }
 
 /// Resize Logical Volume
-pub fn lvm_lv_resize(partition: &str, resizefs: bool, size_type: SizeFormat, size: &str) -> Result<(),String> {
+pub fn lvm_lv_resize(runner: &dyn CommandRunner, partition: &str, resizefs: bool, size_type: SizeFormat, size: &str) -> Result<(),HyraidError> {
     let mut output = Command::new("lvresize");
     output.arg(partition);
     if resizefs {
@@ -73,13 +89,168 @@ pub fn lvm_lv_resize(partition: &str, resizefs: bool, size_type: SizeFormat, siz
         SizeFormat::SIZE => output.arg("-L")
     };
     output.arg(size);
-    run_cmd!(output)
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
 }
 
 /// Add Physical Volume to Volume Group
-pub fn lvm_vg_extend(group_name: &str, partitions: &[&str]) -> Result<(),String> {
+pub fn lvm_vg_extend(runner: &dyn CommandRunner, group_name: &str, partitions: &[&str]) -> Result<(),HyraidError> {
     let mut output = Command::new("vgextend");
     output.arg(group_name);
     output.args(partitions);
-    run_cmd!(output)
-}
\ No newline at end of file
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
+}
+
+/// Size of Logical Volume in bytes
+pub fn lvm_lv_size(runner: &dyn CommandRunner, partition: &str) -> Result<usize,HyraidError> {
+    let mut output = Command::new("lvs");
+    output.args(["--noheadings","--nosuffix","--units","b","-o","lv_size"]);
+    output.arg(partition);
+    let argv = command_argv(&output);
+    let output = runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    output.stdout
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| HyraidError::Lvm(CommandError {
+            argv,
+            code: Some(0),
+            stderr: format!("Unexpected output: {}",output.stdout.trim())
+        }))
+}
+
+/// Deactivate Logical Volume
+pub fn lvm_lv_deactivate(runner: &dyn CommandRunner, partition: &str) -> Result<(),HyraidError> {
+    let mut output = Command::new("lvchange");
+    output.arg("-an");
+    output.arg(partition);
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
+}
+
+/// Remove Logical Volume
+pub fn lvm_lv_remove(runner: &dyn CommandRunner, partition: &str) -> Result<(),HyraidError> {
+    let mut output = Command::new("lvremove");
+    output.arg("-y");
+    output.arg(partition);
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
+}
+
+/// Remove Volume Group
+pub fn lvm_vg_remove(runner: &dyn CommandRunner, group_name: &str) -> Result<(),HyraidError> {
+    let mut output = Command::new("vgremove");
+    output.arg("-y");
+    output.arg(group_name);
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
+}
+
+/// Remove Physical Volume labels
+pub fn lvm_pv_remove(runner: &dyn CommandRunner, partitions: &[&str]) -> Result<(),HyraidError> {
+    let mut output = Command::new("pvremove");
+    output.arg("-y");
+    output.args(partitions);
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
+}
+
+/// Get the Volume Group of a Physical Volume, `None` if it isn't in one
+pub fn lvm_pv_volume_group(runner: &dyn CommandRunner, partition: &str) -> Result<Option<String>,HyraidError> {
+    let mut output = Command::new("pvs");
+    output.args(["--noheadings","-o","vg_name"]);
+    output.arg(partition);
+    let output = runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    let group_name = output.stdout.trim();
+    Ok((!group_name.is_empty()).then(|| group_name.to_string()))
+}
+
+/// Get the Physical Volumes of a Volume Group
+pub fn lvm_vg_physical_volumes(runner: &dyn CommandRunner, group_name: &str) -> Result<Vec<PhysicalVolume>,HyraidError> {
+    let mut output = Command::new("pvs");
+    output.args(["--noheadings","--nosuffix","--units","b","-o","pv_name,pe_start,pv_pe_count,pv_pe_alloc_count,vg_extent_size"]);
+    output.arg("--select");
+    // the selection takes the bare name, not the /dev/ path
+    output.arg(format!("vg_name={}",group_name.trim_start_matches("/dev/")));
+    let argv = command_argv(&output);
+    let output = runner.run(&mut output).map_err(HyraidError::Lvm)?;
+
+    output.stdout
+        .lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let number = |i: usize| fields.get(i).and_then(|x| x.parse::<usize>().ok());
+            match (fields.first(),number(1),number(2),number(3),number(4)) {
+                (Some(name),Some(pe_start),Some(pe_count),Some(pe_alloc_count),Some(extent_size)) => Ok(PhysicalVolume {
+                    name: name.to_string(),
+                    pe_start,
+                    pe_count,
+                    pe_alloc_count,
+                    extent_size
+                }),
+                _ => Err(HyraidError::Lvm(CommandError {
+                    argv: argv.to_vec(),
+                    code: Some(0),
+                    stderr: format!("Unexpected output: {}",line.trim())
+                }))
+            }
+        })
+        .collect()
+}
+
+/// Get the extent after the last allocated one of a Physical Volume, `None` if nothing is allocated
+pub fn lvm_pv_allocated_end(runner: &dyn CommandRunner, partition: &str) -> Result<Option<usize>,HyraidError> {
+    let mut output = Command::new("pvs");
+    output.args(["--noheadings","--segments","-o","pvseg_start,pvseg_size,lv_name"]);
+    output.arg(partition);
+    let output = runner.run(&mut output).map_err(HyraidError::Lvm)?;
+
+    // free segments have no logical volume
+    Ok(output.stdout
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .filter(|fields| fields.len() == 3)
+        .filter_map(|fields| Some(fields[0].parse::<usize>().ok()? + fields[1].parse::<usize>().ok()?))
+        .max())
+}
+
+/// Move allocated extents off a Physical Volume, or a `PV:FIRST-LAST` range of one,
+/// onto the given destinations
+pub fn lvm_pv_move(runner: &dyn CommandRunner, source: &str, destinations: &[&str]) -> Result<(),HyraidError> {
+    let mut output = Command::new("pvmove");
+    output.args(["--alloc","anywhere"]);
+    output.arg(source);
+    output.args(destinations);
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
+}
+
+/// Shrink Physical Volume to the given size in bytes
+pub fn lvm_pv_shrink(runner: &dyn CommandRunner, partition: &str, size: usize) -> Result<(),HyraidError> {
+    let mut output = Command::new("pvresize");
+    output.arg("-y");
+    output.arg("--setphysicalvolumesize");
+    output.arg(format!("{}b",size));
+    output.arg(partition);
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
+}
+
+/// Remove Physical Volumes from Volume Group
+pub fn lvm_vg_reduce(runner: &dyn CommandRunner, group_name: &str, partitions: &[&str]) -> Result<(),HyraidError> {
+    let mut output = Command::new("vgreduce");
+    output.arg(group_name);
+    output.args(partitions);
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
+}
+
+/// Activate every Logical Volume of a Volume Group
+pub fn lvm_vg_activate(runner: &dyn CommandRunner, group_name: &str) -> Result<(),HyraidError> {
+    let mut output = Command::new("vgchange");
+    output.arg("-ay");
+    output.arg(group_name);
+    runner.run(&mut output).map_err(HyraidError::Lvm)?;
+    Ok(())
+}
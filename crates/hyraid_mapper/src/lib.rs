@@ -18,9 +18,11 @@
 */
 
 use std::{
-    collections::{HashMap}, 
-    path::Path, 
-    process::exit
+    collections::{HashMap},
+    fs,
+    sync::Arc,
+    thread,
+    time::Duration
 };
 
 use hyraid_types::{
@@ -28,7 +30,26 @@ use hyraid_types::{
     PartitionMap, 
     PartitionSlices, 
     RaidMap,
-    HyraidArray
+    HyraidArray,
+    ArrayStatus,
+    MdDeviceStatus,
+    MdHealth,
+    HyraidPlan,
+    MdPlan,
+    MigrationStep,
+    LvmPlan,
+    CapacityEstimate,
+    CapacityReport,
+    ScanReport,
+    UpgradeReport,
+    RedundancyPolicy,
+    RedundancyReport,
+    SliceGroupRedundancy,
+    Journal,
+    JournalEntry,
+    JournalOperation,
+    JournalStep,
+    StepState
 };
 
 use hyraid_lvm2::{
@@ -36,36 +57,126 @@ use hyraid_lvm2::{
     lvm_pv_create,
     lvm_vg_create,
     lvm_pv_resize,
-    lvm_vg_extend
+    lvm_vg_extend,
+    lvm_lv_size,
+    lvm_lv_deactivate,
+    lvm_lv_remove,
+    lvm_vg_remove,
+    lvm_pv_remove,
+    lvm_pv_volume_group,
+    lvm_vg_activate,
+    lvm_vg_physical_volumes,
+    lvm_pv_allocated_end,
+    lvm_pv_move,
+    lvm_pv_shrink,
+    lvm_vg_reduce,
+    PhysicalVolume
 };
 
-use raid_rs::mdadm::{
+use hyraid_mdadm::{
     create_raid_array, 
     fail_from_raid_array, 
     remove_from_raid_array,
-    add_to_raid_array
+    add_to_raid_array,
+    stop_raid_array,
+    zero_superblock,
+    examine_partition,
+    assemble_raid_array,
+    replace_in_raid_array,
+    set_raid_array_size,
+    reshape_raid_array,
+    shrink_raid_array,
+    migrate_raid_array,
+    detail_raid_array
+};
+
+use hyraid_mdstat::{
+    MdArray,
+    MdDetail,
+    MemberRole,
+    ArrayState,
+    SyncProgress,
+    read_mdstat
 };
 
 use hyraid_utils::{
-    error_exit,
-    unwrap_or_exit,
-    unwrap_or_exit_verbose
+    HyraidError,
+    CancelToken,
+    CommandRunner,
+    RecordingRunner,
+    SystemRunner,
+    format_size
 };
 
 use hyraid_gpt::{
-    get_path_of_partition,
-    clear_partitions,
-    get_sector_size,
-    ensure_gpt,
-    get_free_space,
-    validate_partition
+    BlockDevice,
+    FakeDisks,
+    GptPartition,
+    PartitionIdentity,
+    SystemDisks
 };
 
-use gpt;
-
 use rand::Rng;
+use uuid::Uuid;
+
+static HYRAID_JSON_PATH: &str = "/etc/hyraid.json";
+static MDSTAT_PATH: &str = "/proc/mdstat";
+/// Free space below this is left alone, e.g. what partition alignment leaves over
+const MIN_FREE_SPACE: usize = 1 << 20;
+
+/// Called with the kernel name and progress of every md device a long operation waits for
+pub type ProgressFn = Arc<dyn Fn(&str,&SyncProgress) + Send + Sync>;
+
+/// Everything the mapper reads or changes outside of itself:
+/// external commands, disks and the state file.
+///
+/// `Backend::default()` is the real system, tests swap in
+/// `RecordingRunner`, `FakeDisks` and a temporary state file.
+#[derive(Clone)]
+pub struct Backend {
+    pub runner: Arc<dyn CommandRunner>,
+    pub disks: Arc<dyn BlockDevice>,
+    pub state_file: String,
+    pub mdstat_file: String,
+    /// Checked by long operations before they change the disks
+    /// and while they wait for md
+    pub cancel: CancelToken,
+    pub progress: ProgressFn,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self {
+            runner: Arc::new(SystemRunner),
+            disks: Arc::new(SystemDisks),
+            state_file: HYRAID_JSON_PATH.to_string(),
+            mdstat_file: MDSTAT_PATH.to_string(),
+            cancel: CancelToken::new(),
+            progress: Arc::new(|_,_| {})
+        }
+    }
+}
 
-static HYRAID_JSON_PATH: &'static str = "/etc/hyraid.json";
+impl Backend {
+    /// Backend for tests: a `RecordingRunner`, `FakeDisks` without any disks,
+    /// an empty `<state_file>.mdstat` and no state file or journal left over from earlier runs.
+    pub fn fake(state_file: &str) -> (Self,Arc<RecordingRunner>,Arc<FakeDisks>) {
+        let runner = Arc::new(RecordingRunner::new());
+        let disks = Arc::new(FakeDisks::new());
+        let backend = Self {
+            runner: runner.clone(),
+            disks: disks.clone(),
+            state_file: state_file.to_string(),
+            mdstat_file: format!("{}.mdstat",state_file),
+            cancel: CancelToken::new(),
+            progress: Arc::new(|_,_| {})
+        };
+        let _ = fs::remove_file(state_file);
+        let _ = hyraid_json::remove_journal(state_file);
+        let _ = fs::write(&backend.mdstat_file,"");
+        (backend,runner,disks)
+    }
+}
 
 fn random_string(length: usize) -> String {
     rand::rng()
@@ -75,36 +186,66 @@ fn random_string(length: usize) -> String {
         .collect()
 }
 
-/// Generates slices from disks.
-fn gen_slices(disks: &[&str]) -> PartitionSlices {
-    let mut sizes = PartitionSlices::new();
-    for disk in disks {
-        sizes.push(get_free_space(disk));
-    }
+/// Gets free space of every disk in bytes
+fn get_disk_sizes<'a>(backend: &Backend, disks: &[&'a str]) -> Result<Vec<(&'a str,usize)>,HyraidError> {
+    disks
+        .iter()
+        .map(|disk| Ok((*disk,backend.disks.free_space(disk)?)))
+        .collect()
+}
+
+/// Finds an array in the state file
+fn find_array(backend: &Backend, name: &str) -> Result<HyraidArray,HyraidError> {
+    hyraid_json::read_arrays(&backend.state_file)?
+        .into_iter()
+        .find(|x| x.name == name)
+        .ok_or(HyraidError::Validation(format!("No such HyRAID array: {}",name)))
+}
+
+/// Writes the layout of an array back to the state file.
+///
+/// Spares and scrub state are left as they're on disk, the monitor
+/// and the API change those while a layout operation runs.
+fn write_layout(backend: &Backend, name: &str, entry: &HyraidArray) -> Result<(),HyraidError> {
+    hyraid_json::update_array(&backend.state_file,name,|x| {
+        x.uuid = entry.uuid.clone();
+        x.generation = entry.generation;
+        x.lvm_lv_path = entry.lvm_lv_path.to_string();
+        x.raid_level = entry.raid_level;
+        x.policy = entry.policy;
+        x.disks = entry.disks.clone();
+        x.raid_map = entry.raid_map.clone();
+        x.slices = entry.slices.clone();
+        x.part_map = entry.part_map.clone();
+    })
+}
+
+/// Generates slices from disk sizes.
+fn gen_slices(disks: &[(&str,usize)]) -> Result<PartitionSlices,HyraidError> {
+    let mut sizes: PartitionSlices = disks.iter().map(|(_,size)| *size).collect();
 
     sizes.sort_unstable();
 
-    let min_size: usize = sizes[0];
+    let min_size: usize = *sizes
+        .first()
+        .ok_or(HyraidError::Validation("No disks given".to_string()))?;
 
     let mut slices: Vec<usize> = vec![min_size];
 
     // skip first element (smallest disk size)
     for size in sizes[1..].iter() { 
-        let slice = *size-slices.iter().sum::<usize>(); // Should probably rewrite this line.
+        let slice = size.saturating_sub(slices.iter().sum::<usize>()); // Should probably rewrite this line.
         if slice != 0 {
             slices.push(slice)
         };
     }
 
-    slices
+    Ok(slices)
 }
 
 /// Re-compute slices to account for larger disks being added
-fn recompute_slices(disks: &[&str], slices: &PartitionSlices) -> PartitionSlices {
-    let mut sizes = PartitionSlices::new();
-    for disk in disks {
-        sizes.push(get_free_space(disk));
-    }
+fn recompute_slices(disks: &[(&str,usize)], slices: &PartitionSlices) -> PartitionSlices {
+    let mut sizes: PartitionSlices = disks.iter().map(|(_,size)| *size).collect();
 
     sizes.sort_unstable();
 
@@ -112,7 +253,7 @@ fn recompute_slices(disks: &[&str], slices: &PartitionSlices) -> PartitionSlices
 
     // skip first element (smallest disk size)
     for size in sizes.iter() { 
-        let slice = *size-slices.iter().sum::<usize>(); // Should probably rewrite this line.
+        let slice = size.saturating_sub(slices.iter().sum::<usize>()); // Should probably rewrite this line.
         if slice != 0 {
             slices.push(slice)
         };
@@ -121,24 +262,93 @@ fn recompute_slices(disks: &[&str], slices: &PartitionSlices) -> PartitionSlices
     slices
 }
 
-/// Finds range from a vector whose sum is x
-fn find_range_sum(vector: Vec<usize>,sum: usize) -> Vec<usize> {
-    let mut x = 0;
+/// Number of members of a RAID array that hold data rather than redundancy
+fn data_members(raid_level: usize,members: usize) -> usize {
+    match raid_level {
+        0 => members,
+        1 => 1,
+        5 => members - 1,
+        6 => members - 2,
+        _ => 0
+    }
+}
 
-    while vector[0..x].iter().sum::<usize>() != sum {
-        x += 1;
+/// Capacity of a classic RAID array built from whole disks,
+/// `None` if there aren't enough disks for the RAID level.
+fn classic_raid_capacity(sizes: &[usize], raid_level: usize) -> Option<CapacityEstimate> {
+    let min_members = match raid_level {
+        1 => 2,
+        5 => 3,
+        6 => 4,
+        _ => 1
+    };
+    if sizes.len() < min_members {
+        return None;
     }
 
-    vector[0..x].to_vec()
+    let raw: usize = sizes.iter().sum();
+    let min_size = *sizes.iter().min()?;
+    let usable = min_size * data_members(raid_level,sizes.len());
+
+    Some(CapacityEstimate {
+        usable,
+        redundancy: min_size * sizes.len() - usable,
+        unused: raw - min_size * sizes.len()
+    })
+}
+
+/// Estimates the capacity of a HyRAID array built from disks of the given sizes
+/// and compares it with classic RAID1/5/6 on the same disks.
+pub fn estimate_capacity(sizes: &[usize], raid_level: usize, policy: RedundancyPolicy) -> Result<CapacityReport,HyraidError> {
+    let disks: Vec<(String,usize)> = sizes
+        .iter()
+        .enumerate()
+        .map(|(i,size)| (format!("disk{}",i),*size))
+        .collect();
+    let disks: Vec<(&str,usize)> = disks.iter().map(|(disk,size)| (disk.as_str(),*size)).collect();
+
+    let slices = gen_slices(&disks)?;
+    let part_map = make_partition_map(&disks,&slices)?;
+
+    let raw: usize = sizes.iter().sum();
+    let mut hyraid = CapacityEstimate { usable: 0, redundancy: 0, unused: 0 };
+
+    // every slice forms an md array with one partition per disk large enough for it
+    for (i,slice) in slices.iter().enumerate() {
+        let members = part_map.values().filter(|parts| parts.len() > i).count();
+        if members == 1 {
+            hyraid.unused += slice;
+            continue;
+        }
+        let usable = slice * data_members(find_raid_level(members,raid_level,policy)?,members);
+        hyraid.usable += usable;
+        hyraid.redundancy += slice * members - usable;
+    }
+
+    Ok(CapacityReport {
+        raw,
+        hyraid,
+        raid1: classic_raid_capacity(sizes,1),
+        raid5: classic_raid_capacity(sizes,5),
+        raid6: classic_raid_capacity(sizes,6),
+    })
+}
+
+/// Finds range from a vector whose sum is x
+fn find_range_sum(vector: Vec<usize>,sum: usize) -> Option<Vec<usize>> {
+    (0..=vector.len())
+        .find(|x| vector[0..*x].iter().sum::<usize>() == sum)
+        .map(|x| vector[0..x].to_vec())
 }
 
 /// Lay-out partition map
-fn make_partition_map(disks: &[&str], slices: &PartitionSlices) -> PartitionMap {
+fn make_partition_map(disks: &[(&str,usize)], slices: &PartitionSlices) -> Result<PartitionMap,HyraidError> {
     let mut result = PartitionMap::new();
 
-    for disk in disks {
-        let size = get_free_space(disk);
-        let part = find_range_sum(slices.clone(),size).iter().map(
+    for (disk,size) in disks {
+        let slices = find_range_sum(slices.clone(),*size)
+            .ok_or(HyraidError::Validation(format!("Size of {} doesn't match the slices of the array",disk)))?;
+        let part = slices.iter().map(
             |x| {
                 DiskPartition {
                     size: *x,
@@ -150,69 +360,63 @@ fn make_partition_map(disks: &[&str], slices: &PartitionSlices) -> PartitionMap
         result.insert(disk.to_string(),part);
     }
 
-    result
+    Ok(result)
 }
 
-/// Creates partitions from partition map and returns same `PartitionMap`, 
-/// this time with path of the partition included.
-fn create_partition_map(part_map: PartitionMap) -> PartitionMap {
+/// Steps creating the partitions of a partition map
+fn partition_steps(part_map: PartitionMap, array_uuid: &str, generation: usize) -> Vec<JournalEntry> {
+    let mut part_map: Vec<(String,Vec<DiskPartition>)> = part_map.into_iter().collect();
+    part_map.sort_by(|(a,_),(b,_)| a.cmp(b));
+    part_map
+        .into_iter()
+        .map(|(disk,parts)| planned(JournalStep::AddPartitions {
+            disk,
+            sizes: parts.iter().map(|x| x.size).collect(),
+            array_uuid: array_uuid.to_string(),
+            generation
+        }))
+        .collect()
+}
+
+/// Partition map of wiped disks once their partitions are created,
+/// every partition on them belongs to the array.
+fn read_partition_map(backend: &Backend, disks: &[&str]) -> Result<PartitionMap,HyraidError> {
     let mut map = PartitionMap::new();
-    for (disk,parts) in part_map {
-        let sector_size = get_sector_size(&disk);
-        let diskpath = std::path::Path::new(&disk);
-        let mut gptdisk = unwrap_or_exit!(
-            gpt::GptConfig::new()
-                .writable(true)
-                .open(diskpath),
-            "Failed to open disk."
-        );
-        for part in parts {
-            gptdisk.add_partition(
-                "hyraid_partition",
-                part.size.try_into().unwrap(),
-                gpt::partition_types::LINUX_FS,
-                0,
-                None
-            ).unwrap();
-        }
-        gptdisk.write().unwrap();
-        let gptdisk = unwrap_or_exit!(
-            gpt::GptConfig::new()
-                .writable(true)
-                .open(diskpath),
-            "Failed to open disk."
-        );
-        let mut partitions: Vec<DiskPartition> = vec![];
-        for (_,partition) in gptdisk.partitions() {
-            let part_path = get_path_of_partition(&partition.clone());
-            partitions.push(
-                DiskPartition { 
-                    path: Some(part_path), 
-                    size: (partition.sectors_len().unwrap() * TryInto::<u64>::try_into(sector_size).unwrap())
-                        .try_into()
-                        .unwrap()
-                }
-            );
-            validate_partition(partition.clone());
-        }
+    for disk in disks {
+        let mut partitions = get_disk_partitions(backend,disk)?;
         partitions.sort_by_key(|k| k.size);
-        map.insert(disk,partitions);
+        map.insert(disk.to_string(),partitions);
     }
 
-    map
+    Ok(map)
+}
+
+fn into_disk_partitions(partitions: Vec<GptPartition>) -> Vec<DiskPartition> {
+    partitions
+        .into_iter()
+        .map(|x| DiskPartition { path: Some(x.path), size: x.size })
+        .collect()
+}
+
+/// Sorts the disks of a partition map by how many partitions they have, most first.
+/// Ties are broken by the disk path so the order is always the same.
+fn sort_partition_map(part_map: PartitionMap) -> Vec<(String,Vec<DiskPartition>)> {
+    let mut part_map: Vec<(String,Vec<DiskPartition>)> = part_map.into_iter().collect();
+    part_map.sort_by(|(disk_a,parts_a),(disk_b,parts_b)| {
+        parts_b.len().cmp(&parts_a.len()).then(disk_a.cmp(disk_b))
+    });
+    part_map
 }
 
 /// Create initial RAID arrays
 fn init_raid_map(part_map: PartitionMap) -> RaidMap {
     let mut raid_map = RaidMap::new();
     
-    let mut part_map: Vec<(String,Vec<DiskPartition>)> = part_map.into_iter().collect();
-    part_map.sort_unstable_by_key(|(_,parts)| parts.len());
-    part_map.reverse();
+    let part_map = sort_partition_map(part_map);
 
     let mut groups: HashMap<usize,Vec<DiskPartition>> = HashMap::new();
     
-    for i in 0..part_map[0].1.len() {
+    for i in 0..part_map.first().map_or(0,|(_,parts)| parts.len()) {
         groups.insert(i,vec![]);
     }
 
@@ -243,18 +447,44 @@ fn init_raid_map(part_map: PartitionMap) -> RaidMap {
     raid_map
 }
 
+/// Gives partitions that don't exist yet a placeholder path naming their disk,
+/// in the same order `read_partition_map` would return them.
+fn label_planned_partitions(part_map: PartitionMap) -> PartitionMap {
+    part_map
+        .into_iter()
+        .map(|(disk,mut parts)| {
+            parts.sort_by_key(|k| k.size);
+            let parts = parts
+                .into_iter()
+                .enumerate()
+                .map(|(i,part)| {
+                    DiskPartition {
+                        path: Some(format!("{} (new partition {})",disk,i+1)),
+                        size: part.size
+                    }
+                })
+                .collect();
+            (disk,parts)
+        })
+        .collect()
+}
+
 /// Return 2 raid maps, one of them is for creating and one of them for extending.
+///
+/// A slice group extends the md device whose members all are in it, wherever they
+/// are in the group: new disks with fewer partitions sort after the existing ones,
+/// so the members aren't always at its end. Only the partitions that aren't
+/// members yet are extended with, md refuses to add a member twice, and md devices
+/// that gain none are left alone.
 fn expand_raid_map(part_map: PartitionMap, raid_map: RaidMap) -> (RaidMap,RaidMap) {
     let mut raid_map_create = RaidMap::new();
     let mut raid_map_extend = RaidMap::new();
     
-    let mut part_map: Vec<(String,Vec<DiskPartition>)> = part_map.into_iter().collect();
-    part_map.sort_unstable_by_key(|(_,parts)| parts.len());
-    part_map.reverse();
+    let part_map = sort_partition_map(part_map);
 
     let mut groups: HashMap<usize,Vec<DiskPartition>> = HashMap::new();
     
-    for i in 0..part_map[0].1.len() {
+    for i in 0..part_map.first().map_or(0,|(_,parts)| parts.len()) {
         groups.insert(i,vec![]);
     }
 
@@ -280,19 +510,26 @@ fn expand_raid_map(part_map: PartitionMap, raid_map: RaidMap) -> (RaidMap,RaidMa
         let array = raid_map
             .iter()
             .find(|(name,partitions)| {
-                if group.ends_with(&partitions) {
+                if partitions.iter().all(|x| group.contains(x)) {
                     devname = name.to_string();
                     true
                 } else {
-                    devname = (&format!("/dev/md/hyraid_md_{}",random_string(10))[..]).to_string();
+                    devname = format!("/dev/md/hyraid_md_{}",random_string(10));
                     false
                 }
             });
 
-        if let Some((_,_)) = array {
-            raid_map_extend.insert(devname.to_string(),slice);
+        if let Some((_,members)) = array {
+            // only the partitions that aren't members yet
+            let slice: Vec<DiskPartition> = slice
+                .into_iter()
+                .filter(|x| !members.contains(x))
+                .collect();
+            if !slice.is_empty() {
+                raid_map_extend.insert(devname.to_string(),slice);
+            }
         } else {
-            if slice.len() != 1 && raid_map.values().find(|x| **x == slice) == None {
+            if slice.len() != 1 && !raid_map.values().any(|x| *x == slice) {
                 raid_map_create.insert(devname.to_string(),slice);
             }
         }
@@ -302,8 +539,17 @@ fn expand_raid_map(part_map: PartitionMap, raid_map: RaidMap) -> (RaidMap,RaidMa
 }
 
 /// Determine RAID level automatically
-fn find_raid_level(partitions: usize,intended_raid_level: usize) -> usize {
-    match intended_raid_level {
+fn find_raid_level(partitions: usize,intended_raid_level: usize,policy: RedundancyPolicy) -> Result<usize,HyraidError> {
+    if policy == RedundancyPolicy::TwoDisk {
+        if intended_raid_level != 6 {
+            return Err(HyraidError::Validation(
+                "The two-disk redundancy policy needs RAID level 6".to_string()
+            ));
+        }
+        return Ok(if partitions < 4 { 1 } else { 6 });
+    }
+
+    let level = match intended_raid_level {
         0 => 0,
         1 => 1,
         5 => {
@@ -314,247 +560,2089 @@ fn find_raid_level(partitions: usize,intended_raid_level: usize) -> usize {
             }
         },
         6 => {
-            if partitions < 3 {
-                1 // RAID1
+            if partitions < 4 {
+                1 // RAID1, mdadm needs 4 devices for RAID6
             } else {
                 6 // RAID6
             }
         },
 
         _ => {
-            error_exit!("Incorrect RAID level. Only RAID0,RAID1,RAID5 and RAID6 is supported.");
+            return Err(HyraidError::Validation(
+                "Incorrect RAID level. Only RAID0,RAID1,RAID5 and RAID6 is supported.".to_string()
+            ));
         }
+    };
+
+    Ok(level)
+}
+
+/// Number of members an md device can lose without losing data
+fn failures_tolerated(raid_level: usize,members: usize) -> usize {
+    match raid_level {
+        1 => members.saturating_sub(1),
+        5 => 1,
+        6 => 2,
+        _ => 0
+    }
+}
+
+/// Number of disk failures the requested RAID level and policy promise
+fn target_failures(raid_level: usize, policy: RedundancyPolicy) -> usize {
+    match (policy,raid_level) {
+        (RedundancyPolicy::TwoDisk,_) => 2,
+        (_,1) | (_,5) => 1,
+        (_,6) => 2,
+        _ => 0
+    }
+}
+
+/// Reports the fault tolerance of every md device of a layout and the space
+/// of every disk left out of them, e.g. the extra space of the largest disk.
+///
+/// `groups` pairs every md device with its RAID level and members once the layout is in place.
+fn redundancy_report(
+    groups: &[(&str,usize,&[DiskPartition])],
+    part_map: &PartitionMap,
+    raid_level: usize,
+    policy: RedundancyPolicy
+) -> RedundancyReport {
+    let mut unused: Vec<(String,usize)> = part_map
+        .iter()
+        .map(|(disk,partitions)| {
+            let size: usize = partitions
+                .iter()
+                .filter(|x| !groups.iter().any(|(_,_,members)| members.contains(x)))
+                .map(|x| x.size)
+                .sum();
+            (disk.to_string(),size)
+        })
+        .filter(|(_,size)| *size > 0)
+        .collect();
+    unused.sort();
+
+    let mut groups: Vec<SliceGroupRedundancy> = groups
+        .iter()
+        .map(|(device,level,members)| SliceGroupRedundancy {
+            device: device.to_string(),
+            raid_level: *level,
+            members: members.len(),
+            size: members.iter().map(|x| x.size).min().unwrap_or(0),
+            failures_tolerated: failures_tolerated(*level,members.len())
+        })
+        .collect();
+    groups.sort_by(|a,b| a.device.cmp(&b.device));
+
+    RedundancyReport {
+        target: target_failures(raid_level,policy),
+        groups,
+        unused
     }
 }
 
+/// Refuses a layout surviving fewer disk failures than requested, unless degraded layouts are allowed
+fn check_redundancy(report: &RedundancyReport, allow_degraded: bool) -> Result<(),HyraidError> {
+    let degraded = report.degraded();
+    if degraded.is_empty() || allow_degraded {
+        return Ok(());
+    }
+
+    let groups: Vec<String> = degraded
+        .iter()
+        .map(|x| format!(
+            "RAID{} of {} x {} survives {}",
+            x.raid_level,
+            x.members,
+            format_size(x.size),
+            x.failures_tolerated
+        ))
+        .collect();
+    Err(HyraidError::Validation(format!(
+        "Layout doesn't survive {} disk failure(s): {}. Pass --allow-degraded-layout to use it anyway",
+        report.target,
+        groups.join(", ")
+    )))
+}
+
 fn into_paths_slice(partitions: Vec<DiskPartition>) -> Vec<String> {
     let slice: Vec<String> = partitions
         .iter()
-        .map(|s| s.path.to_owned().unwrap())
+        .filter_map(|s| s.path.to_owned())
         .collect();
 
     slice.to_vec()
 }
 
-fn create_init_raid_map(raid_map: RaidMap,raid_level: usize) {
-    for (raid_dev,partitions) in raid_map {
-        let level = find_raid_level(partitions.len(),raid_level);
-
-        let slice: Vec<String> = into_paths_slice(partitions);
-        let slice: Vec<&str> = slice.iter().map(
-            |s| s.as_str()
-        ).collect();
-        
-        unwrap_or_exit_verbose!(
-            create_raid_array(&raid_dev,&slice,level),
-            "Error occurred while creating MD array"
-        );
-    }
+/// Steps creating the md devices of a raid map
+fn init_raid_map_steps(raid_map: &RaidMap, raid_level: usize, policy: RedundancyPolicy) -> Result<Vec<JournalEntry>,HyraidError> {
+    let mut md_devices: Vec<&String> = raid_map.keys().collect();
+    md_devices.sort();
+    md_devices
+        .into_iter()
+        .map(|device| Ok(planned(JournalStep::CreateMd {
+            device: device.to_string(),
+            partitions: into_paths_slice(raid_map[device].to_vec()),
+            raid_level: find_raid_level(raid_map[device].len(),raid_level,policy)?
+        })))
+        .collect()
 }
 
-/// Create LVM logical volume with all of the raid arrays.
+/// Steps creating the LVM logical volume with all of the raid arrays.
 /// basically combine the raid arrays into one.
-fn create_lvm(raid_map: &RaidMap) -> String {
-    let raid_arrays: &Vec<&str> = &raid_map.keys()
-        .into_iter()
-        .map(|s| s.as_str())
+fn lvm_steps(raid_map: &RaidMap, volume_group: &str) -> Vec<JournalEntry> {
+    let mut devices: Vec<String> = raid_map.keys().cloned().collect();
+    devices.sort();
+    vec![
+        planned(JournalStep::CreatePv { devices: devices.to_vec() }),
+        planned(JournalStep::CreateVg { volume_group: volume_group.to_string(), devices: devices.to_vec() }),
+        planned(JournalStep::CreateLv { volume_group: volume_group.to_string(), devices }),
+    ]
+}
+
+/// Plans the creation of a HyRAID array without touching any disk.
+///
+/// `disks` pairs every disk with its free space in bytes.
+pub fn plan_create(disks: &[(&str,usize)], raid_level: usize, policy: RedundancyPolicy) -> Result<HyraidPlan,HyraidError> {
+    let slices = gen_slices(disks)?;
+
+    let part_map = label_planned_partitions(make_partition_map(disks,&slices)?);
+    let raid_map = init_raid_map(part_map.clone());
+
+    let md_create: Vec<MdPlan> = raid_map
+        .iter()
+        .map(|(device,partitions)| {
+            Ok(MdPlan {
+                device: device.to_string(),
+                raid_level: find_raid_level(partitions.len(),raid_level,policy)?,
+                partitions: partitions.to_vec(),
+                migration: vec![]
+            })
+        })
+        .collect::<Result<_,HyraidError>>()?;
+    let groups: Vec<(&str,usize,&[DiskPartition])> = md_create
+        .iter()
+        .map(|x| (x.device.as_str(),x.raid_level,x.partitions.as_slice()))
         .collect();
-    let lv_name = format!("hyraid_vg_{}",random_string(16));
-    unwrap_or_exit_verbose!(
-        lvm_pv_create(raid_arrays),
-        "Error occured while setting up LVM"
-    );
-    unwrap_or_exit_verbose!(
-        lvm_vg_create(&lv_name[..],raid_arrays),
-        "Error occured while setting up LVM"
-    );
-    unwrap_or_exit_verbose!(
-        lvm_lv_create(&lv_name[..],raid_arrays,hyraid_lvm2::SizeFormat::EXTENTS,"100%FREE"),
-        "Error occured while setting up LVM"
-    );
-    
-    "/dev/".to_string() + &lv_name + &"/lvol0"
+    let redundancy = redundancy_report(&groups,&part_map,raid_level,policy);
+
+    Ok(HyraidPlan {
+        slices,
+        part_map,
+        md_create,
+        md_grow: vec![],
+        redundancy,
+        lvm: LvmPlan::Create {
+            physical_volumes: raid_map.keys().cloned().collect()
+        }
+    })
 }
 
-pub fn create_hyraid_array(name: String,disks: &[&str], raid_level: usize) -> String {
-    if let Some(_) = hyraid_json::read_arrays(HYRAID_JSON_PATH).iter().find(|x| x.name == name) {
-        error_exit!(format!("Array \"{}\" already exists",name));
+/// Plans adding disks to an existing HyRAID array without touching any disk.
+///
+/// `disks` pairs every disk with its free space in bytes.
+pub fn plan_add(backend: &Backend, name: String, disks: &[(&str,usize)]) -> Result<HyraidPlan,HyraidError> {
+    let entry = find_array(backend,&name)?;
+    let slices = recompute_slices(disks,&entry.slices);
+
+    let new_part_map = label_planned_partitions(make_partition_map(disks,&slices)?);
+    let mut part_map = new_part_map.clone();
+    part_map.extend(entry.part_map.to_owned());
+
+    let (raid_map_create,raid_map_extend) = expand_raid_map(part_map.clone(),entry.raid_map.to_owned());
+
+    let md_create: Vec<MdPlan> = raid_map_create
+        .iter()
+        .map(|(device,partitions)| {
+            Ok(MdPlan {
+                device: device.to_string(),
+                raid_level: find_raid_level(partitions.len(),entry.raid_level,entry.policy)?,
+                partitions: partitions.to_vec(),
+                migration: vec![]
+            })
+        })
+        .collect::<Result<_,HyraidError>>()?;
+    let md_grow: Vec<MdPlan> = raid_map_extend
+        .iter()
+        .map(|(device,partitions)| {
+            let members = entry.raid_map[device].len();
+            let target_members = members + partitions.len();
+            // the level it's migrated to once the partitions are added
+            let raid_level = find_raid_level(target_members,entry.raid_level,entry.policy)?;
+            Ok(MdPlan {
+                device: device.to_string(),
+                raid_level,
+                partitions: partitions.to_vec(),
+                migration: migration_steps(
+                    find_raid_level(members,entry.raid_level,entry.policy)?,
+                    members,
+                    raid_level,
+                    target_members
+                )?
+            })
+        })
+        .collect::<Result<_,HyraidError>>()?;
+    // every md device of the array once the disks are added
+    let mut members = entry.raid_map.to_owned();
+    for md in &md_grow {
+        members.entry(md.device.to_string()).or_default().extend(md.partitions.to_vec());
+    }
+    let mut groups: Vec<(&str,usize,&[DiskPartition])> = members
+        .iter()
+        .map(|(device,members)| {
+            Ok((device.as_str(),find_raid_level(members.len(),entry.raid_level,entry.policy)?,members.as_slice()))
+        })
+        .collect::<Result<_,HyraidError>>()?;
+    groups.extend(md_create.iter().map(|x| (x.device.as_str(),x.raid_level,x.partitions.as_slice())));
+    let mut redundancy = redundancy_report(&groups,&part_map,entry.raid_level,entry.policy);
+    // a grown md device survives less while it's reshaped through lower RAID levels
+    for group in redundancy.groups.iter_mut() {
+        let Some(md) = md_grow.iter().find(|x| x.device == group.device) else { continue };
+        for step in &md.migration {
+            group.failures_tolerated = group.failures_tolerated.min(step.failures_tolerated);
+        }
     }
 
-    for disk in disks {
-        ensure_gpt(disk);
-        clear_partitions(disk);
+    Ok(HyraidPlan {
+        slices,
+        part_map: new_part_map,
+        redundancy,
+        lvm: LvmPlan::Extend {
+            volume_group: entry.lvm_lv_path.trim_end_matches("/lvol0").to_string(),
+            created: md_create.iter().map(|x| x.device.to_string()).collect(),
+            resized: md_grow.iter().map(|x| x.device.to_string()).collect()
+        },
+        md_create,
+        md_grow,
+    })
+}
+
+/// Creates a HyRAID array, refusing a layout with less redundancy than
+/// `raid_level` and `policy` ask for unless `allow_degraded` is set.
+pub fn create_hyraid_array(
+    backend: &Backend,
+    name: String,
+    disks: &[&str],
+    raid_level: usize,
+    policy: RedundancyPolicy,
+    allow_degraded: bool
+) -> Result<String,HyraidError> {
+    if hyraid_json::read_arrays(&backend.state_file)?.iter().any(|x| x.name == name) {
+        return Err(HyraidError::Validation(format!("Array \"{}\" already exists",name)));
     }
+    check_no_journal(backend)?;
+    // fail before wiping any disk
+    find_raid_level(disks.len(),raid_level,policy)?;
+    let sizes: Vec<(&str,usize)> = disks
+        .iter()
+        .map(|disk| Ok((*disk,backend.disks.usable_space(disk)?)))
+        .collect::<Result<_,HyraidError>>()?;
+    check_redundancy(&plan_create(&sizes,raid_level,policy)?.redundancy,allow_degraded)?;
+    backend.cancel.check()?;
+
+    let mut journal = Journal {
+        name: name.to_string(),
+        operation: JournalOperation::Create {
+            disks: disks.iter().map(|x| x.to_string()).collect(),
+            raid_level,
+            policy,
+            allow_degraded
+        },
+        previous: None,
+        entry: None,
+        steps: wipe_steps(disks)
+    };
+    hyraid_json::create_journal(&backend.state_file,&journal)?;
+    run_journal(backend,&mut journal)?;
 
-    let slices = gen_slices(disks);
+    let disk_sizes = get_disk_sizes(backend,disks)?;
+    let slices = gen_slices(&disk_sizes)?;
     
-    let part_map = make_partition_map(disks,&slices);
-    let part_map = create_partition_map(part_map);
+    let array_uuid = Uuid::new_v4().hyphenated().to_string();
+    let part_map = make_partition_map(&disk_sizes,&slices)?;
+    journal.steps.extend(partition_steps(part_map,&array_uuid,0));
+    run_journal(backend,&mut journal)?;
+    let part_map = read_partition_map(backend,disks)?;
 
     let raid_map = init_raid_map(part_map.clone());
-    create_init_raid_map(raid_map.clone(),raid_level.clone());
+    journal.steps.extend(init_raid_map_steps(&raid_map,raid_level,policy)?);
     // Combine disks
-    let lvm_lv = create_lvm(&raid_map);
-
-    if Path::new(&lvm_lv).exists() {
-        let entry = HyraidArray {
-            name: name,
-            lvm_lv_path: lvm_lv.to_owned(),
-            raid_level, 
-            disks: disks
-                .iter()
-                .map(|&s| {
-                    let diskpath = std::path::Path::new(&s);
-                    let gptdisk = unwrap_or_exit!(
-                        gpt::GptConfig::new()
-                            .open(diskpath),
-                        "Failed to open disk."
-                    );
-                    hyraid_types::Disk::from(gptdisk,get_sector_size(
-                        diskpath
-                            .as_os_str()
-                            .to_str()
-                            .unwrap()
-                    ))
-                }).collect(),
-            raid_map: raid_map,
-            part_map: part_map,
-            slices,
-        };
-        hyraid_json::write_array(HYRAID_JSON_PATH,entry);
-    }
+    let volume_group = format!("hyraid_vg_{}",random_string(16));
+    journal.steps.extend(lvm_steps(&raid_map,&volume_group));
+    journal.steps.push(planned(JournalStep::WriteState));
+
+    let lvm_lv = format!("/dev/{}/lvol0",volume_group);
+    journal.entry = Some(HyraidArray {
+        name,
+        uuid: Some(array_uuid),
+        generation: 0,
+        lvm_lv_path: lvm_lv.to_owned(),
+        raid_level, 
+        policy,
+        disks: disks
+            .iter()
+            .map(|&s| {
+                Ok(hyraid_types::Disk {
+                    partitions: get_disk_partitions(backend,s)?
+                })
+            }).collect::<Result<_,HyraidError>>()?,
+        raid_map,
+        part_map,
+        slices,
+        spares: PartitionMap::new(),
+        scrub: None,
+        scrub_schedule: None,
+    });
+    run_journal(backend,&mut journal)?;
+    hyraid_json::remove_journal(&backend.state_file)?;
+
+    Ok(lvm_lv)
+}
 
-    lvm_lv
+/// Partitions of a disk, as they'd appear in a `RaidMap`
+fn get_disk_partitions(backend: &Backend, disk: &str) -> Result<Vec<DiskPartition>,HyraidError> {
+    Ok(into_disk_partitions(backend.disks.partitions(disk)?))
 }
 
-pub fn fail_from_hyraid_array(name: String, disks: &[&str]) {
+pub fn fail_from_hyraid_array(backend: &Backend, name: String, disks: &[&str]) -> Result<(),HyraidError> {
+    let entry = find_array(backend,&name)?;
     for disk in disks {
-        let mut partitions: Vec<DiskPartition> = vec![];
-        let gptdisk = unwrap_or_exit!(
-            gpt::GptConfig::new()
-                .open(disk),
-            "Failed to open disk."
-        );
-        for partition in gptdisk.partitions().values() {
-            partitions.push(DiskPartition::from(partition));
-        }
-        match hyraid_json::read_arrays(HYRAID_JSON_PATH).iter().find(|x| x.name == name) {
-            Some(entry) => {
-                for part in partitions.clone() {
-                    let raid_array = entry.raid_map
-                        .iter()
-                        .find(|x| x.1.contains(&part));
-                    if let Some(array) = raid_array {
-                        for partition in &partitions {
-                            if array.1.contains(&partition) {
-                                unwrap_or_exit_verbose!(
-                                    fail_from_raid_array(array.0,&[&partition.path.clone().unwrap().as_str()]),
-                                    "Failed to mark drive as faulty. mdadm output:"
-                                );
-                                println!("Marked disk(s) as faulty on array.");
-                            }
-                        }
+        let partitions = get_disk_partitions(backend,disk)?;
+        for part in &partitions {
+            let raid_array = entry.raid_map
+                .iter()
+                .find(|x| x.1.contains(part));
+            if let Some(array) = raid_array {
+                for partition in &partitions {
+                    if array.1.contains(partition) {
+                        fail_from_raid_array(backend.runner.as_ref(),array.0,&[partition.path.clone().unwrap_or_default().as_str()])
+                            ?;
                     }
-                } 
-            },
-            None => {
-                error_exit!("Error: No such HyRAID array.");
+                }
             }
-        }
+        } 
     }
+
+    Ok(())
 }
 
-pub fn add_disk_to_hyraid_array(name: String, disks: &[&str]) {
-    for disk in disks {
-        ensure_gpt(disk);
-        clear_partitions(disk);
-    }
-    match hyraid_json::read_arrays(HYRAID_JSON_PATH).iter().find(|x| x.name == name) {
-        Some(entry) => {
-            let raid_map_entry: RaidMap = entry.raid_map.to_owned();
-
-            // Re-compute the slices to account for larger disks being added
-            // since a larger disk means the current slices won't be enough
-            let slices = &recompute_slices(disks,&entry.slices);
-
-            let mut part_map = create_partition_map(make_partition_map(disks,slices));
-            part_map.extend(entry.part_map.to_owned());
-            
-            let (raid_map_create,raid_map_extend) = expand_raid_map(part_map,raid_map_entry);
-            
-            for (array,partitions) in raid_map_create {
-                let slice = into_paths_slice(partitions.to_vec());
-                let slice: Vec<&str> = slice.iter().map(
-                    |s| s.as_str()
-                ).collect();
-                let level = find_raid_level(slice.len(),entry.raid_level);
-                unwrap_or_exit_verbose!(
-                    create_raid_array(&array,&slice,level),
-                    "Failed to add disk to array. mdadm output:"
-                );
-                unwrap_or_exit_verbose!(
-                    lvm_pv_create(&[&array]),
-                    "Failed to add disk to array. LVM (lvresize) output:"
-                );
-                unwrap_or_exit_verbose!(
-                    lvm_vg_extend(entry.lvm_lv_path.trim_end_matches("/lvol0"),&[&array]),
-                    "Failed to add disk to array. LVM (lvresize) output:"
-                );
-            }
-            for (array,partitions) in raid_map_extend {
-                let slice = into_paths_slice(partitions.to_vec());
-                let slice: Vec<&str> = slice.iter().map(
-                    |s| s.as_str()
-                ).collect();
-                unwrap_or_exit_verbose!(
-                    add_to_raid_array(&array,&slice),
-                    "Failed to add disk to array. mdadm output:"
-                );
-                unwrap_or_exit_verbose!(
-                    lvm_pv_resize(&[&array]),
-                    "Failed to add disk to array. LVM (lvresize) output:"
-                );
-            }
+/// Adds disks to a HyRAID array, refusing a layout with less redundancy than
+/// the array asks for unless `allow_degraded` is set.
+pub fn add_disk_to_hyraid_array(backend: &Backend, name: String, disks: &[&str], allow_degraded: bool) -> Result<(),HyraidError> {
+    let mut entry = find_array(backend,&name)?;
+    check_no_journal(backend)?;
+
+    // fail before wiping any disk
+    let sizes: Vec<(&str,usize)> = disks
+        .iter()
+        .map(|disk| Ok((*disk,backend.disks.usable_space(disk)?)))
+        .collect::<Result<_,HyraidError>>()?;
+    check_redundancy(&plan_add(backend,name.to_string(),&sizes)?.redundancy,allow_degraded)?;
+    backend.cancel.check()?;
+
+    let mut journal = Journal {
+        name: name.to_string(),
+        operation: JournalOperation::Add {
+            disks: disks.iter().map(|x| x.to_string()).collect(),
+            allow_degraded
         },
-        None => {
-            error_exit!("Error: No such HyRAID array.");
-        }
+        previous: Some(entry.clone()),
+        entry: None,
+        steps: wipe_steps(disks)
+    };
+    hyraid_json::create_journal(&backend.state_file,&journal)?;
+    run_journal(backend,&mut journal)?;
+    let raid_map_entry: RaidMap = entry.raid_map.to_owned();
+
+    // Re-compute the slices to account for larger disks being added
+    // since a larger disk means the current slices won't be enough
+    let disk_sizes = get_disk_sizes(backend,disks)?;
+    let slices = &recompute_slices(&disk_sizes,&entry.slices);
+
+    let array_uuid = entry.uuid.clone().unwrap_or_else(|| Uuid::new_v4().hyphenated().to_string());
+    let generation = entry.generation + 1;
+    journal.steps.extend(partition_steps(make_partition_map(&disk_sizes,slices)?,&array_uuid,generation));
+    run_journal(backend,&mut journal)?;
+    let mut part_map = read_partition_map(backend,disks)?;
+    part_map.extend(entry.part_map.to_owned());
+    
+    let (raid_map_create,raid_map_extend) = expand_raid_map(part_map.clone(),raid_map_entry);
+
+    // the new layout, recorded once every md device is in place
+    for (array,partitions) in raid_map_create.iter().chain(raid_map_extend.iter()) {
+        entry.raid_map.entry(array.to_string()).or_default().extend(partitions.to_vec());
+    }
+    for disk in disks {
+        entry.disks.push(hyraid_types::Disk {
+            partitions: get_disk_partitions(backend,disk)?
+        });
+    }
+    entry.part_map = part_map;
+    entry.slices = slices.to_vec();
+    entry.uuid = Some(array_uuid);
+    entry.generation = generation;
+    
+    let volume_group = entry.lvm_lv_path.trim_end_matches("/lvol0");
+    for step in init_raid_map_steps(&raid_map_create,entry.raid_level,entry.policy)? {
+        let JournalStep::CreateMd { device,.. } = &step.step else { unreachable!("only creates md devices") };
+        let devices = vec![device.to_string()];
+        journal.steps.extend([
+            step,
+            planned(JournalStep::CreatePv { devices: devices.to_vec() }),
+            planned(JournalStep::ExtendVg { volume_group: volume_group.to_string(), devices }),
+        ]);
+    }
+    let mut raid_map_extend: Vec<(String,Vec<DiskPartition>)> = raid_map_extend.into_iter().collect();
+    raid_map_extend.sort_by(|(a,_),(b,_)| a.cmp(b));
+    for (array,partitions) in raid_map_extend {
+        let slice = into_paths_slice(partitions.to_vec());
+        // the layout above already counts the new partitions
+        let members = entry.raid_map[&array].len() - slice.len();
+        journal.steps.push(planned(JournalStep::GrowMd { device: array, members, partitions: slice }));
     }
+    journal.steps.push(planned(JournalStep::WriteState));
+
+    journal.entry = Some(entry);
+    run_journal(backend,&mut journal)?;
+    hyraid_json::remove_journal(&backend.state_file)
 }
 
-pub fn remove_disk_from_array(name: String, disks: &[&str]) {
+pub fn remove_disk_from_array(backend: &Backend, name: String, disks: &[&str]) -> Result<(),HyraidError> {
+    let entry = find_array(backend,&name)?;
     for disk in disks {
-        let mut partitions: Vec<DiskPartition> = vec![];
-        let gptdisk = unwrap_or_exit!(
-            gpt::GptConfig::new()
-                .open(disk),
-            "Failed to open disk."
-        );
-        for partition in gptdisk.partitions().values() {
-            partitions.push(DiskPartition::from(partition));
-        }
-        match hyraid_json::read_arrays(HYRAID_JSON_PATH).iter().find(|x| x.name == name) {
-            Some(entry) => {
-                for part in partitions.to_owned() {
-                    let raid_array = entry.raid_map
-                        .iter()
-                        .find(|x| x.1.contains(&part));
-                    if let Some(array) = raid_array {
-                        for partition in &partitions {
-                            if array.1.contains(&partition) {
-                                unwrap_or_exit_verbose!(
-                                    remove_from_raid_array(array.0,&[&partition.path.clone().unwrap().as_str()]),
-                                    "Failed to remove disk(s). mdadm output:"
-                                );
-                                println!("Removed disk(s) from array.");
-                            }
-                        }
+        let partitions = get_disk_partitions(backend,disk)?;
+        for part in &partitions {
+            let raid_array = entry.raid_map
+                .iter()
+                .find(|x| x.1.contains(part));
+            if let Some(array) = raid_array {
+                for partition in &partitions {
+                    if array.1.contains(partition) {
+                        remove_from_raid_array(backend.runner.as_ref(),array.0,&[partition.path.clone().unwrap_or_default().as_str()])
+                            ?;
                     }
-                } 
-            },
-            None => {
-                error_exit!("Error: No such HyRAID array.");
+                }
             }
+        } 
+    }
+
+    Ok(())
+}
+
+/// Tears down an array: removes its logical volume and volume group,
+/// stops its md devices, erases their superblocks, deletes its partitions
+/// and finally its entry in the state file.
+///
+/// Pieces that no longer exist are skipped, so half torn down arrays
+/// can still be cleaned up.
+pub fn destroy_hyraid_array(backend: &Backend, name: String) -> Result<(),HyraidError> {
+    let entry = find_array(backend,&name)?;
+    let runner = backend.runner.as_ref();
+
+    if backend.disks.is_mounted(&entry.lvm_lv_path)? {
+        return Err(HyraidError::Validation(format!(
+            "Logical volume {} of array {} is mounted, unmount it first",
+            entry.lvm_lv_path,
+            name
+        )));
+    }
+
+    let mut md_devices: Vec<&String> = entry.raid_map.keys().collect();
+    md_devices.sort();
+    let md_devices: Vec<&str> = md_devices
+        .into_iter()
+        .map(|x| x.as_str())
+        .filter(|x| backend.disks.device_exists(x))
+        .collect();
+
+    if backend.disks.device_exists(&entry.lvm_lv_path) {
+        lvm_lv_deactivate(runner,&entry.lvm_lv_path)?;
+        lvm_lv_remove(runner,&entry.lvm_lv_path)?;
+        lvm_vg_remove(runner,entry.lvm_lv_path.trim_end_matches("/lvol0"))?;
+        lvm_pv_remove(runner,&md_devices)?;
+    }
+
+    for device in &md_devices {
+        stop_raid_array(runner,device)?;
+        let slice = into_paths_slice(entry.raid_map[*device].to_vec());
+        let slice: Vec<&str> = slice.iter().map(
+            |s| s.as_str()
+        ).collect();
+        zero_superblock(runner,&slice)?;
+    }
+    let spares = attached_spares(&entry);
+    let spares: Vec<&str> = spares.iter().map(|(_,path)| path.as_str()).collect();
+    if !spares.is_empty() {
+        zero_superblock(runner,&spares)?;
+    }
+
+    let mut disks: Vec<&String> = entry.part_map.keys().chain(entry.spares.keys()).collect();
+    disks.sort();
+    for disk in disks {
+        let partitions = entry.part_map.get(disk).or(entry.spares.get(disk)).cloned().unwrap_or_default();
+        let slice = into_paths_slice(partitions);
+        let slice: Vec<&str> = slice.iter().map(
+            |s| s.as_str()
+        ).collect();
+        backend.disks.remove_partitions(disk,&slice)?;
+    }
+
+    hyraid_json::remove_array(&backend.state_file,&name)
+}
+
+/// Lists every HyRAID array in the state file
+pub fn list_hyraid_arrays(backend: &Backend) -> Result<Vec<HyraidArray>,HyraidError> {
+    hyraid_json::read_arrays(&backend.state_file)
+}
+
+/// Summarises the state of an md device in /proc/mdstat
+fn md_health(array: &MdArray) -> MdHealth {
+    if array.state == ArrayState::Inactive {
+        return MdHealth::Inactive;
+    }
+    if let Some(progress) = &array.progress {
+        return MdHealth::Syncing {
+            action: progress.action.to_string(),
+            percent: progress.percent,
+            finish: progress.finish.clone()
+        };
+    }
+    if array.is_degraded() {
+        MdHealth::Degraded
+    } else {
+        MdHealth::Clean
+    }
+}
+
+/// Shows the live state of every md device of an array,
+/// its disks and its logical volume. The RAID level is the one in /proc/mdstat,
+/// or the one the state file implies for an md device that isn't assembled.
+pub fn hyraid_array_status(backend: &Backend, name: String) -> Result<ArrayStatus,HyraidError> {
+    let entry = find_array(backend,&name)?;
+    let mdstat: HashMap<String,MdArray> = read_mdstat(&backend.mdstat_file)?
+        .arrays
+        .into_iter()
+        .map(|x| (x.name.to_string(),x))
+        .collect();
+
+    let mut md_devices: Vec<MdDeviceStatus> = entry.raid_map
+        .iter()
+        .map(|(device,members)| {
+            let kernel_name = backend.disks.kernel_name(device);
+            let array = kernel_name.as_ref().and_then(|x| mdstat.get(x));
+            // the level the md device would have going by the state file, if it isn't running
+            let raid_level = match array.and_then(|x| x.level.as_ref()?.strip_prefix("raid")?.parse().ok()) {
+                Some(raid_level) => raid_level,
+                None => find_raid_level(members.len(),entry.raid_level,entry.policy)?
+            };
+            Ok(MdDeviceStatus {
+                device: device.to_string(),
+                kernel_name,
+                raid_level,
+                health: array.map(md_health).unwrap_or(MdHealth::Missing),
+                members: members.to_vec()
+            })
+        })
+        .collect::<Result<_,HyraidError>>()?;
+    md_devices.sort_by(|a,b| a.device.cmp(&b.device));
+
+    let raw_capacity: usize = entry.part_map
+        .values()
+        .flatten()
+        .map(|x| x.size)
+        .sum();
+    let usable_capacity: usize = md_devices
+        .iter()
+        .map(|md| {
+            let min_size = md.members.iter().map(|x| x.size).min().unwrap_or(0);
+            min_size * data_members(md.raid_level,md.members.len())
+        })
+        .sum();
+
+    Ok(ArrayStatus {
+        name: entry.name,
+        lv_size: lvm_lv_size(backend.runner.as_ref(),&entry.lvm_lv_path).ok(),
+        lvm_lv_path: entry.lvm_lv_path,
+        raw_capacity,
+        usable_capacity,
+        md_devices,
+        part_map: entry.part_map
+    })
+}
+
+/// md device found by a scan
+struct ScannedMd {
+    device: String,
+    raid_level: usize,
+    /// Disk and partition of every member
+    members: Vec<(String,DiskPartition)>,
+}
+
+/// Intended RAID level of an array going by the levels of its md devices.
+///
+/// Groups too small for RAID5/6 fall back to RAID1, so the highest level wins.
+fn intended_raid_level(levels: &[usize]) -> usize {
+    [6,5,1,0]
+        .into_iter()
+        .find(|x| levels.contains(x))
+        .unwrap_or(0)
+}
+
+/// Redundancy policy of an array going by its md devices.
+///
+/// Only the two-disk policy makes RAID1 devices of three or more members next to RAID6,
+/// or instead of the 2-way RAID1 fallback. RAID level 6 mirrors slices of three disks
+/// the same way, such arrays are taken to have the stricter two-disk policy.
+fn intended_policy(mds: &[ScannedMd]) -> RedundancyPolicy {
+    let wide_mirror = |x: &ScannedMd| x.raid_level == 1 && x.members.len() >= 3;
+    let two_disk = mds.iter().any(wide_mirror)
+        && mds.iter().all(|x| x.raid_level == 6 || wide_mirror(x));
+    if two_disk {
+        RedundancyPolicy::TwoDisk
+    } else {
+        RedundancyPolicy::RaidLevel
+    }
+}
+
+/// Rebuilds HyRAID arrays from the metadata on the disks, without writing anything.
+///
+/// HyRAID partitions are found by their GPT type or name and grouped into md devices
+/// by their superblock, and md devices into arrays by their LVM volume group.
+/// Arrays are named after their volume group, since the name isn't stored on disk.
+/// The md devices have to be assembled for their volume group to be read.
+pub fn scan_hyraid_arrays(backend: &Backend) -> Result<ScanReport,HyraidError> {
+    let runner = backend.runner.as_ref();
+
+    // disks without a readable GPT are skipped
+    let mut disk_partitions: Vec<(String,Vec<GptPartition>)> = vec![];
+    for disk in backend.disks.list_disks()? {
+        let Ok(partitions) = backend.disks.partitions(&disk) else { continue };
+        let partitions: Vec<GptPartition> = partitions
+            .into_iter()
+            .filter(|x| x.is_hyraid())
+            .collect();
+        if !partitions.is_empty() {
+            disk_partitions.push((disk,partitions));
+        }
+    }
+
+    let mut md_devices: HashMap<String,ScannedMd> = HashMap::new();
+    let mut unassigned: Vec<(String,String)> = vec![];
+    for (disk,partitions) in &disk_partitions {
+        for partition in partitions {
+            let superblock = examine_partition(runner,&partition.path).ok();
+            let Some((uuid,md_name,level)) = superblock.and_then(|x| Some((x.uuid?,x.name?,x.level))) else {
+                unassigned.push((disk.to_string(),partition.path.to_string()));
+                continue;
+            };
+
+            // md names are stored as `host:name`
+            let md_name = md_name.rsplit(':').next().unwrap_or_default().to_string();
+            let md = md_devices.entry(uuid).or_insert(ScannedMd {
+                device: format!("/dev/md/{}",md_name),
+                raid_level: level
+                    .and_then(|x| x.strip_prefix("raid").and_then(|x| x.parse().ok()))
+                    .unwrap_or(0),
+                members: vec![]
+            });
+            md.members.push((disk.to_string(),DiskPartition {
+                path: Some(partition.path.to_string()),
+                size: partition.size
+            }));
+        }
+    }
+    let mut md_devices: Vec<ScannedMd> = md_devices.into_values().collect();
+    md_devices.sort_by(|a,b| a.device.cmp(&b.device));
+
+    let mut volume_groups: HashMap<String,Vec<ScannedMd>> = HashMap::new();
+    let mut orphan_md_devices: Vec<String> = vec![];
+    for md in md_devices {
+        match lvm_pv_volume_group(runner,&md.device) {
+            Ok(Some(volume_group)) => volume_groups.entry(volume_group).or_default().push(md),
+            _ => orphan_md_devices.push(md.device)
         }
     }
-}
\ No newline at end of file
+    let mut volume_groups: Vec<(String,Vec<ScannedMd>)> = volume_groups.into_iter().collect();
+    volume_groups.sort_by(|(a,_),(b,_)| a.cmp(b));
+
+    let mut arrays: Vec<HyraidArray> = vec![];
+    for (volume_group,mds) in volume_groups {
+        let array_disks: Vec<&(String,Vec<GptPartition>)> = disk_partitions
+            .iter()
+            .filter(|(disk,_)| mds.iter().any(|md| md.members.iter().any(|(x,_)| x == disk)))
+            .collect();
+
+        let raid_map: RaidMap = mds
+            .iter()
+            .map(|md| (md.device.to_string(),md.members.iter().map(|(_,x)| x.clone()).collect()))
+            .collect();
+        let part_map: PartitionMap = array_disks
+            .iter()
+            .map(|(disk,partitions)| {
+                let mut partitions = into_disk_partitions(partitions.to_vec());
+                partitions.sort_by_key(|k| k.size);
+                (disk.to_string(),partitions)
+            })
+            .collect();
+        // partitions are laid out in slice order, so the disk with the most
+        // of them has one partition per slice
+        let slices: PartitionSlices = array_disks
+            .iter()
+            .max_by_key(|(_,partitions)| partitions.len())
+            .map(|(_,partitions)| partitions.iter().map(|x| x.size).collect())
+            .unwrap_or_default();
+        let levels: Vec<usize> = mds.iter().map(|x| x.raid_level).collect();
+        let policy = intended_policy(&mds);
+        let identities: Vec<PartitionIdentity> = array_disks
+            .iter()
+            .flat_map(|(_,partitions)| partitions.iter().filter_map(|x| x.identity()))
+            .collect();
+
+        arrays.push(HyraidArray {
+            name: volume_group.to_string(),
+            uuid: identities.first().map(|x| x.array_uuid.to_string()),
+            generation: identities.iter().map(|x| x.generation).max().unwrap_or(0),
+            lvm_lv_path: format!("/dev/{}/lvol0",volume_group),
+            // the two-disk policy is only valid with RAID level 6
+            raid_level: if policy == RedundancyPolicy::TwoDisk { 6 } else { intended_raid_level(&levels) },
+            policy,
+            disks: array_disks
+                .iter()
+                .map(|(_,partitions)| hyraid_types::Disk {
+                    partitions: into_disk_partitions(partitions.to_vec())
+                })
+                .collect(),
+            raid_map,
+            slices,
+            part_map,
+            spares: PartitionMap::new(),
+            scrub: None,
+            scrub_schedule: None
+        });
+    }
+
+    let orphan_partitions: Vec<String> = unassigned
+        .into_iter()
+        .filter(|(disk,_)| !arrays.iter().any(|x| x.part_map.contains_key(disk)))
+        .map(|(_,partition)| partition)
+        .collect();
+
+    Ok(ScanReport {
+        arrays,
+        orphan_md_devices,
+        orphan_partitions
+    })
+}
+
+/// Writes the arrays found by a scan to the state file,
+/// skipping those whose logical volume is already in it.
+///
+/// With `volume_group` only the array of that volume group is imported,
+/// under `name` if given.
+pub fn import_hyraid_arrays(backend: &Backend, volume_group: Option<&str>, name: Option<String>) -> Result<Vec<HyraidArray>,HyraidError> {
+    if name.is_some() && volume_group.is_none() {
+        return Err(HyraidError::Validation("An array name can only be given along with a volume group".to_string()));
+    }
+
+    let known: Vec<String> = hyraid_json::read_arrays(&backend.state_file)?
+        .into_iter()
+        .map(|x| x.lvm_lv_path)
+        .collect();
+    let mut arrays: Vec<HyraidArray> = scan_hyraid_arrays(backend)?
+        .arrays
+        .into_iter()
+        .filter(|x| volume_group.is_none_or(|volume_group| x.name == volume_group))
+        .filter(|x| !known.contains(&x.lvm_lv_path))
+        .collect();
+
+    if let Some(volume_group) = volume_group && arrays.is_empty() {
+        return Err(HyraidError::Validation(format!("No HyRAID array to import in volume group {}",volume_group)));
+    }
+    if let Some(name) = name {
+        arrays[0].name = name;
+    }
+
+    for array in &arrays {
+        hyraid_json::write_array(&backend.state_file,array.clone())?;
+    }
+
+    Ok(arrays)
+}
+
+/// Reads the md superblock of the first member of an md device that has one
+fn examine_md_device(backend: &Backend, device: &str, members: &[DiskPartition]) -> Result<MdDetail,HyraidError> {
+    let runner = backend.runner.as_ref();
+    let mut last_err = HyraidError::Validation(format!("md device {} has no members",device));
+    for path in into_paths_slice(members.to_vec()) {
+        match examine_partition(runner,&path) {
+            Ok(detail) if detail.uuid.is_some() => return Ok(detail),
+            Ok(_) => last_err = HyraidError::Validation(format!("No md superblock on {}",path)),
+            Err(err) => last_err = err
+        }
+    }
+    Err(last_err)
+}
+
+/// Waits for a device to show up, giving up after 10 seconds
+fn wait_for_device(backend: &Backend, device: &str) -> Result<(),HyraidError> {
+    for _ in 0..100 {
+        if backend.disks.device_exists(device) {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Err(HyraidError::System(format!("Timed out waiting for {}",device)))
+}
+
+/// Assembles every md device of an array by its UUID, so they come back
+/// under the names in the state file, then activates the volume group.
+///
+/// md devices already running are left alone. Returns the logical volume.
+pub fn assemble_hyraid_array(backend: &Backend, name: String) -> Result<String,HyraidError> {
+    let entry = find_array(backend,&name)?;
+    let runner = backend.runner.as_ref();
+
+    // without /proc/mdstat the md module isn't loaded yet, so nothing is running
+    let running: Vec<String> = read_mdstat(&backend.mdstat_file)
+        .map(|x| x.arrays.into_iter().map(|x| x.name).collect())
+        .unwrap_or_default();
+
+    let mut md_devices: Vec<&String> = entry.raid_map.keys().collect();
+    md_devices.sort();
+    for device in md_devices {
+        let kernel_name = backend.disks.kernel_name(device);
+        if kernel_name.is_some_and(|x| running.contains(&x)) {
+            continue;
+        }
+
+        let members = &entry.raid_map[device];
+        let uuid = examine_md_device(backend,device,members)?
+            .uuid
+            .unwrap_or_default();
+        let slice = into_paths_slice(members.to_vec());
+        let slice: Vec<&str> = slice.iter().map(
+            |s| s.as_str()
+        ).collect();
+        assemble_raid_array(runner,device,&uuid,&slice)?;
+        wait_for_device(backend,device)?;
+    }
+
+    lvm_vg_activate(runner,entry.lvm_lv_path.trim_end_matches("/lvol0"))?;
+    wait_for_device(backend,&entry.lvm_lv_path)?;
+
+    Ok(entry.lvm_lv_path)
+}
+
+/// `ARRAY` lines for mdadm.conf pinning the md devices of every array,
+/// or of the named one, to their names in the state file
+pub fn generate_mdadm_conf(backend: &Backend, name: Option<String>) -> Result<String,HyraidError> {
+    let arrays = match name {
+        Some(name) => vec![find_array(backend,&name)?],
+        None => hyraid_json::read_arrays(&backend.state_file)?
+    };
+
+    let mut lines: Vec<String> = vec![];
+    for array in arrays {
+        lines.push(format!("# HyRAID array {}",array.name));
+        let mut md_devices: Vec<&String> = array.raid_map.keys().collect();
+        md_devices.sort();
+        for device in md_devices {
+            let detail = examine_md_device(backend,device,&array.raid_map[device])?;
+            let mut line = format!("ARRAY {}",device);
+            if let Some(metadata) = detail.metadata {
+                line += &format!(" metadata={}",metadata);
+            }
+            line += &format!(" UUID={}",detail.uuid.unwrap_or_default());
+            if let Some(md_name) = detail.name {
+                line += &format!(" name={}",md_name);
+            }
+            lines.push(line);
+        }
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Slice index of every partition of an array, by path.
+///
+/// The partitions of a disk hold a prefix of the slices, so pairing them
+/// with those slices by size gives the slice of each.
+fn slice_indexes(entry: &HyraidArray) -> HashMap<String,usize> {
+    let mut result = HashMap::new();
+    for partitions in entry.part_map.values() {
+        let mut slices: Vec<(usize,usize)> = entry.slices
+            .iter()
+            .copied()
+            .enumerate()
+            .take(partitions.len())
+            .collect();
+        slices.sort_by_key(|(_,size)| *size);
+        let mut partitions: Vec<&DiskPartition> = partitions.iter().collect();
+        partitions.sort_by_key(|x| x.size);
+
+        for ((slice,_),partition) in slices.into_iter().zip(partitions) {
+            if let Some(path) = &partition.path {
+                result.insert(path.to_string(),slice);
+            }
+        }
+    }
+    result
+}
+
+/// Slice of every partition of the array, taking those of `disk` from the
+/// identity HyRAID stored in their GPT entries.
+///
+/// Partitions created before the identity was stored, and those of a disk
+/// that's gone, keep the slice `slice_indexes` infers from their size.
+fn disk_slice_indexes(backend: &Backend, entry: &HyraidArray, disk: &str) -> HashMap<String,usize> {
+    let mut result = slice_indexes(entry);
+    for partition in backend.disks.partitions(disk).unwrap_or_default() {
+        if let Some(identity) = partition.identity() && entry.uuid.as_ref() == Some(&identity.array_uuid) {
+            result.insert(partition.path,identity.slice);
+        }
+    }
+    result
+}
+
+/// md device of every slice that has one
+fn slice_md_devices(entry: &HyraidArray) -> HashMap<usize,String> {
+    let slices = slice_indexes(entry);
+    entry.raid_map
+        .iter()
+        .filter_map(|(device,members)| {
+            let slice = members.iter().find_map(|x| slices.get(x.path.as_ref()?))?;
+            Some((*slice,device.to_string()))
+        })
+        .collect()
+}
+
+/// Whether an md device is recovering, resyncing or reshaping
+fn md_busy(array: &MdArray) -> bool {
+    array.progress.is_some() || array.members.iter().any(|x| x.role == MemberRole::Rebuilding)
+}
+
+/// Waits until none of the md devices is busy, polling /proc/mdstat
+/// and passing the progress of every one that is to `backend.progress`.
+///
+/// Returns `HyraidError::Cancelled` once cancelled, md carries on in the background.
+fn wait_for_recovery(backend: &Backend, devices: &[&str]) -> Result<(),HyraidError> {
+    let kernel_names: Vec<String> = devices
+        .iter()
+        .filter_map(|x| backend.disks.kernel_name(x))
+        .collect();
+    loop {
+        // give md a moment to start recovering onto new members
+        thread::sleep(Duration::from_secs(1));
+        let mdstat = read_mdstat(&backend.mdstat_file)?;
+        let busy: Vec<&MdArray> = mdstat
+            .arrays
+            .iter()
+            .filter(|x| kernel_names.contains(&x.name) && md_busy(x))
+            .collect();
+        if busy.is_empty() {
+            return Ok(());
+        }
+        for array in busy {
+            if let Some(progress) = &array.progress {
+                (backend.progress)(&array.name,progress);
+            }
+        }
+        backend.cancel.check()?;
+        thread::sleep(Duration::from_secs(4));
+    }
+}
+
+/// Steps taking an md device from one RAID level and number of members to another.
+///
+/// md only converts 2-device RAID1 to RAID5, and RAID5 to RAID6 by adding a device,
+/// so a wider mirror migrated to RAID5 or RAID6 survives a single disk failure part way.
+fn migration_steps(
+    level: usize,
+    members: usize,
+    target_level: usize,
+    target_members: usize
+) -> Result<Vec<MigrationStep>,HyraidError> {
+    let mut steps = vec![];
+    match (level,target_level) {
+        (level,target_level) if level == target_level => {},
+        (1,5) | (1,6) => {
+            if members > 2 {
+                steps.push((None,2));
+            }
+            steps.push((Some(5),2));
+        },
+        (5,6) => {},
+        _ => {
+            return Err(HyraidError::Validation(format!(
+                "Can't migrate RAID{} to RAID{}",
+                level,
+                target_level
+            )));
+        }
+    }
+
+    if target_level == 6 && level != 6 {
+        steps.push((None,target_members - 1));
+        steps.push((Some(6),target_members));
+    } else {
+        steps.push((None,target_members));
+    }
+    // reshaping to the number of devices it already has is a no-op md refuses
+    let mut current = members;
+    steps.retain(|(level,devices)| {
+        let keep = level.is_some() || *devices != current;
+        current = *devices;
+        keep
+    });
+
+    let mut current_level = level;
+    Ok(steps
+        .into_iter()
+        .map(|(raid_level,raid_devices)| {
+            current_level = raid_level.unwrap_or(current_level);
+            MigrationStep {
+                raid_level,
+                raid_devices,
+                failures_tolerated: failures_tolerated(current_level,raid_devices)
+            }
+        })
+        .collect())
+}
+
+/// Adds partitions to an md device of `members` members and reshapes it to use them,
+/// migrating it to the RAID level a new md device of that many members would get.
+///
+/// Refuses a migration surviving fewer disk failures part way than the array asks for,
+/// unless `allow_degraded` is set.
+/// The physical volume on it is only resized once every reshape has finished.
+fn grow_md_device(
+    backend: &Backend,
+    entry: &HyraidArray,
+    device: &str,
+    members: usize,
+    partitions: &[&str],
+    allow_degraded: bool
+) -> Result<(),HyraidError> {
+    let runner = backend.runner.as_ref();
+    let target_members = members + partitions.len();
+    let target_level = find_raid_level(target_members,entry.raid_level,entry.policy)?;
+    let steps = migration_steps(
+        find_raid_level(members,entry.raid_level,entry.policy)?,
+        members,
+        target_level,
+        target_members
+    )?;
+
+    let target = target_failures(entry.raid_level,entry.policy);
+    if !allow_degraded && let Some(step) = steps.iter().find(|x| x.failures_tolerated < target) {
+        return Err(HyraidError::Validation(format!(
+            "Migrating {} to RAID{} only survives {} disk failure(s) part way, {} are asked for. Pass --allow-degraded-layout to do it anyway",
+            device,
+            target_level,
+            step.failures_tolerated,
+            target
+        )));
+    }
+
+    // new partitions join as spares until the reshape takes them in
+    add_to_raid_array(runner,device,partitions)?;
+    for step in steps {
+        match step.raid_level {
+            Some(raid_level) => migrate_raid_array(runner,device,raid_level,step.raid_devices)?,
+            None => reshape_raid_array(runner,device,step.raid_devices)?
+        }
+        wait_for_recovery(backend,&[device])?;
+    }
+    lvm_pv_resize(runner,&[device])
+}
+
+/// Replaces a disk of an array with a new one, which must be at least as large.
+///
+/// The new disk gets partitions for the slices of the old one. Each is copied
+/// onto with `mdadm --replace`, or recovered onto if the old member is already
+/// faulty or gone, and once every md device has recovered the old partitions
+/// are removed. Space the larger new disk frees up is then put to use by
+/// [`grow_hyraid_array`], whose layout is checked against the redundancy the
+/// array asks for before the new disk is touched, unless `allow_degraded` is set.
+///
+/// If md refuses to copy onto a new partition while the old member is still
+/// in use, the partition is taken out again and the error returned, leaving
+/// the old member in place.
+pub fn replace_disk(backend: &Backend, name: String, old: &str, new: &str, allow_degraded: bool) -> Result<(),HyraidError> {
+    let mut entry = find_array(backend,&name)?;
+    let runner = backend.runner.as_ref();
+
+    let old_partitions = entry.part_map
+        .get(old)
+        .cloned()
+        .ok_or(HyraidError::Validation(format!("{} is not a disk of array {}",old,name)))?;
+    if entry.part_map.contains_key(new) {
+        return Err(HyraidError::Validation(format!("{} is already a disk of array {}",new,name)));
+    }
+    let old_size: usize = old_partitions.iter().map(|x| x.size).sum();
+    if backend.disks.usable_space(new)? < old_size {
+        return Err(HyraidError::Validation(format!("{} is smaller than {}",new,old)));
+    }
+    let slices = disk_slice_indexes(backend,&entry,old);
+
+    // fail before touching the new disk if growing into it afterwards would be refused
+    let mut replaced_entry = entry.clone();
+    let planned: Vec<DiskPartition> = old_partitions
+        .iter()
+        .map(|x| {
+            let slice = x.path.as_ref().and_then(|x| slices.get(x)).copied().unwrap_or_default();
+            let partition = DiskPartition {
+                path: Some(format!("{} (new partition {})",new,slice + 1)),
+                size: x.size
+            };
+            for members in replaced_entry.raid_map.values_mut() {
+                members.iter_mut().filter(|member| *member == x).for_each(|member| *member = partition.clone());
+            }
+            partition
+        })
+        .collect();
+    replaced_entry.part_map.remove(old);
+    replaced_entry.part_map.insert(new.to_string(),planned);
+    let mut totals = disk_totals(backend,&entry);
+    totals.retain(|(disk,_)| disk != old);
+    totals.push((new.to_string(),backend.disks.usable_space(new)?));
+    check_grow_redundancy(&replaced_entry,&totals,allow_degraded)?;
+    backend.cancel.check()?;
+
+    backend.disks.ensure_gpt(backend.runner.as_ref(),new)?;
+    backend.disks.clear_partitions(new)?;
+
+    let slice_md = slice_md_devices(&entry);
+    let array_uuid = entry.uuid.clone().unwrap_or_else(|| Uuid::new_v4().hyphenated().to_string());
+    let generation = entry.generation + 1;
+    let new_partitions = into_disk_partitions(
+        backend.disks.add_partitions(new,&entry.slices[..old_partitions.len()],&PartitionIdentity {
+            array_uuid: array_uuid.to_string(),
+            slice: 0,
+            generation
+        })?
+    );
+
+    // md device, old member and new member
+    let mut replaced: Vec<(String,DiskPartition,DiskPartition)> = vec![];
+    for (slice,partition) in new_partitions.iter().enumerate() {
+        let Some(device) = slice_md.get(&slice) else { continue };
+        // slices the old disk didn't have are grown into the md device afterwards
+        let Some(old_partition) = old_partitions
+            .iter()
+            .find(|x| x.path.as_ref().and_then(|x| slices.get(x)) == Some(&slice) && entry.raid_map[device].contains(x))
+        else { continue };
+        let path = partition.path.clone().unwrap_or_default();
+        add_to_raid_array(runner,device,&[&path])?;
+
+        let old_path = old_partition.path.clone().unwrap_or_default();
+        // a disk that's already gone leaves its slot to the new member
+        if backend.disks.device_exists(&old_path)
+            && let Err(err) = replace_in_raid_array(runner,device,&old_path,&path) {
+            match member_role(backend,device,&old_path)? {
+                Some(MemberRole::Faulty) => fail_from_raid_array(runner,device,&[&old_path])?,
+                None => {},
+                Some(_) => {
+                    remove_from_raid_array(runner,device,&[&path])?;
+                    return Err(err);
+                }
+            }
+        }
+        replaced.push((device.to_string(),old_partition.clone(),partition.clone()));
+    }
+
+    let devices: Vec<&str> = replaced.iter().map(|(device,_,_)| device.as_str()).collect();
+    wait_for_recovery(backend,&devices)?;
+
+    for (device,old_partition,partition) in replaced {
+        let old_path = old_partition.path.clone().unwrap_or_default();
+        if backend.disks.device_exists(&old_path) {
+            remove_from_raid_array(runner,&device,&[&old_path])?;
+        }
+        let members = entry.raid_map.entry(device.to_string()).or_default();
+        members.retain(|x| *x != old_partition);
+        members.push(partition);
+    }
+
+    entry.disks.retain(|disk| !disk.partitions.iter().any(|x| old_partitions.contains(x)));
+    entry.disks.push(hyraid_types::Disk { partitions: new_partitions.to_vec() });
+    let mut new_partitions = new_partitions;
+    new_partitions.sort_by_key(|k| k.size);
+    entry.part_map.remove(old);
+    entry.part_map.insert(new.to_string(),new_partitions);
+    entry.uuid = Some(array_uuid);
+    entry.generation = generation;
+
+    write_layout(backend,&name,&entry)?;
+
+    // the new disk may leave room for slices no md device holds yet,
+    // cancelling from here on keeps the replacement
+    grow_hyraid_array(backend,name,allow_degraded)?;
+    Ok(())
+}
+
+/// Size every disk of an array could hold, its partitions and the free space after them
+fn disk_totals(backend: &Backend, entry: &HyraidArray) -> Vec<(String,usize)> {
+    let mut disks: Vec<(String,usize)> = entry.part_map
+        .iter()
+        .map(|(disk,partitions)| {
+            let used: usize = partitions.iter().map(|x| x.size).sum();
+            // a disk without free space has no free sectors at all
+            let free = backend.disks.free_space(disk).unwrap_or(0);
+            let free = if free < MIN_FREE_SPACE { 0 } else { free };
+            (disk.to_string(),used + free)
+        })
+        .collect();
+    disks.sort();
+    disks
+}
+
+/// Partitions of every slice that aren't in an md device yet, paired with the
+/// md device of the slice they join, or `None` for the members of a new md device
+/// once there are two of them.
+fn grow_groups(entry: &HyraidArray, slice_md: &HashMap<usize,String>) -> Vec<(Option<String>,Vec<DiskPartition>)> {
+    let slice_of = slice_indexes(entry);
+    // the new partitions and those left by replace
+    let mut grown: Vec<usize> = entry.part_map
+        .values()
+        .flatten()
+        .filter(|x| !entry.raid_map.values().any(|members| members.contains(x)))
+        .filter_map(|x| x.path.as_ref().and_then(|x| slice_of.get(x)))
+        .copied()
+        .collect();
+    grown.sort();
+    grown.dedup();
+
+    grown
+        .into_iter()
+        .filter_map(|slice| {
+            let members: Vec<DiskPartition> = entry.part_map
+                .values()
+                .flatten()
+                .filter(|x| x.path.as_ref().and_then(|x| slice_of.get(x)) == Some(&slice))
+                .cloned()
+                .collect();
+            match slice_md.get(&slice) {
+                Some(device) => {
+                    let added = members.into_iter().filter(|x| !entry.raid_map[device].contains(x)).collect();
+                    Some((Some(device.to_string()),added))
+                },
+                // a slice only one disk has leaves its partition unused
+                None if members.len() >= 2 => Some((None,members)),
+                None => None
+            }
+        })
+        .collect()
+}
+
+/// Slices of an array whose disks could hold `totals`, and every disk getting
+/// partitions for more of them with its number of slices now and once grown
+fn grow_targets(entry: &HyraidArray, totals: &[(String,usize)]) -> (Vec<usize>,Vec<(String,usize,usize)>) {
+    let disk_sizes: Vec<(&str,usize)> = totals.iter().map(|(disk,size)| (disk.as_str(),*size)).collect();
+    let slices = recompute_slices(&disk_sizes,&entry.slices);
+    let targets = totals
+        .iter()
+        .map(|(disk,size)| {
+            let target = (0..=slices.len())
+                .take_while(|x| slices[..*x].iter().sum::<usize>() <= *size)
+                .last()
+                .unwrap_or(0);
+            (disk.to_string(),entry.part_map[disk].len(),target)
+        })
+        .filter(|(_,current,target)| target > current)
+        .collect();
+    (slices,targets)
+}
+
+/// Refuses growing an array into `totals` if its md devices would survive fewer
+/// disk failures than it asks for, also part way through migrating them, unless
+/// `allow_degraded` is set. md devices the growth leaves alone aren't looked at.
+fn check_grow_redundancy(entry: &HyraidArray, totals: &[(String,usize)], allow_degraded: bool) -> Result<(),HyraidError> {
+    let (slices,targets) = grow_targets(entry,totals);
+    let mut planned = entry.clone();
+    for (disk,current,target) in &targets {
+        let part_map = planned.part_map.entry(disk.to_string()).or_default();
+        part_map.extend((*current..*target).map(|slice| DiskPartition {
+            path: Some(format!("{} (new partition {})",disk,slice + 1)),
+            size: slices[slice]
+        }));
+        part_map.sort_by_key(|k| k.size);
+    }
+    let slice_md = slice_md_devices(&planned);
+    planned.slices = slices;
+    let groups = grow_groups(&planned,&slice_md);
+
+    let level = |members: usize| find_raid_level(members,entry.raid_level,entry.policy);
+    let mut layout: Vec<(String,usize,Vec<DiskPartition>)> = vec![];
+    let mut migrations: HashMap<String,Vec<MigrationStep>> = HashMap::new();
+    for (i,(device,partitions)) in groups.iter().enumerate() {
+        let (device,mut members) = match device {
+            Some(device) => (device.to_string(),entry.raid_map[device].to_vec()),
+            None => (format!("new md device {}",i + 1),vec![])
+        };
+        let current = members.len();
+        members.extend(partitions.to_vec());
+        if current > 0 {
+            migrations.insert(device.to_string(),migration_steps(level(current)?,current,level(members.len())?,members.len())?);
+        }
+        layout.push((device,level(members.len())?,members));
+    }
+
+    let groups: Vec<(&str,usize,&[DiskPartition])> = layout
+        .iter()
+        .map(|(device,level,members)| (device.as_str(),*level,members.as_slice()))
+        .collect();
+    let mut report = redundancy_report(&groups,&planned.part_map,entry.raid_level,entry.policy);
+    for group in report.groups.iter_mut() {
+        for step in migrations.get(&group.device).into_iter().flatten() {
+            group.failures_tolerated = group.failures_tolerated.min(step.failures_tolerated);
+        }
+    }
+    check_redundancy(&report,allow_degraded)
+}
+
+/// Puts the free space left on the disks of an array to use,
+/// e.g. once enough small disks were swapped for larger ones.
+///
+/// The extra space becomes new slices, every disk gets partitions for the
+/// slices that fit on it, and the partitions of each slice join its md device,
+/// or form a new one once there are two of them. A layout surviving fewer disk
+/// failures than the array asks for is refused before any disk is partitioned,
+/// unless `allow_degraded` is set.
+/// Returns whether the array grew.
+pub fn grow_hyraid_array(backend: &Backend, name: String, allow_degraded: bool) -> Result<bool,HyraidError> {
+    let mut entry = find_array(backend,&name)?;
+    let runner = backend.runner.as_ref();
+    let volume_group = entry.lvm_lv_path.trim_end_matches("/lvol0").to_string();
+
+    let totals = disk_totals(backend,&entry);
+    check_grow_redundancy(&entry,&totals,allow_degraded)?;
+    let (slices,targets) = grow_targets(&entry,&totals);
+
+    let array_uuid = entry.uuid.clone().unwrap_or_else(|| Uuid::new_v4().hyphenated().to_string());
+    let generation = entry.generation + 1;
+    backend.cancel.check()?;
+
+    for (disk,current,target) in &targets {
+        let partitions = into_disk_partitions(backend.disks.add_partitions(disk,&slices[*current..*target],&PartitionIdentity {
+            array_uuid: array_uuid.to_string(),
+            slice: *current,
+            generation
+        })?);
+        let known = entry.part_map[disk].to_vec();
+        let part_map = entry.part_map.entry(disk.to_string()).or_default();
+        part_map.extend(partitions.into_iter().filter(|x| !known.contains(x)));
+        part_map.sort_by_key(|k| k.size);
+        for record in entry.disks.iter_mut().filter(|x| x.partitions.iter().any(|x| known.contains(x))) {
+            record.partitions = into_disk_partitions(backend.disks.partitions(disk)?);
+        }
+    }
+
+    let slice_md = slice_md_devices(&entry);
+    entry.slices = slices;
+
+    let mut changed = !targets.is_empty();
+    for (device,partitions) in grow_groups(&entry,&slice_md) {
+        let paths = into_paths_slice(partitions.to_vec());
+        let paths: Vec<&str> = paths.iter().map(|s| s.as_str()).collect();
+
+        if let Some(device) = device {
+            grow_md_device(backend,&entry,&device,entry.raid_map[&device].len(),&paths,allow_degraded)?;
+            entry.raid_map.entry(device).or_default().extend(partitions);
+            changed = true;
+            continue;
+        }
+
+        let device = format!("/dev/md/hyraid_md_{}",random_string(10));
+        create_raid_array(runner,&device,&paths,find_raid_level(partitions.len(),entry.raid_level,entry.policy)?)?;
+        lvm_pv_create(runner,&[&device])?;
+        lvm_vg_extend(runner,&volume_group,&[&device])?;
+        entry.raid_map.insert(device,partitions);
+        changed = true;
+    }
+    if !changed {
+        return Ok(false);
+    }
+
+    entry.uuid = Some(array_uuid);
+    entry.generation = generation;
+    write_layout(backend,&name,&entry)?;
+
+    Ok(true)
+}
+
+/// Usable capacity of an array now, and once its smallest disk is swapped
+/// for one as large as its largest
+pub fn upgrade_report(backend: &Backend, name: String) -> Result<UpgradeReport,HyraidError> {
+    let entry = find_array(backend,&name)?;
+    let totals = disk_totals(backend,&entry);
+
+    let (next_swap,_) = totals
+        .iter()
+        .min_by_key(|(_,size)| *size)
+        .cloned()
+        .ok_or(HyraidError::Validation(format!("Array {} has no disks",name)))?;
+    let swap_size = totals.iter().map(|(_,size)| *size).max().unwrap_or(0);
+
+    let sizes: Vec<usize> = totals.iter().map(|(_,size)| *size).collect();
+    let swapped: Vec<usize> = totals
+        .iter()
+        .map(|(disk,size)| if *disk == next_swap { swap_size } else { *size })
+        .collect();
+
+    Ok(UpgradeReport {
+        usable: estimate_capacity(&sizes,entry.raid_level,entry.policy)?.hyraid.usable,
+        next_swap,
+        swap_size,
+        usable_after_swap: estimate_capacity(&swapped,entry.raid_level,entry.policy)?.hyraid.usable
+    })
+}
+
+/// What evacuating a disk does to an md device holding one of its partitions
+enum Evacuation {
+    /// reshaped in place to one member less, ending up with the given size in bytes
+    Reshape { raid_level: usize, size: usize },
+    /// emptied, then created again from the remaining members at the given RAID level
+    Recreate { raid_level: usize },
+    /// emptied and stopped, its only remaining member left unused
+    Remove
+}
+
+/// md device holding a partition of a disk being evacuated
+struct EvacuatedMd {
+    device: String,
+    partition: DiskPartition,
+    remaining: Vec<DiskPartition>,
+    action: Evacuation,
+}
+
+/// File mdadm backs up the critical section of reshaping an md device to, outside of any array
+fn reshape_backup_file(backend: &Backend, device: &str) -> String {
+    format!("{}.{}.backup",backend.state_file,device.rsplit('/').next().unwrap_or(device))
+}
+
+/// Role of a partition in an md device going by `mdadm --detail`, `None` if it isn't a member
+fn member_role(backend: &Backend, device: &str, path: &str) -> Result<Option<MemberRole>,HyraidError> {
+    let kernel_name = backend.disks.kernel_name(path);
+    Ok(detail_raid_array(backend.runner.as_ref(),device)?
+        .members
+        .into_iter()
+        .find(|x| backend.disks.kernel_name(&x.device) == kernel_name)
+        .map(|x| x.role))
+}
+
+/// Takes the member of an evacuated disk out of an md device reshaped to one member less.
+///
+/// md turns whichever members end up past the new number of devices into spares,
+/// so the evacuated member is first replaced with that spare if it's still active.
+fn remove_reshaped_member(backend: &Backend, device: &str, path: &str) -> Result<(),HyraidError> {
+    let runner = backend.runner.as_ref();
+    let kernel_name = backend.disks.kernel_name(path);
+    let members = detail_raid_array(runner,device)?.members;
+    let role = members
+        .iter()
+        .find(|x| backend.disks.kernel_name(&x.device) == kernel_name)
+        .map(|x| x.role.clone());
+
+    match role {
+        None => return Ok(()),
+        Some(MemberRole::Active) | Some(MemberRole::Rebuilding) => {
+            let spare = members
+                .iter()
+                .find(|x| x.role == MemberRole::Spare)
+                .ok_or(HyraidError::Validation(format!("{} has no spare to take the place of {} after reshaping",device,path)))?;
+            replace_in_raid_array(runner,device,path,&spare.device)?;
+            wait_for_recovery(backend,&[device])?;
+        },
+        Some(_) => {}
+    }
+    remove_from_raid_array(runner,device,&[path])
+}
+
+/// Removes a disk from an array for good, without leaving its md devices degraded.
+///
+/// Every md device holding the disk is reshaped to one member less where its
+/// RAID level allows it, moving the extents past its new size elsewhere first.
+/// RAID5 and RAID6 are reshaped with the member of the disk still in place,
+/// which is only removed once md made it a spare.
+/// Other md devices are emptied with `pvmove`, then created again from their
+/// remaining members, or stopped if only one is left.
+/// Fails before touching anything if the volume group can't hold its data afterwards.
+pub fn evacuate_disk(backend: &Backend, name: String, disk: &str) -> Result<(),HyraidError> {
+    let mut entry = find_array(backend,&name)?;
+    let runner = backend.runner.as_ref();
+    let volume_group = entry.lvm_lv_path.trim_end_matches("/lvol0").to_string();
+
+    let disk_partitions = entry.part_map
+        .get(disk)
+        .cloned()
+        .ok_or(HyraidError::Validation(format!("{} is not a disk of array {}",disk,name)))?;
+    if entry.part_map.len() < 2 {
+        return Err(HyraidError::Validation(format!("{} is the last disk of array {}",disk,name)));
+    }
+
+    let mut devices: Vec<(&String,&Vec<DiskPartition>)> = entry.raid_map.iter().collect();
+    devices.sort_by_key(|(device,_)| *device);
+    let mut evacuated: Vec<EvacuatedMd> = vec![];
+    for (device,members) in devices {
+        let Some(partition) = members.iter().find(|x| disk_partitions.contains(x)) else { continue };
+        let remaining: Vec<DiskPartition> = members.iter().filter(|x| *x != partition).cloned().collect();
+
+        let action = if remaining.len() < 2 {
+            Evacuation::Remove
+        } else {
+            let raid_level = find_raid_level(members.len(),entry.raid_level,entry.policy)?;
+            let new_level = find_raid_level(remaining.len(),entry.raid_level,entry.policy)?;
+            if new_level == raid_level && matches!(raid_level,1 | 5 | 6) {
+                let min_size = remaining.iter().map(|x| x.size).min().unwrap_or(0);
+                Evacuation::Reshape { raid_level, size: min_size * data_members(raid_level,remaining.len()) }
+            } else {
+                Evacuation::Recreate { raid_level: new_level }
+            }
+        };
+        evacuated.push(EvacuatedMd {
+            device: device.to_string(),
+            partition: partition.clone(),
+            remaining,
+            action
+        });
+    }
+
+    // the physical volumes of the volume group, with the extents they can hold afterwards
+    let physical_volumes: Vec<(PhysicalVolume,Option<&EvacuatedMd>)> = lvm_vg_physical_volumes(runner,&volume_group)?
+        .into_iter()
+        .map(|pv| {
+            let kernel_name = backend.disks.kernel_name(&pv.name);
+            let md = evacuated.iter().find(|x| kernel_name.is_some() && backend.disks.kernel_name(&x.device) == kernel_name);
+            (pv,md)
+        })
+        .collect();
+    let capacity = |pv: &PhysicalVolume, md: Option<&EvacuatedMd>| match md.map(|x| &x.action) {
+        None | Some(Evacuation::Reshape { raid_level: 1, .. }) => pv.pe_count,
+        Some(Evacuation::Reshape { size, .. }) => pv.pe_count.min(size.saturating_sub(pv.pe_start) / pv.extent_size),
+        Some(_) => 0
+    };
+
+    let extent_size = physical_volumes.first().map_or(0,|(pv,_)| pv.extent_size);
+    let mut to_move = 0;
+    let mut free = 0;
+    for (pv,md) in &physical_volumes {
+        let capacity = capacity(pv,*md);
+        if capacity >= pv.pe_alloc_count {
+            free += capacity - pv.pe_alloc_count;
+        } else {
+            to_move += pv.pe_alloc_count - capacity;
+        }
+    }
+    if to_move > free {
+        return Err(HyraidError::Validation(format!(
+            "Not enough free space in volume group {} to evacuate {}: {} to move, {} free",
+            volume_group,
+            disk,
+            format_size(to_move * extent_size),
+            format_size(free * extent_size)
+        )));
+    }
+
+    // where extents may go: every physical volume kept, up to its new size
+    let destinations: Vec<String> = physical_volumes
+        .iter()
+        .filter_map(|(pv,md)| match capacity(pv,*md) {
+            0 => None,
+            x if x == pv.pe_count => Some(pv.name.to_string()),
+            x => Some(format!("{}:0-{}",pv.name,x - 1))
+        })
+        .collect();
+    let destinations: Vec<&str> = destinations.iter().map(|x| x.as_str()).collect();
+
+    for (pv,md) in &physical_volumes {
+        let Some(md) = md else { continue };
+        let capacity = capacity(pv,Some(md));
+        match md.action {
+            Evacuation::Reshape { .. } => {
+                if let Some(end) = lvm_pv_allocated_end(runner,&pv.name)?
+                    && end > capacity {
+                    lvm_pv_move(runner,&format!("{}:{}-{}",pv.name,capacity,end - 1),&destinations)?;
+                }
+                if capacity < pv.pe_count {
+                    lvm_pv_shrink(runner,&pv.name,pv.pe_start + capacity * pv.extent_size)?;
+                }
+            },
+            Evacuation::Recreate { .. } | Evacuation::Remove => {
+                if pv.pe_alloc_count > 0 {
+                    lvm_pv_move(runner,&pv.name,&destinations)?;
+                }
+                lvm_vg_reduce(runner,&volume_group,&[&pv.name])?;
+                lvm_pv_remove(runner,&[&pv.name])?;
+            }
+        }
+    }
+
+    let mut reshaped: Vec<&EvacuatedMd> = vec![];
+    for md in &evacuated {
+        let path = md.partition.path.clone().unwrap_or_default();
+        let remaining = into_paths_slice(md.remaining.to_vec());
+        let remaining: Vec<&str> = remaining.iter().map(|s| s.as_str()).collect();
+
+        match md.action {
+            // a mirror only drops a copy, so it can lose the member first
+            Evacuation::Reshape { raid_level: 1, .. } => {
+                if backend.disks.device_exists(&path) {
+                    fail_from_raid_array(runner,&md.device,&[&path])?;
+                    remove_from_raid_array(runner,&md.device,&[&path])?;
+                }
+                reshape_raid_array(runner,&md.device,remaining.len())?;
+            },
+            Evacuation::Reshape { size, .. } => {
+                set_raid_array_size(runner,&md.device,size / 1024)?;
+                shrink_raid_array(runner,&md.device,remaining.len(),&reshape_backup_file(backend,&md.device))?;
+                reshaped.push(md);
+            },
+            Evacuation::Recreate { raid_level } => {
+                stop_raid_array(runner,&md.device)?;
+                zero_superblock(runner,&remaining)?;
+                create_raid_array(runner,&md.device,&remaining,raid_level)?;
+                lvm_pv_create(runner,&[&md.device])?;
+                lvm_vg_extend(runner,&volume_group,&[&md.device])?;
+            },
+            Evacuation::Remove => {
+                stop_raid_array(runner,&md.device)?;
+                zero_superblock(runner,&remaining)?;
+            }
+        }
+    }
+    let devices: Vec<&str> = reshaped.iter().map(|x| x.device.as_str()).collect();
+    wait_for_recovery(backend,&devices)?;
+    for md in reshaped {
+        let path = md.partition.path.clone().unwrap_or_default();
+        if backend.disks.device_exists(&path) {
+            remove_reshaped_member(backend,&md.device,&path)?;
+        }
+        // mdadm leaves it behind
+        let _ = fs::remove_file(reshape_backup_file(backend,&md.device));
+    }
+
+    let members: Vec<String> = evacuated
+        .iter()
+        .filter_map(|x| x.partition.path.clone())
+        .filter(|x| backend.disks.device_exists(x))
+        .collect();
+    let members: Vec<&str> = members.iter().map(|x| x.as_str()).collect();
+    if !members.is_empty() {
+        zero_superblock(runner,&members)?;
+    }
+    let paths = into_paths_slice(disk_partitions.to_vec());
+    let paths: Vec<&str> = paths.iter().map(|x| x.as_str()).collect();
+    if backend.disks.device_exists(disk) {
+        backend.disks.remove_partitions(disk,&paths)?;
+    }
+
+    for md in evacuated {
+        match md.action {
+            Evacuation::Remove => { entry.raid_map.remove(&md.device); },
+            _ => { entry.raid_map.insert(md.device,md.remaining); }
+        }
+    }
+    entry.disks.retain(|x| !x.partitions.iter().any(|x| disk_partitions.contains(x)));
+    entry.part_map.remove(disk);
+    // slices only the evacuated disk had are gone with it
+    let slices = entry.part_map.values().map(|x| x.len()).max().unwrap_or(0);
+    entry.slices.truncate(slices);
+    entry.generation += 1;
+
+    write_layout(backend,&name,&entry)
+}
+
+/// md device and path of every spare partition attached to one
+fn attached_spares(entry: &HyraidArray) -> Vec<(String,String)> {
+    let slice_md = slice_md_devices(entry);
+    let mut disks: Vec<&String> = entry.spares.keys().collect();
+    disks.sort();
+    disks
+        .into_iter()
+        .flat_map(|disk| entry.spares[disk].iter().enumerate())
+        .filter_map(|(slice,partition)| Some((slice_md.get(&slice)?.to_string(),partition.path.clone()?)))
+        .collect()
+}
+
+/// Refuses a disk that already belongs to an array or the global spare pool
+fn check_disk_unused(backend: &Backend, disk: &str) -> Result<(),HyraidError> {
+    let document = hyraid_json::read_document(&backend.state_file)?;
+    if let Some(array) = document.arrays.iter().find(|x| x.part_map.contains_key(disk)) {
+        return Err(HyraidError::Validation(format!("{} is a disk of array {}",disk,array.name)));
+    }
+    if let Some(array) = document.arrays.iter().find(|x| x.spares.contains_key(disk)) {
+        return Err(HyraidError::Validation(format!("{} is a spare of array {}",disk,array.name)));
+    }
+    if document.global_spares.iter().any(|x| x == disk) {
+        return Err(HyraidError::Validation(format!("{} is a global spare",disk)));
+    }
+    Ok(())
+}
+
+/// Partitions a disk to match the slices of an array and attaches every partition
+/// as a spare of the md device of its slice, for md to rebuild onto once a member fails.
+///
+/// The disk gets as many slices as fit on it, smallest first.
+pub fn add_spare(backend: &Backend, name: String, disk: &str) -> Result<(),HyraidError> {
+    let entry = find_array(backend,&name)?;
+    let runner = backend.runner.as_ref();
+    check_disk_unused(backend,disk)?;
+
+    let free = backend.disks.usable_space(disk)?;
+    let count = (0..=entry.slices.len())
+        .take_while(|x| entry.slices[..*x].iter().sum::<usize>() <= free)
+        .last()
+        .unwrap_or(0);
+    if count == 0 {
+        return Err(HyraidError::Validation(format!("{} is too small to be a spare of array {}",disk,name)));
+    }
+
+    backend.disks.ensure_gpt(backend.runner.as_ref(),disk)?;
+    backend.disks.clear_partitions(disk)?;
+
+    let array_uuid = entry.uuid.clone().unwrap_or_else(|| Uuid::new_v4().hyphenated().to_string());
+    let partitions = into_disk_partitions(
+        backend.disks.add_partitions(disk,&entry.slices[..count],&PartitionIdentity {
+            array_uuid: array_uuid.to_string(),
+            slice: 0,
+            generation: entry.generation
+        })?
+    );
+
+    let slice_md = slice_md_devices(&entry);
+    for (slice,partition) in partitions.iter().enumerate() {
+        let Some(device) = slice_md.get(&slice) else { continue };
+        add_to_raid_array(runner,device,&[&partition.path.clone().unwrap_or_default()])?;
+    }
+
+    hyraid_json::update_array(&backend.state_file,&name,|x| {
+        x.spares.insert(disk.to_string(),partitions);
+        x.uuid = Some(array_uuid);
+    })
+}
+
+/// Detaches the partitions of a spare disk from the md devices of an array and deletes them.
+///
+/// Refuses once md has rebuilt onto any of them, since they hold data then.
+pub fn remove_spare(backend: &Backend, name: String, disk: &str) -> Result<(),HyraidError> {
+    let entry = find_array(backend,&name)?;
+    let runner = backend.runner.as_ref();
+    let partitions = entry.spares
+        .get(disk)
+        .cloned()
+        .ok_or(HyraidError::Validation(format!("{} is not a spare of array {}",disk,name)))?;
+
+    let mut attached: Vec<(String,String)> = vec![];
+    for (device,path) in attached_spares(&entry) {
+        if !partitions.iter().any(|x| x.path.as_ref() == Some(&path)) || !backend.disks.device_exists(&path) {
+            continue;
+        }
+        let kernel_name = backend.disks.kernel_name(&path);
+        let role = detail_raid_array(runner,&device)?
+            .members
+            .into_iter()
+            .find(|x| backend.disks.kernel_name(&x.device) == kernel_name)
+            .map(|x| x.role);
+        match role {
+            Some(MemberRole::Spare) | Some(MemberRole::Faulty) => attached.push((device,path)),
+            Some(_) => {
+                return Err(HyraidError::Validation(format!(
+                    "{} has taken the place of a failed member of {}, remove the failed disk instead",
+                    path,
+                    device
+                )));
+            },
+            None => {}
+        }
+    }
+
+    for (device,path) in &attached {
+        remove_from_raid_array(runner,device,&[path])?;
+        zero_superblock(runner,&[path])?;
+    }
+    let paths = into_paths_slice(partitions);
+    let paths: Vec<&str> = paths.iter().map(|x| x.as_str()).collect();
+    backend.disks.remove_partitions(disk,&paths)?;
+
+    hyraid_json::update_array(&backend.state_file,&name,|x| { x.spares.remove(disk); })
+}
+
+/// Adds a disk to the global spare pool, left untouched until a degraded array takes it
+pub fn add_global_spare(backend: &Backend, disk: &str) -> Result<(),HyraidError> {
+    check_disk_unused(backend,disk)?;
+    if !backend.disks.device_exists(disk) {
+        return Err(HyraidError::Validation(format!("No such disk: {}",disk)));
+    }
+    hyraid_json::add_global_spare(&backend.state_file,disk)
+}
+
+/// Lists the disks of the global spare pool
+pub fn list_global_spares(backend: &Backend) -> Result<Vec<String>,HyraidError> {
+    Ok(hyraid_json::read_document(&backend.state_file)?.global_spares)
+}
+
+/// Removes a disk from the global spare pool
+pub fn remove_global_spare(backend: &Backend, disk: &str) -> Result<(),HyraidError> {
+    hyraid_json::remove_global_spare(&backend.state_file,disk)
+}
+
+/// Gives a degraded array a disk of the global spare pool large enough to hold
+/// every degraded slice, which md starts rebuilding onto right away.
+///
+/// Returns the disk taken, `None` if the array isn't degraded or no global spare fits.
+pub fn attach_global_spare(backend: &Backend, name: String) -> Result<Option<String>,HyraidError> {
+    let entry = find_array(backend,&name)?;
+    let mdstat = read_mdstat(&backend.mdstat_file)?;
+
+    let degraded: Vec<usize> = slice_md_devices(&entry)
+        .into_iter()
+        .filter(|(_,device)| {
+            let kernel_name = backend.disks.kernel_name(device);
+            mdstat.arrays.iter().any(|x| Some(&x.name) == kernel_name.as_ref() && x.is_degraded())
+        })
+        .map(|(slice,_)| slice)
+        .collect();
+    let Some(last_slice) = degraded.into_iter().max() else { return Ok(None) };
+    let needed: usize = entry.slices[..=last_slice].iter().sum();
+
+    for disk in hyraid_json::read_document(&backend.state_file)?.global_spares {
+        if backend.disks.usable_space(&disk).unwrap_or(0) < needed {
+            continue;
+        }
+        hyraid_json::remove_global_spare(&backend.state_file,&disk)?;
+        if let Err(err) = add_spare(backend,name.to_string(),&disk) {
+            // back into the pool for the next degraded array
+            hyraid_json::add_global_spare(&backend.state_file,&disk)?;
+            return Err(err);
+        }
+        return Ok(Some(disk));
+    }
+    Ok(None)
+}
+
+fn planned(step: JournalStep) -> JournalEntry {
+    JournalEntry { step, state: StepState::Planned }
+}
+
+fn wipe_steps(disks: &[&str]) -> Vec<JournalEntry> {
+    disks
+        .iter()
+        .map(|disk| planned(JournalStep::WipeDisk { disk: disk.to_string() }))
+        .collect()
+}
+
+fn as_strs(strings: &[String]) -> Vec<&str> {
+    strings.iter().map(|s| s.as_str()).collect()
+}
+
+/// Refuses to start an operation while another one hasn't finished,
+/// its md devices and partitions would get in the way. This only fails early,
+/// an operation owns the journal once `create_journal` made it.
+fn check_no_journal(backend: &Backend) -> Result<(),HyraidError> {
+    match hyraid_json::read_journal(&backend.state_file)? {
+        Some(journal) => Err(HyraidError::Validation(format!(
+            "Unfinished {} of array {} found, run `hyraid recover` first",
+            journal.operation,
+            journal.name
+        ))),
+        None => Ok(())
+    }
+}
+
+/// Entry the journal takes its array to
+fn planned_entry(journal: &Journal) -> Result<&HyraidArray,HyraidError> {
+    journal.entry
+        .as_ref()
+        .ok_or(HyraidError::StateFile(format!("Journal of array {} has no planned entry",journal.name)))
+}
+
+fn run_step(backend: &Backend, journal: &Journal, step: &JournalStep) -> Result<(),HyraidError> {
+    let runner = backend.runner.as_ref();
+    match step {
+        JournalStep::WipeDisk { disk } => {
+            backend.disks.ensure_gpt(backend.runner.as_ref(),disk)?;
+            backend.disks.clear_partitions(disk)
+        },
+        JournalStep::AddPartitions { disk, sizes, array_uuid, generation } => {
+            backend.disks.add_partitions(disk,sizes,&PartitionIdentity {
+                array_uuid: array_uuid.to_string(),
+                slice: 0,
+                generation: *generation
+            })?;
+            Ok(())
+        },
+        JournalStep::CreateMd { device, partitions, raid_level } => {
+            create_raid_array(runner,device,&as_strs(partitions),*raid_level)
+        },
+        JournalStep::CreatePv { devices } => lvm_pv_create(runner,&as_strs(devices)),
+        JournalStep::CreateVg { volume_group, devices } => lvm_vg_create(runner,volume_group,&as_strs(devices)),
+        JournalStep::ExtendVg { volume_group, devices } => lvm_vg_extend(runner,volume_group,&as_strs(devices)),
+        JournalStep::CreateLv { volume_group, devices } => {
+            lvm_lv_create(runner,volume_group,&as_strs(devices),hyraid_lvm2::SizeFormat::EXTENTS,"100%FREE")
+        },
+        JournalStep::GrowMd { device, members, partitions } => {
+            let allow_degraded = matches!(journal.operation,JournalOperation::Add { allow_degraded: true, .. });
+            grow_md_device(backend,planned_entry(journal)?,device,*members,&as_strs(partitions),allow_degraded)
+        },
+        JournalStep::WriteState => {
+            let entry = planned_entry(journal)?.clone();
+            match journal.operation {
+                JournalOperation::Create { .. } => hyraid_json::write_array(&backend.state_file,entry),
+                JournalOperation::Add { .. } => write_layout(backend,&journal.name,&entry)
+            }
+        },
+    }
+}
+
+/// Finishes a step that was interrupted, skipping it if it already happened
+fn resume_step(backend: &Backend, journal: &Journal, step: &JournalStep) -> Result<(),HyraidError> {
+    let runner = backend.runner.as_ref();
+    let in_volume_group = |devices: &[String], volume_group: &str| devices
+        .iter()
+        .all(|x| lvm_pv_volume_group(runner,x).ok().flatten().is_some_and(|x| x == volume_group));
+    let done = match step {
+        JournalStep::WipeDisk { .. } => false,
+        // the disk was wiped before, so any partition on it is one of ours
+        JournalStep::AddPartitions { disk, .. } => !backend.disks.partitions(disk)?.is_empty(),
+        JournalStep::CreateMd { device, .. } => backend.disks.device_exists(device),
+        JournalStep::CreatePv { devices } => devices.iter().all(|x| lvm_pv_volume_group(runner,x).is_ok()),
+        JournalStep::CreateVg { volume_group, devices } => in_volume_group(devices,volume_group),
+        JournalStep::ExtendVg { volume_group, devices } => in_volume_group(devices,volume_group.trim_start_matches("/dev/")),
+        JournalStep::CreateLv { volume_group, .. } => backend.disks.device_exists(&format!("/dev/{}/lvol0",volume_group)),
+        JournalStep::GrowMd { device, members, partitions } => {
+            let target_members = members + partitions.len();
+            // md carries on with a reshape once the md device is assembled again
+            if detail_raid_array(runner,device)?.devices.is_some_and(|x| x >= target_members) {
+                wait_for_recovery(backend,&[device])?;
+                return lvm_pv_resize(runner,&[device]);
+            }
+            if partitions.iter().any(|x| examine_partition(runner,x).is_ok_and(|x| x.uuid.is_some())) {
+                return Err(HyraidError::Validation(format!(
+                    "Growing {} to {} devices stopped part way, finish it with `mdadm --grow` and run `hyraid recover` again",
+                    device,
+                    target_members
+                )));
+            }
+            false
+        },
+        JournalStep::WriteState => match journal.operation {
+            JournalOperation::Create { .. } => find_array(backend,&journal.name).is_ok(),
+            JournalOperation::Add { .. } => false
+        },
+    };
+    if done {
+        return Ok(());
+    }
+    run_step(backend,journal,step)
+}
+
+/// Runs every step of the journal that isn't done yet, recording each before it runs
+fn run_journal(backend: &Backend, journal: &mut Journal) -> Result<(),HyraidError> {
+    for i in 0..journal.steps.len() {
+        let step = journal.steps[i].step.clone();
+        match journal.steps[i].state {
+            StepState::Done => continue,
+            StepState::Started => resume_step(backend,journal,&step)?,
+            StepState::Planned => {
+                journal.steps[i].state = StepState::Started;
+                hyraid_json::write_journal(&backend.state_file,journal)?;
+                run_step(backend,journal,&step)?;
+            }
+        }
+        journal.steps[i].state = StepState::Done;
+        hyraid_json::write_journal(&backend.state_file,journal)?;
+    }
+    Ok(())
+}
+
+fn undo_step(backend: &Backend, journal: &Journal, step: &JournalStep) -> Result<(),HyraidError> {
+    let runner = backend.runner.as_ref();
+    match step {
+        // whatever was on the disk is gone either way
+        JournalStep::WipeDisk { .. } => Ok(()),
+        JournalStep::AddPartitions { disk, .. } => backend.disks.clear_partitions(disk),
+        JournalStep::CreateMd { device, partitions, .. } => {
+            if backend.disks.device_exists(device) {
+                stop_raid_array(runner,device)?;
+            }
+            zero_superblock(runner,&as_strs(partitions))
+        },
+        JournalStep::CreatePv { devices } => lvm_pv_remove(runner,&as_strs(devices)),
+        JournalStep::CreateVg { volume_group, .. } => lvm_vg_remove(runner,volume_group),
+        JournalStep::ExtendVg { volume_group, devices } => lvm_vg_reduce(runner,volume_group,&as_strs(devices)),
+        JournalStep::CreateLv { volume_group, .. } => {
+            let lv = format!("/dev/{}/lvol0",volume_group);
+            lvm_lv_deactivate(runner,&lv)?;
+            lvm_lv_remove(runner,&lv)
+        },
+        JournalStep::GrowMd { device, .. } => Err(HyraidError::Validation(format!("Growing {} can't be undone",device))),
+        JournalStep::WriteState => match &journal.previous {
+            Some(previous) => write_layout(backend,&journal.name,previous),
+            None => hyraid_json::remove_array(&backend.state_file,&journal.name)
+        },
+    }
+}
+
+/// Journal of an operation that was interrupted, by a crash or a failed step
+pub fn pending_operation(backend: &Backend) -> Result<Option<Journal>,HyraidError> {
+    hyraid_json::read_journal(&backend.state_file)
+}
+
+fn read_pending(backend: &Backend) -> Result<Journal,HyraidError> {
+    pending_operation(backend)?.ok_or(HyraidError::Validation("No unfinished operation".to_string()))
+}
+
+/// Undoes every step an interrupted operation took, newest first.
+///
+/// Wiped disks stay wiped, and md devices that were grown can't be shrunk back,
+/// so such an operation can only be resumed.
+pub fn rollback_operation(backend: &Backend) -> Result<(),HyraidError> {
+    let mut journal = read_pending(backend)?;
+    let grown = journal.steps
+        .iter()
+        .any(|x| matches!(x.step,JournalStep::GrowMd { .. }) && x.state != StepState::Planned);
+    if grown {
+        return Err(HyraidError::Validation(format!(
+            "The {} of array {} already grew md devices, which can't be undone, resume it instead",
+            journal.operation,
+            journal.name
+        )));
+    }
+
+    for i in (0..journal.steps.len()).rev() {
+        let step = journal.steps[i].step.clone();
+        match journal.steps[i].state {
+            StepState::Planned => continue,
+            // may not have happened, so there may be nothing to undo
+            StepState::Started => {
+                let _ = undo_step(backend,&journal,&step);
+            },
+            StepState::Done => undo_step(backend,&journal,&step)?,
+        }
+        journal.steps[i].state = StepState::Planned;
+        hyraid_json::write_journal(&backend.state_file,&journal)?;
+    }
+    hyraid_json::remove_journal(&backend.state_file)
+}
+
+/// Carries on with an interrupted operation from the step it stopped at.
+///
+/// An operation interrupted before its layout was settled, while the disks were
+/// being wiped or partitioned, is rolled back and run again from the start.
+pub fn resume_operation(backend: &Backend) -> Result<(),HyraidError> {
+    let mut journal = read_pending(backend)?;
+    if journal.entry.is_none() {
+        rollback_operation(backend)?;
+        return match &journal.operation {
+            JournalOperation::Create { disks, raid_level, policy, allow_degraded } => {
+                create_hyraid_array(backend,journal.name,&as_strs(disks),*raid_level,*policy,*allow_degraded).map(|_| ())
+            },
+            JournalOperation::Add { disks, allow_degraded } => {
+                add_disk_to_hyraid_array(backend,journal.name,&as_strs(disks),*allow_degraded)
+            }
+        };
+    }
+
+    run_journal(backend,&mut journal)?;
+    hyraid_json::remove_journal(&backend.state_file)
+}
@@ -0,0 +1,957 @@
+/*!
+    Tests for the commands HyRAID runs, using fake disks and a recording command runner.
+*/
+
+use std::{fs, sync::{Arc, Mutex}};
+
+use hyraid_gpt::{BlockDevice, FakeDisks, PartitionIdentity};
+use hyraid_mapper::{
+    Backend,
+    create_hyraid_array,
+    add_disk_to_hyraid_array,
+    remove_disk_from_array,
+    destroy_hyraid_array,
+    scan_hyraid_arrays,
+    import_hyraid_arrays,
+    assemble_hyraid_array,
+    generate_mdadm_conf,
+    replace_disk,
+    grow_hyraid_array,
+    upgrade_report,
+    plan_create,
+    plan_add,
+    evacuate_disk,
+    add_spare,
+    remove_spare,
+    add_global_spare,
+    attach_global_spare,
+    hyraid_array_status
+};
+use hyraid_types::RedundancyPolicy;
+use hyraid_utils::{CommandOutput, HyraidError, RecordingRunner};
+
+const DISK_SIZE: usize = 4_000_000;
+const DISKS: [&str; 3] = ["/dev/sda","/dev/sdb","/dev/sdc"];
+
+fn backend(test: &str) -> (Backend,Arc<RecordingRunner>,Arc<FakeDisks>) {
+    let state_file = std::env::temp_dir()
+        .join(format!("hyraid-test-{}-{}.json",test,std::process::id()))
+        .to_string_lossy()
+        .to_string();
+
+    let (backend,runner,disks) = Backend::fake(&state_file);
+    for disk in DISKS {
+        disks.add_disk(disk,DISK_SIZE);
+    }
+    (backend,runner,disks)
+}
+
+fn argv(args: &[&str]) -> Vec<String> {
+    args.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn create_runs_mdadm_then_lvm() {
+    let (backend,runner,disks) = backend("create");
+
+    let lv = create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+
+    let size = DISK_SIZE.to_string();
+    assert_eq!(disks.operations(),vec![
+        argv(&["ensure_gpt","/dev/sda"]),
+        argv(&["clear_partitions","/dev/sda"]),
+        argv(&["ensure_gpt","/dev/sdb"]),
+        argv(&["clear_partitions","/dev/sdb"]),
+        argv(&["ensure_gpt","/dev/sdc"]),
+        argv(&["clear_partitions","/dev/sdc"]),
+        argv(&["add_partitions","/dev/sda",&size]),
+        argv(&["add_partitions","/dev/sdb",&size]),
+        argv(&["add_partitions","/dev/sdc",&size]),
+    ]);
+
+    let commands = runner.commands();
+    let md = commands[0][2].to_string();
+    let vg = commands[2][1].to_string();
+    assert!(md.starts_with("/dev/md/hyraid_md_"));
+    assert!(vg.starts_with("hyraid_vg_"));
+    assert_eq!(commands,vec![
+        argv(&[
+            "mdadm","--create",&md,"--run","--level=5","--raid-devices=3",
+            "/dev/disk/by-partuuid/sda-part1",
+            "/dev/disk/by-partuuid/sdb-part1",
+            "/dev/disk/by-partuuid/sdc-part1"
+        ]),
+        argv(&["pvcreate",&md]),
+        argv(&["vgcreate",&vg,&md]),
+        argv(&["lvcreate",&vg,&md,"-l","100%FREE"]),
+    ]);
+    assert_eq!(lv,format!("/dev/{}/lvol0",vg));
+
+    let arrays = hyraid_json::read_arrays(&backend.state_file).unwrap();
+    assert_eq!(arrays.len(),1);
+    assert_eq!(arrays[0].lvm_lv_path,lv);
+    assert_eq!(arrays[0].raid_map[&md].len(),3);
+}
+
+#[test]
+fn failed_command_is_not_recorded_in_state_file() {
+    let (backend,runner,_) = backend("create-fail");
+    runner.respond(&["vgcreate"],Err(Some(5)));
+
+    let err = create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap_err();
+
+    match err {
+        HyraidError::Lvm(err) => {
+            assert_eq!(err.argv[0],"vgcreate");
+            assert_eq!(err.code,Some(5));
+        },
+        err => panic!("unexpected error: {}",err)
+    }
+    assert!(runner.commands().iter().all(|x| x[0] != "lvcreate"));
+    assert!(hyraid_json::read_arrays(&backend.state_file).unwrap().is_empty());
+}
+
+#[test]
+fn add_grows_existing_md_device() {
+    let (backend,runner,disks) = backend("add");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+    fs::write(&backend.mdstat_file,"").unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    let created = runner.commands().len();
+
+    add_disk_to_hyraid_array(&backend,"test".to_string(),&["/dev/sdd"],false).unwrap();
+
+    assert_eq!(runner.commands()[created..].to_vec(),vec![
+        argv(&["mdadm","--manage",&md,"--add","/dev/disk/by-partuuid/sdd-part1"]),
+        argv(&["mdadm","--grow",&md,"--raid-devices=4"]),
+        argv(&["pvresize",&md]),
+    ]);
+
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    assert_eq!(array.generation,1);
+    assert_eq!(array.disks.len(),4);
+    assert_eq!(array.raid_map[&md].len(),4);
+    assert!(array.part_map.contains_key("/dev/sdd"));
+
+    let identity = disks.partitions("/dev/sdd").unwrap()[0].identity().unwrap();
+    assert_eq!(Some(identity.array_uuid),array.uuid);
+    assert_eq!(identity.generation,1);
+}
+
+#[test]
+fn create_stores_array_identity_in_partitions() {
+    let (backend,_,disks) = backend("identity");
+    disks.add_disk("/dev/sdd",DISK_SIZE * 2);
+
+    create_hyraid_array(&backend,"test".to_string(),&["/dev/sda","/dev/sdb","/dev/sdd"],5,RedundancyPolicy::RaidLevel,false).unwrap();
+
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    let partitions = disks.partitions("/dev/sdd").unwrap();
+    assert_eq!(partitions.len(),2);
+    for (slice,partition) in partitions.iter().enumerate() {
+        assert!(partition.is_hyraid());
+        assert_eq!(partition.identity(),Some(PartitionIdentity {
+            array_uuid: array.uuid.clone().unwrap(),
+            slice,
+            generation: 0
+        }));
+    }
+}
+
+#[test]
+fn remove_removes_partitions_from_md_device() {
+    let (backend,runner,_) = backend("remove");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    let created = runner.commands().len();
+
+    remove_disk_from_array(&backend,"test".to_string(),&["/dev/sdb"]).unwrap();
+
+    assert_eq!(runner.commands()[created..].to_vec(),vec![
+        argv(&["mdadm","--manage",&md,"--remove","/dev/disk/by-partuuid/sdb-part1"]),
+    ]);
+}
+
+#[test]
+fn destroy_tears_down_everything() {
+    let (backend,runner,disks) = backend("destroy");
+    let lv = create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    let vg = runner.commands()[2][1].to_string();
+    let created = runner.commands().len();
+
+    destroy_hyraid_array(&backend,"test".to_string()).unwrap();
+
+    let partitions = [
+        "/dev/disk/by-partuuid/sda-part1",
+        "/dev/disk/by-partuuid/sdb-part1",
+        "/dev/disk/by-partuuid/sdc-part1"
+    ];
+    let mut zero = vec!["mdadm","--zero-superblock"];
+    zero.extend(partitions);
+    assert_eq!(runner.commands()[created..].to_vec(),vec![
+        argv(&["lvchange","-an",&lv]),
+        argv(&["lvremove","-y",&lv]),
+        argv(&["vgremove","-y",&format!("/dev/{}",vg)]),
+        argv(&["pvremove","-y",&md]),
+        argv(&["mdadm","--stop",&md]),
+        argv(&zero),
+    ]);
+    assert_eq!(disks.operations()[9..].to_vec(),vec![
+        argv(&["remove_partitions","/dev/sda",partitions[0]]),
+        argv(&["remove_partitions","/dev/sdb",partitions[1]]),
+        argv(&["remove_partitions","/dev/sdc",partitions[2]]),
+    ]);
+    assert!(hyraid_json::read_arrays(&backend.state_file).unwrap().is_empty());
+
+    // the name can be reused
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+}
+
+#[test]
+fn destroy_refuses_mounted_array() {
+    let (backend,runner,disks) = backend("destroy-mounted");
+    let lv = create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.mount(&lv);
+    let created = runner.commands().len();
+
+    let err = destroy_hyraid_array(&backend,"test".to_string()).unwrap_err();
+
+    assert!(matches!(err,HyraidError::Validation(_)));
+    assert_eq!(runner.commands().len(),created);
+    assert_eq!(hyraid_json::read_arrays(&backend.state_file).unwrap().len(),1);
+}
+
+fn stdout(stdout: &str) -> Result<CommandOutput,Option<i32>> {
+    Ok(CommandOutput {
+        stdout: stdout.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn scan_rebuilds_created_array() {
+    let (backend,runner,_) = backend("scan");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    let created = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+
+    let md = runner.commands()[0][2].to_string();
+    let vg = runner.commands()[2][1].to_string();
+    runner.respond(&["mdadm","--examine"],stdout(&format!(
+        "MD_LEVEL=raid5\nMD_DEVICES=3\nMD_UUID=11111111:22222222:33333333:44444444\nMD_NAME=host:{}\n",
+        md.trim_start_matches("/dev/md/")
+    )));
+    runner.respond(&["pvs"],stdout(&format!("  {}\n",vg)));
+
+    let report = scan_hyraid_arrays(&backend).unwrap();
+
+    assert!(report.orphan_md_devices.is_empty());
+    assert!(report.orphan_partitions.is_empty());
+    assert_eq!(report.arrays.len(),1);
+    let array = &report.arrays[0];
+    assert_eq!(array.name,vg);
+    assert_eq!(array.lvm_lv_path,created.lvm_lv_path);
+    assert_eq!(array.raid_level,5);
+    assert_eq!(array.raid_map,created.raid_map);
+    assert_eq!(array.part_map,created.part_map);
+    assert_eq!(array.slices,vec![DISK_SIZE]);
+}
+
+#[test]
+fn scan_reports_partitions_without_superblock() {
+    let (backend,runner,disks) = backend("scan-orphan");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    runner.respond(&["mdadm","--examine"],Err(Some(1)));
+
+    let report = scan_hyraid_arrays(&backend).unwrap();
+
+    assert!(report.arrays.is_empty());
+    let partitions: Vec<String> = DISKS
+        .iter()
+        .flat_map(|disk| disks.partitions(disk).unwrap())
+        .map(|x| x.path)
+        .collect();
+    assert_eq!(report.orphan_partitions,partitions);
+}
+
+#[test]
+fn import_writes_scanned_array_once() {
+    let (backend,runner,_) = backend("import");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    let md = runner.commands()[0][2].to_string();
+    let vg = runner.commands()[2][1].to_string();
+    runner.respond(&["mdadm","--examine"],stdout(&format!(
+        "MD_LEVEL=raid5\nMD_UUID=11111111:22222222:33333333:44444444\nMD_NAME=host:{}\n",
+        md.trim_start_matches("/dev/md/")
+    )));
+    runner.respond(&["pvs"],stdout(&vg));
+
+    // already in the state file
+    assert!(import_hyraid_arrays(&backend,None,None).unwrap().is_empty());
+
+    // as if the state file was lost
+    fs::remove_file(&backend.state_file).unwrap();
+    let imported = import_hyraid_arrays(&backend,Some(&vg),Some("media".to_string())).unwrap();
+
+    assert_eq!(imported.len(),1);
+    let arrays = hyraid_json::read_arrays(&backend.state_file).unwrap();
+    assert_eq!(arrays.len(),1);
+    assert_eq!(arrays[0].name,"media");
+}
+
+#[test]
+fn assemble_uses_md_uuid_and_activates_volume_group() {
+    let (backend,runner,_) = backend("assemble");
+    let lv = create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    let md = runner.commands()[0][2].to_string();
+    let vg = runner.commands()[2][1].to_string();
+    runner.respond(&["mdadm","--examine"],stdout(&format!(
+        "MD_LEVEL=raid5\nMD_METADATA=1.2\nMD_UUID=11111111:22222222:33333333:44444444\nMD_NAME=host:{}\n",
+        md.trim_start_matches("/dev/md/")
+    )));
+    let created = runner.commands().len();
+
+    assert_eq!(assemble_hyraid_array(&backend,"test".to_string()).unwrap(),lv);
+
+    assert_eq!(runner.commands()[created..].to_vec(),vec![
+        argv(&["mdadm","--examine","--export","/dev/disk/by-partuuid/sda-part1"]),
+        argv(&[
+            "mdadm","--assemble",&md,"--uuid=11111111:22222222:33333333:44444444",
+            "/dev/disk/by-partuuid/sda-part1",
+            "/dev/disk/by-partuuid/sdb-part1",
+            "/dev/disk/by-partuuid/sdc-part1"
+        ]),
+        argv(&["vgchange","-ay",&format!("/dev/{}",vg)]),
+    ]);
+
+    assert_eq!(generate_mdadm_conf(&backend,None).unwrap(),format!(
+        "# HyRAID array test\nARRAY {} metadata=1.2 UUID=11111111:22222222:33333333:44444444 name=host:{}\n",
+        md,
+        md.trim_start_matches("/dev/md/")
+    ));
+}
+
+#[test]
+fn replace_copies_onto_new_disk_then_removes_old() {
+    let (backend,runner,disks) = backend("replace");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+    fs::write(&backend.mdstat_file,"").unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    let created = runner.commands().len();
+
+    replace_disk(&backend,"test".to_string(),"/dev/sdb","/dev/sdd",false).unwrap();
+
+    assert_eq!(runner.commands()[created..].to_vec(),vec![
+        argv(&["mdadm","--manage",&md,"--add","/dev/disk/by-partuuid/sdd-part1"]),
+        argv(&[
+            "mdadm","--manage",&md,"--replace","/dev/disk/by-partuuid/sdb-part1",
+            "--with","/dev/disk/by-partuuid/sdd-part1"
+        ]),
+        argv(&["mdadm","--manage",&md,"--remove","/dev/disk/by-partuuid/sdb-part1"]),
+    ]);
+
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    assert_eq!(array.generation,1);
+    assert_eq!(array.disks.len(),3);
+    let mut members: Vec<String> = array.raid_map[&md].iter().filter_map(|x| x.path.clone()).collect();
+    members.sort();
+    assert_eq!(members,vec![
+        "/dev/disk/by-partuuid/sda-part1",
+        "/dev/disk/by-partuuid/sdc-part1",
+        "/dev/disk/by-partuuid/sdd-part1"
+    ]);
+    assert!(!array.part_map.contains_key("/dev/sdb"));
+    assert!(array.part_map.contains_key("/dev/sdd"));
+}
+
+/// Array `test` on `DISKS` whose `mdadm --replace` of sdb with sdd is refused,
+/// with sdb in the role `role` according to `mdadm --detail`
+fn refused_replace(test: &str, role: &str) -> (Backend,Arc<RecordingRunner>,String) {
+    let (backend,runner,disks) = backend(test);
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+    fs::write(&backend.mdstat_file,"").unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    let replace = argv(&[
+        "mdadm","--manage",&md,"--replace","/dev/disk/by-partuuid/sdb-part1",
+        "--with","/dev/disk/by-partuuid/sdd-part1"
+    ]);
+    let replace: Vec<&str> = replace.iter().map(|x| x.as_str()).collect();
+    runner.respond(&replace,Err(Some(1)));
+    runner.respond(&["mdadm","--detail"],stdout(&format!(
+        "MD_DEVICE_dev_sdb-part1_ROLE={}\nMD_DEVICE_dev_sdb-part1_DEV=/dev/sdb-part1\n",
+        role
+    )));
+    (backend,runner,md)
+}
+
+#[test]
+fn replace_falls_back_to_fail_when_old_member_is_faulty() {
+    let (backend,runner,md) = refused_replace("replace-fail","faulty");
+
+    replace_disk(&backend,"test".to_string(),"/dev/sdb","/dev/sdd",false).unwrap();
+
+    assert!(runner.commands().contains(
+        &argv(&["mdadm","--manage",&md,"--fail","/dev/disk/by-partuuid/sdb-part1"])
+    ));
+}
+
+#[test]
+fn refused_replace_keeps_healthy_member() {
+    let (backend,runner,md) = refused_replace("replace-refused","1");
+
+    assert!(replace_disk(&backend,"test".to_string(),"/dev/sdb","/dev/sdd",false).is_err());
+
+    let commands = runner.commands();
+    assert!(!commands.iter().any(|x| x.len() > 3 && x[3] == "--fail"));
+    assert_eq!(commands.last().unwrap(),&argv(&["mdadm","--manage",&md,"--remove","/dev/disk/by-partuuid/sdd-part1"]));
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    assert!(array.part_map.contains_key("/dev/sdb"));
+}
+
+#[test]
+fn replace_leaves_slices_the_old_disk_lacked_to_grow() {
+    let (backend,runner,disks) = backend("replace-small");
+    disks.add_disk("/dev/sdb",DISK_SIZE * 2);
+    disks.add_disk("/dev/sdc",DISK_SIZE * 2);
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE * 2);
+    fs::write(&backend.mdstat_file,"").unwrap();
+    let created = runner.commands().len();
+
+    replace_disk(&backend,"test".to_string(),"/dev/sda","/dev/sdd",false).unwrap();
+
+    // the second slice is only ever added along with a reshape taking it in
+    let commands = runner.commands()[created..].to_vec();
+    let added = commands
+        .iter()
+        .position(|x| x.len() == 5 && x[3] == "--add" && x[4] == "/dev/disk/by-partuuid/sdd-part2");
+    if let Some(added) = added {
+        assert!(commands[added..].iter().any(|x| x.len() > 2 && x[1] == "--grow"));
+    }
+    assert!(!commands.iter().any(|x| x.len() == 7 && x[3] == "--replace" && x[6].ends_with("sdd-part2")));
+
+    assert!(commands.iter().any(|x| x.len() == 7 && x[4].ends_with("sda-part1") && x[6].ends_with("sdd-part1")));
+
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    let members: Vec<&str> = array.raid_map.values().flatten().filter_map(|x| x.path.as_deref()).collect();
+    assert!(!members.iter().any(|x| x.contains("sda")));
+    assert!(array.part_map["/dev/sdd"].len() == 2);
+}
+
+#[test]
+fn replace_reports_recovery_and_stops_waiting_once_cancelled() {
+    let (backend,runner,disks) = backend("replace-cancel");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+
+    let md = runner.commands()[0][2].to_string();
+    disks.set_kernel_name(&md,"md127");
+    fs::write(
+        &backend.mdstat_file,
+        "Personalities : [raid5]\nmd127 : active raid5 sdd1[3] sdc1[2] sdb1[1] sda1[0]\n      7808 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/3] [UUU]\n      [==>..................]  recovery = 12.6% (493/3904) finish=1.7min speed=35724K/sec\n\nunused devices: <none>\n"
+    ).unwrap();
+
+    let reported = Arc::new(Mutex::new(vec![]));
+    let backend = Backend {
+        progress: {
+            let reported = reported.clone();
+            let cancel = backend.cancel.clone();
+            Arc::new(move |name,progress| {
+                reported.lock().unwrap().push((name.to_string(),progress.percent));
+                cancel.cancel();
+            })
+        },
+        ..backend
+    };
+
+    let err = replace_disk(&backend,"test".to_string(),"/dev/sdb","/dev/sdd",false).unwrap_err();
+
+    assert_eq!(err,HyraidError::Cancelled);
+    assert_eq!(*reported.lock().unwrap(),vec![("md127".to_string(),12.6)]);
+    // the old member stays until md finished copying onto the new one
+    assert!(!runner.commands().contains(
+        &argv(&["mdadm","--manage",&md,"--remove","/dev/disk/by-partuuid/sdb-part1"])
+    ));
+}
+
+#[test]
+fn replace_matches_old_members_by_gpt_slice() {
+    let (backend,runner,disks) = backend("replace-slice");
+    disks.add_disk("/dev/sdd",DISK_SIZE / 2);
+    create_hyraid_array(&backend,"test".to_string(),&["/dev/sda","/dev/sdb","/dev/sdc","/dev/sdd"],5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sde",DISK_SIZE);
+    fs::write(&backend.mdstat_file,"").unwrap();
+
+    // both slices are the same size, so only the GPT tells the partitions of sdb apart
+    hyraid_json::update_array(&backend.state_file,"test",|x| x.part_map.get_mut("/dev/sdb").unwrap().reverse()).unwrap();
+
+    replace_disk(&backend,"test".to_string(),"/dev/sdb","/dev/sde",false).unwrap();
+
+    for (old,new) in [("sdb-part1","sde-part1"),("sdb-part2","sde-part2")] {
+        let replaced = runner.commands().into_iter().any(|x| {
+            x.len() == 7
+                && x[3] == "--replace"
+                && x[4] == format!("/dev/disk/by-partuuid/{}",old)
+                && x[6] == format!("/dev/disk/by-partuuid/{}",new)
+        });
+        assert!(replaced,"{} wasn't replaced with {}",old,new);
+    }
+}
+
+#[test]
+fn replace_refuses_smaller_disk() {
+    let (backend,runner,disks) = backend("replace-small");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE / 2);
+    let created = runner.commands().len();
+
+    let err = replace_disk(&backend,"test".to_string(),"/dev/sdb","/dev/sdd",false).unwrap_err();
+
+    assert!(matches!(err,HyraidError::Validation(_)));
+    assert_eq!(runner.commands().len(),created);
+}
+
+#[test]
+fn replace_with_larger_disks_grows_array() {
+    let (backend,runner,disks) = backend("grow");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE * 2);
+    disks.add_disk("/dev/sde",DISK_SIZE * 2);
+    fs::write(&backend.mdstat_file,"").unwrap();
+
+    replace_disk(&backend,"test".to_string(),"/dev/sdb","/dev/sdd",false).unwrap();
+    let report = upgrade_report(&backend,"test".to_string()).unwrap();
+    assert_eq!(report.swap_size,DISK_SIZE * 2);
+    assert!(report.usable_after_swap > report.usable);
+
+    // one larger disk alone leaves its extra slice unused
+    let replaced = runner.commands().len();
+    assert!(!runner.commands().iter().any(|x| x[0] == "vgextend"));
+
+    replace_disk(&backend,"test".to_string(),"/dev/sdc","/dev/sde",false).unwrap();
+
+    let commands = runner.commands()[replaced..].to_vec();
+    let create = commands.iter().find(|x| x[1] == "--create").unwrap();
+    let md = create[2].to_string();
+    assert!(create.contains(&"--level=1".to_string()));
+    assert!(create.contains(&"/dev/disk/by-partuuid/sdd-part2".to_string()));
+    assert!(create.contains(&"/dev/disk/by-partuuid/sde-part2".to_string()));
+    assert!(commands.contains(&argv(&["pvcreate",&md])));
+    assert!(commands.iter().any(|x| x[0] == "vgextend" && x.contains(&md)));
+
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    assert_eq!(array.slices.len(),2);
+    assert_eq!(array.raid_map[&md].len(),2);
+    assert_eq!(array.generation,4);
+
+    assert!(!grow_hyraid_array(&backend,"test".to_string(),false).unwrap());
+}
+
+#[test]
+fn replacing_the_small_disk_grows_mirror_to_raid5() {
+    let (backend,runner,disks) = backend("grow-mirror");
+    disks.add_disk("/dev/sdb",DISK_SIZE * 2);
+    disks.add_disk("/dev/sdc",DISK_SIZE * 2);
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE * 2);
+    fs::write(&backend.mdstat_file,"").unwrap();
+    let before = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    let (mirror,_) = before.raid_map.iter().find(|(_,members)| members.len() == 2).unwrap();
+    let created = runner.commands().len();
+
+    replace_disk(&backend,"test".to_string(),"/dev/sda","/dev/sdd",false).unwrap();
+
+    let commands = runner.commands()[created..].to_vec();
+    let added = commands
+        .iter()
+        .position(|x| *x == argv(&["mdadm","--manage",mirror,"--add","/dev/disk/by-partuuid/sdd-part2"]))
+        .unwrap();
+    let grown: Vec<Vec<String>> = commands[added + 1..].iter().filter(|x| x.contains(mirror)).cloned().collect();
+    assert_eq!(grown,vec![
+        argv(&["mdadm","--grow",mirror,"--level=5","--raid-devices=2"]),
+        argv(&["mdadm","--grow",mirror,"--raid-devices=3"]),
+        argv(&["pvresize",mirror]),
+    ]);
+
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    let mut members: Vec<String> = array.raid_map[mirror].iter().filter_map(|x| x.path.clone()).collect();
+    members.sort();
+    assert_eq!(members,vec![
+        "/dev/disk/by-partuuid/sdb-part2",
+        "/dev/disk/by-partuuid/sdc-part2",
+        "/dev/disk/by-partuuid/sdd-part2"
+    ]);
+}
+
+#[test]
+fn replace_refuses_growing_below_policy_before_touching_new_disk() {
+    let (backend,runner,disks) = backend("grow-policy");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,6,RedundancyPolicy::TwoDisk,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE * 2);
+    disks.add_disk("/dev/sde",DISK_SIZE * 2);
+    fs::write(&backend.mdstat_file,"").unwrap();
+    replace_disk(&backend,"test".to_string(),"/dev/sdb","/dev/sdd",false).unwrap();
+    let replaced = runner.commands().len();
+
+    // the extra slice of sdd and sde would only make a 2-way mirror
+    let err = replace_disk(&backend,"test".to_string(),"/dev/sdc","/dev/sde",false).unwrap_err();
+
+    assert!(matches!(err,HyraidError::Validation(_)));
+    assert_eq!(runner.commands().len(),replaced);
+    assert!(disks.partitions("/dev/sde").unwrap().is_empty());
+
+    replace_disk(&backend,"test".to_string(),"/dev/sdc","/dev/sde",true).unwrap();
+    let create = runner.commands()[replaced..].iter().find(|x| x[1] == "--create").unwrap().to_vec();
+    assert!(create.contains(&"--level=1".to_string()));
+    assert!(create.contains(&"--raid-devices=2".to_string()));
+}
+
+#[test]
+fn status_takes_raid_level_from_mdstat() {
+    let (backend,runner,disks) = backend("status-level");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    let md = runner.commands()[0][2].to_string();
+    disks.set_kernel_name(&md,"md127");
+
+    // not assembled, so going by the state file
+    fs::write(&backend.mdstat_file,"Personalities : [raid5]\n\nunused devices: <none>\n").unwrap();
+    assert_eq!(hyraid_array_status(&backend,"test".to_string()).unwrap().md_devices[0].raid_level,5);
+
+    // md still mirrors, e.g. half way through a migration
+    fs::write(
+        &backend.mdstat_file,
+        "Personalities : [raid1]\nmd127 : active raid1 sdc1[2] sdb1[1] sda1[0]\n      3904 blocks super 1.2 [3/3] [UUU]\n\nunused devices: <none>\n"
+    ).unwrap();
+    assert_eq!(hyraid_array_status(&backend,"test".to_string()).unwrap().md_devices[0].raid_level,1);
+}
+
+#[test]
+fn two_disk_policy_uses_three_way_mirror_on_small_groups() {
+    let (backend,runner,_) = backend("two-disk");
+
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,6,RedundancyPolicy::TwoDisk,false).unwrap();
+
+    let create = &runner.commands()[0];
+    assert!(create.contains(&"--level=1".to_string()));
+    assert!(create.contains(&"--raid-devices=3".to_string()));
+
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    assert_eq!(array.policy,RedundancyPolicy::TwoDisk);
+}
+
+#[test]
+fn two_disk_policy_needs_raid_level_6() {
+    let (backend,runner,_) = backend("two-disk-level");
+
+    let err = create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::TwoDisk,false).unwrap_err();
+
+    assert!(matches!(err,HyraidError::Validation(_)));
+    assert!(runner.commands().is_empty());
+}
+
+#[test]
+fn plan_warns_about_groups_below_policy() {
+    let disks = [
+        ("/dev/sda",DISK_SIZE),
+        ("/dev/sdb",DISK_SIZE),
+        ("/dev/sdc",DISK_SIZE),
+        ("/dev/sdd",DISK_SIZE),
+        ("/dev/sde",DISK_SIZE * 2),
+        ("/dev/sdf",DISK_SIZE * 2)
+    ];
+
+    let plan = plan_create(&disks,6,RedundancyPolicy::TwoDisk).unwrap();
+    let mut levels: Vec<(usize,usize)> = plan.md_create.iter().map(|x| (x.raid_level,x.partitions.len())).collect();
+    levels.sort();
+    assert_eq!(levels,vec![(1,2),(6,6)]);
+    assert_eq!(plan.redundancy.degraded().len(),1);
+
+    // plain RAID6 falls back to a 2-way mirror just the same
+    assert_eq!(plan_create(&disks,6,RedundancyPolicy::RaidLevel).unwrap().redundancy.degraded().len(),1);
+
+    let plan = plan_create(&disks[..3],6,RedundancyPolicy::TwoDisk).unwrap();
+    assert!(plan.redundancy.degraded().is_empty());
+}
+
+#[test]
+fn create_refuses_degraded_layout_unless_allowed() {
+    let (backend,runner,disks) = backend("degraded");
+    disks.add_disk("/dev/sdd",DISK_SIZE * 2);
+    disks.add_disk("/dev/sde",DISK_SIZE * 2);
+    let layout = ["/dev/sda","/dev/sdb","/dev/sdd","/dev/sde"];
+
+    // the extra space of sdd and sde only makes a 2-way mirror
+    let err = create_hyraid_array(&backend,"test".to_string(),&layout,6,RedundancyPolicy::RaidLevel,false).unwrap_err();
+    assert!(matches!(err,HyraidError::Validation(_)));
+    assert!(runner.commands().is_empty());
+
+    create_hyraid_array(&backend,"test".to_string(),&layout,6,RedundancyPolicy::RaidLevel,true).unwrap();
+    assert_eq!(hyraid_json::read_arrays(&backend.state_file).unwrap().len(),1);
+}
+
+#[test]
+fn plan_reports_fault_tolerance_and_unused_space() {
+    let disks = [("/dev/sda",DISK_SIZE),("/dev/sdb",DISK_SIZE),("/dev/sdc",DISK_SIZE * 3)];
+
+    let report = plan_create(&disks,5,RedundancyPolicy::RaidLevel).unwrap().redundancy;
+
+    assert_eq!(report.target,1);
+    assert_eq!(report.groups.len(),1);
+    assert_eq!(report.groups[0].raid_level,5);
+    assert_eq!(report.groups[0].failures_tolerated,1);
+    assert_eq!(report.unused,vec![("/dev/sdc".to_string(),DISK_SIZE * 2)]);
+    assert!(report.degraded().is_empty());
+}
+
+#[test]
+fn evacuate_reshapes_md_device_to_fewer_members() {
+    let (backend,runner,disks) = backend("evacuate");
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+    let layout = ["/dev/sda","/dev/sdb","/dev/sdc","/dev/sdd"];
+    create_hyraid_array(&backend,"test".to_string(),&layout,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    fs::write(&backend.mdstat_file,"").unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    let vg = runner.commands()[2][1].to_string();
+    let created = runner.commands().len();
+    // 1 KiB extents after 1 MiB of metadata, none allocated past the new size
+    runner.respond(&["pvs","--noheadings","--nosuffix"],stdout(&format!("  {} 1048576 10694 5000 1024\n",md)));
+    runner.respond(&["pvs","--noheadings","--segments"],stdout("  0 5000 lvol0\n  5000 5694\n"));
+    // md made the member of sda a spare once reshaped
+    runner.respond(&["mdadm","--detail"],stdout(
+        "MD_DEVICE_dev_sda-part1_ROLE=spare\nMD_DEVICE_dev_sda-part1_DEV=/dev/sda-part1\nMD_DEVICE_dev_sdb-part1_ROLE=0\nMD_DEVICE_dev_sdb-part1_DEV=/dev/sdb-part1\n"
+    ));
+
+    evacuate_disk(&backend,"test".to_string(),"/dev/sda").unwrap();
+
+    let capacity = (DISK_SIZE * 2 - 1048576) / 1024;
+    let backup_file = format!("--backup-file={}.{}.backup",backend.state_file,md.rsplit('/').next().unwrap());
+    let commands = runner.commands()[created..].to_vec();
+    // the member stays in place until md reshaped around it, so md never runs degraded
+    assert_eq!(commands[2..].to_vec(),vec![
+        argv(&["pvresize","-y","--setphysicalvolumesize",&format!("{}b",1048576 + capacity * 1024),&md]),
+        argv(&["mdadm","--grow",&md,&format!("--array-size={}",DISK_SIZE * 2 / 1024)]),
+        argv(&["mdadm","--grow",&md,"--raid-devices=3",&backup_file]),
+        argv(&["mdadm","--detail","--export",&md]),
+        argv(&["mdadm","--manage",&md,"--remove","/dev/disk/by-partuuid/sda-part1"]),
+        argv(&["mdadm","--zero-superblock","/dev/disk/by-partuuid/sda-part1"]),
+    ]);
+    assert!(commands[0].contains(&format!("vg_name={}",vg)));
+    assert!(disks.operations().contains(
+        &argv(&["remove_partitions","/dev/sda","/dev/disk/by-partuuid/sda-part1"])
+    ));
+
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    assert_eq!(array.raid_map[&md].len(),3);
+    assert_eq!(array.disks.len(),3);
+    assert!(!array.part_map.contains_key("/dev/sda"));
+}
+
+#[test]
+fn evacuate_replaces_member_md_left_active_with_spare() {
+    let (backend,runner,disks) = backend("evacuate-spare");
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+    let layout = ["/dev/sda","/dev/sdb","/dev/sdc","/dev/sdd"];
+    create_hyraid_array(&backend,"test".to_string(),&layout,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    fs::write(&backend.mdstat_file,"").unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    runner.respond(&["pvs","--noheadings","--nosuffix"],stdout(&format!("  {} 1048576 10694 5000 1024\n",md)));
+    runner.respond(&["pvs","--noheadings","--segments"],stdout("  0 5000 lvol0\n"));
+    // md made the member in the last slot a spare, not the one of sda
+    runner.respond(&["mdadm","--detail"],stdout(
+        "MD_DEVICE_dev_sda-part1_ROLE=0\nMD_DEVICE_dev_sda-part1_DEV=/dev/sda-part1\nMD_DEVICE_dev_sdd-part1_ROLE=spare\nMD_DEVICE_dev_sdd-part1_DEV=/dev/sdd-part1\n"
+    ));
+
+    evacuate_disk(&backend,"test".to_string(),"/dev/sda").unwrap();
+
+    let commands = runner.commands();
+    let detail = commands.iter().position(|x| x[1] == "--detail").unwrap();
+    assert_eq!(commands[detail + 1..detail + 3].to_vec(),vec![
+        argv(&["mdadm","--manage",&md,"--replace","/dev/disk/by-partuuid/sda-part1","--with","/dev/sdd-part1"]),
+        argv(&["mdadm","--manage",&md,"--remove","/dev/disk/by-partuuid/sda-part1"]),
+    ]);
+}
+
+#[test]
+fn evacuate_refuses_without_free_space() {
+    let (backend,runner,_) = backend("evacuate-full");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+
+    // RAID5 of 3 falls back to a mirror, which needs its extents moved elsewhere first
+    let md = runner.commands()[0][2].to_string();
+    runner.respond(&["pvs","--noheadings","--nosuffix"],stdout(&format!("  {} 1048576 6835 100 1024\n",md)));
+    let created = runner.commands().len();
+
+    let err = evacuate_disk(&backend,"test".to_string(),"/dev/sda").unwrap_err();
+
+    assert!(matches!(err,HyraidError::Validation(_)));
+    assert_eq!(runner.commands().len(),created + 1);
+    assert_eq!(hyraid_json::read_arrays(&backend.state_file).unwrap()[0].part_map.len(),3);
+}
+
+#[test]
+fn add_migrates_mirror_to_raid5() {
+    let (backend,runner,disks) = backend("migrate");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS[..2],5,RedundancyPolicy::RaidLevel,true).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+    fs::write(&backend.mdstat_file,"").unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    let created = runner.commands().len();
+
+    // the mirror survives a single failure throughout, as much as RAID5 asks for
+    let plan = plan_add(&backend,"test".to_string(),&[("/dev/sdd",DISK_SIZE)]).unwrap();
+    let steps: Vec<(Option<usize>,usize,usize)> = plan.md_grow[0].migration
+        .iter()
+        .map(|x| (x.raid_level,x.raid_devices,x.failures_tolerated))
+        .collect();
+    assert_eq!(steps,vec![(Some(5),2,1),(None,3,1)]);
+
+    add_disk_to_hyraid_array(&backend,"test".to_string(),&["/dev/sdd"],false).unwrap();
+
+    assert_eq!(runner.commands()[created..].to_vec(),vec![
+        argv(&["mdadm","--manage",&md,"--add","/dev/disk/by-partuuid/sdd-part1"]),
+        argv(&["mdadm","--grow",&md,"--level=5","--raid-devices=2"]),
+        argv(&["mdadm","--grow",&md,"--raid-devices=3"]),
+        argv(&["pvresize",&md]),
+    ]);
+}
+
+#[test]
+fn add_migrates_three_way_mirror_to_raid6() {
+    let (backend,runner,disks) = backend("migrate-raid6");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,6,RedundancyPolicy::TwoDisk,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+    fs::write(&backend.mdstat_file,"").unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    let created = runner.commands().len();
+
+    // md only converts a 2-way mirror, so the migration survives a single failure part way
+    let plan = plan_add(&backend,"test".to_string(),&[("/dev/sdd",DISK_SIZE)]).unwrap();
+    let steps: Vec<(Option<usize>,usize,usize)> = plan.md_grow[0].migration
+        .iter()
+        .map(|x| (x.raid_level,x.raid_devices,x.failures_tolerated))
+        .collect();
+    assert_eq!(steps,vec![
+        (None,2,1),
+        (Some(5),2,1),
+        (None,3,1),
+        (Some(6),4,2),
+    ]);
+    assert_eq!(plan.redundancy.groups[0].failures_tolerated,1);
+
+    let err = add_disk_to_hyraid_array(&backend,"test".to_string(),&["/dev/sdd"],false).unwrap_err();
+    assert!(matches!(err,HyraidError::Validation(_)));
+    assert_eq!(runner.commands().len(),created);
+
+    add_disk_to_hyraid_array(&backend,"test".to_string(),&["/dev/sdd"],true).unwrap();
+
+    assert_eq!(runner.commands()[created + 1..].to_vec(),vec![
+        argv(&["mdadm","--grow",&md,"--raid-devices=2"]),
+        argv(&["mdadm","--grow",&md,"--level=5","--raid-devices=2"]),
+        argv(&["mdadm","--grow",&md,"--raid-devices=3"]),
+        argv(&["mdadm","--grow",&md,"--level=6","--raid-devices=4"]),
+        argv(&["pvresize",&md]),
+    ]);
+}
+
+#[test]
+fn spare_is_attached_to_md_device_and_kept_out_of_disks() {
+    let (backend,runner,disks) = backend("spare");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+
+    let md = runner.commands()[0][2].to_string();
+    let created = runner.commands().len();
+
+    add_spare(&backend,"test".to_string(),"/dev/sdd").unwrap();
+
+    assert_eq!(runner.commands()[created..].to_vec(),vec![
+        argv(&["mdadm","--manage",&md,"--add","/dev/disk/by-partuuid/sdd-part1"]),
+    ]);
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    assert_eq!(array.spares["/dev/sdd"].len(),1);
+    assert_eq!(array.disks.len(),3);
+    assert!(!array.part_map.contains_key("/dev/sdd"));
+
+    // a disk can't be a spare twice
+    assert!(add_global_spare(&backend,"/dev/sdd").is_err());
+
+    runner.respond(&["mdadm","--detail"],stdout("MD_DEVICE_dev_sdd-part1_ROLE=spare\nMD_DEVICE_dev_sdd-part1_DEV=/dev/sdd-part1\n"));
+    let detached = runner.commands().len();
+
+    remove_spare(&backend,"test".to_string(),"/dev/sdd").unwrap();
+
+    assert_eq!(runner.commands()[detached + 1..].to_vec(),vec![
+        argv(&["mdadm","--manage",&md,"--remove","/dev/disk/by-partuuid/sdd-part1"]),
+        argv(&["mdadm","--zero-superblock","/dev/disk/by-partuuid/sdd-part1"]),
+    ]);
+    assert!(hyraid_json::read_arrays(&backend.state_file).unwrap()[0].spares.is_empty());
+}
+
+#[test]
+fn remove_spare_refuses_once_rebuilt_onto() {
+    let (backend,runner,disks) = backend("spare-active");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+    add_spare(&backend,"test".to_string(),"/dev/sdd").unwrap();
+    runner.respond(&["mdadm","--detail"],stdout("MD_DEVICE_dev_sdd-part1_ROLE=0\nMD_DEVICE_dev_sdd-part1_DEV=/dev/sdd-part1\n"));
+
+    let err = remove_spare(&backend,"test".to_string(),"/dev/sdd").unwrap_err();
+
+    assert!(matches!(err,HyraidError::Validation(_)));
+    assert_eq!(hyraid_json::read_arrays(&backend.state_file).unwrap()[0].spares.len(),1);
+}
+
+#[test]
+fn degraded_array_takes_global_spare() {
+    let (backend,runner,disks) = backend("global-spare");
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE / 2);
+    disks.add_disk("/dev/sde",DISK_SIZE);
+    add_global_spare(&backend,"/dev/sdd").unwrap();
+    add_global_spare(&backend,"/dev/sde").unwrap();
+
+    let md = runner.commands()[0][2].to_string();
+    disks.set_kernel_name(&md,"md127");
+    fs::write(&backend.mdstat_file,"").unwrap();
+    assert_eq!(attach_global_spare(&backend,"test".to_string()).unwrap(),None);
+
+    fs::write(
+        &backend.mdstat_file,
+        "Personalities : [raid5]\nmd127 : active raid5 sdc1[2](F) sdb1[1] sda1[0]\n      7808 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [UU_]\n\nunused devices: <none>\n"
+    ).unwrap();
+
+    // sdd is too small for the degraded slice
+    assert_eq!(attach_global_spare(&backend,"test".to_string()).unwrap(),Some("/dev/sde".to_string()));
+
+    assert!(runner.commands().contains(
+        &argv(&["mdadm","--manage",&md,"--add","/dev/disk/by-partuuid/sde-part1"])
+    ));
+    let document = hyraid_json::read_document(&backend.state_file).unwrap();
+    assert_eq!(document.global_spares,vec!["/dev/sdd".to_string()]);
+    assert!(document.arrays[0].spares.contains_key("/dev/sde"));
+}
+
+#[test]
+fn cancelled_create_leaves_disks_alone() {
+    let (backend,runner,disks) = backend("cancel");
+    backend.cancel.cancel();
+
+    let err = create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap_err();
+
+    assert_eq!(err,HyraidError::Cancelled);
+    assert!(disks.operations().is_empty());
+    assert!(runner.commands().is_empty());
+    assert!(hyraid_json::read_arrays(&backend.state_file).unwrap().is_empty());
+}
@@ -0,0 +1,132 @@
+/*!
+    Tests for resuming and rolling back operations from their journal.
+*/
+
+use std::sync::Arc;
+
+use hyraid_gpt::{BlockDevice, FakeDisks};
+use hyraid_mapper::{
+    Backend,
+    create_hyraid_array,
+    add_disk_to_hyraid_array,
+    pending_operation,
+    resume_operation,
+    rollback_operation
+};
+use hyraid_types::{JournalStep, RedundancyPolicy, StepState};
+use hyraid_utils::{CommandOutput, RecordingRunner};
+
+const DISK_SIZE: usize = 4_000_000;
+const DISKS: [&str; 3] = ["/dev/sda","/dev/sdb","/dev/sdc"];
+
+fn backend(test: &str) -> (Backend,Arc<RecordingRunner>,Arc<FakeDisks>) {
+    let state_file = std::env::temp_dir()
+        .join(format!("hyraid-journal-{}-{}.json",test,std::process::id()))
+        .to_string_lossy()
+        .to_string();
+
+    let (backend,runner,disks) = Backend::fake(&state_file);
+    for disk in DISKS {
+        disks.add_disk(disk,DISK_SIZE);
+    }
+    (backend,runner,disks)
+}
+
+fn create(backend: &Backend) -> Result<String,hyraid_utils::HyraidError> {
+    create_hyraid_array(backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false)
+}
+
+#[test]
+fn finished_operation_leaves_no_journal() {
+    let (backend,_,_) = backend("finished");
+    create(&backend).unwrap();
+    assert!(pending_operation(&backend).unwrap().is_none());
+}
+
+#[test]
+fn interrupted_create_is_resumed() {
+    let (backend,runner,_) = backend("resume");
+    runner.respond(&["vgcreate"],Err(Some(5)));
+    assert!(create(&backend).is_err());
+
+    let journal = pending_operation(&backend).unwrap().unwrap();
+    let started: Vec<&JournalStep> = journal.steps
+        .iter()
+        .filter(|x| x.state == StepState::Started)
+        .map(|x| &x.step)
+        .collect();
+    assert!(matches!(started[..],[JournalStep::CreateVg { .. }]));
+    assert!(hyraid_json::read_arrays(&backend.state_file).unwrap().is_empty());
+
+    // nothing else may touch the disks until it's recovered
+    let err = create_hyraid_array(&backend,"other".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap_err();
+    assert_eq!(err.to_string(),"Unfinished create of array test found, run `hyraid recover` first");
+
+    runner.respond(&["vgcreate"],Ok(CommandOutput::default()));
+    let ran = runner.commands().len();
+    resume_operation(&backend).unwrap();
+
+    let commands: Vec<String> = runner.commands()[ran..].iter().map(|x| x[0].to_string()).collect();
+    assert_eq!(commands,vec!["pvs","vgcreate","lvcreate"]);
+    let arrays = hyraid_json::read_arrays(&backend.state_file).unwrap();
+    assert_eq!(arrays[0].lvm_lv_path,journal.entry.unwrap().lvm_lv_path);
+    assert!(pending_operation(&backend).unwrap().is_none());
+}
+
+#[test]
+fn interrupted_create_is_rolled_back() {
+    let (backend,runner,disks) = backend("rollback");
+    runner.respond(&["lvcreate"],Err(Some(5)));
+    assert!(create(&backend).is_err());
+    let md = runner.commands()[0][2].to_string();
+    let vg = runner.commands()[2][1].to_string();
+
+    let ran = runner.commands().len();
+    rollback_operation(&backend).unwrap();
+
+    let commands: Vec<Vec<String>> = runner.commands()[ran..].iter().map(|x| x[..2].to_vec()).collect();
+    assert_eq!(commands,vec![
+        vec!["lvchange".to_string(),"-an".to_string()],
+        vec!["lvremove".to_string(),"-y".to_string()],
+        vec!["vgremove".to_string(),"-y".to_string()],
+        vec!["pvremove".to_string(),"-y".to_string()],
+        vec!["mdadm".to_string(),"--stop".to_string()],
+        vec!["mdadm".to_string(),"--zero-superblock".to_string()],
+    ]);
+    assert!(runner.commands()[ran..].iter().any(|x| x.contains(&vg)));
+    assert!(runner.commands()[ran..].iter().any(|x| x.contains(&md)));
+    for disk in DISKS {
+        assert!(disks.partitions(disk).unwrap().is_empty());
+    }
+    assert!(pending_operation(&backend).unwrap().is_none());
+
+    // the disks are free again
+    runner.respond(&["lvcreate"],Ok(CommandOutput::default()));
+    create(&backend).unwrap();
+}
+
+#[test]
+fn grown_md_device_can_only_be_resumed() {
+    let (backend,runner,disks) = backend("grow");
+    create(&backend).unwrap();
+    disks.add_disk("/dev/sdd",DISK_SIZE);
+    runner.respond(&["pvresize"],Err(Some(5)));
+    assert!(add_disk_to_hyraid_array(&backend,"test".to_string(),&["/dev/sdd"],false).is_err());
+
+    assert!(rollback_operation(&backend).unwrap_err().to_string().contains("resume it instead"));
+
+    // md took the new partition and reshaped, only resizing the physical volume is left
+    let md = runner.commands()[0][2].to_string();
+    runner.respond(&["mdadm","--detail","--export",&md],Ok(CommandOutput {
+        stdout: "MD_LEVEL=raid5\nMD_DEVICES=4\n".to_string(),
+        stderr: String::new()
+    }));
+    runner.respond(&["pvresize"],Ok(CommandOutput::default()));
+    let ran = runner.commands().len();
+    resume_operation(&backend).unwrap();
+
+    let commands: Vec<String> = runner.commands()[ran..].iter().map(|x| x[..2].join(" ")).collect();
+    assert_eq!(commands,vec!["mdadm --detail".to_string(),format!("pvresize {}",md)]);
+    let array = hyraid_json::read_arrays(&backend.state_file).unwrap().remove(0);
+    assert_eq!((array.generation,array.raid_map[&md].len()),(1,4));
+}
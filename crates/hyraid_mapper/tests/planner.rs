@@ -0,0 +1,198 @@
+/*!
+    Tests for planning layouts without touching any disk.
+*/
+
+use std::sync::Arc;
+
+use hyraid_gpt::FakeDisks;
+use hyraid_mapper::{Backend, create_hyraid_array, estimate_capacity, plan_add, plan_create};
+use hyraid_types::{CapacityEstimate, DiskPartition, LvmPlan, RedundancyPolicy};
+use hyraid_utils::{HyraidError, RecordingRunner};
+
+const DISK_SIZE: usize = 4_000_000;
+const TB: usize = 1_000_000_000_000;
+
+fn backend(test: &str) -> (Backend,Arc<RecordingRunner>,Arc<FakeDisks>) {
+    let state_file = std::env::temp_dir()
+        .join(format!("hyraid-planner-{}-{}.json",test,std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    Backend::fake(&state_file)
+}
+
+fn paths(partitions: &[DiskPartition]) -> Vec<String> {
+    let mut paths: Vec<String> = partitions.iter().filter_map(|x| x.path.clone()).collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn plan_add_extends_md_device_with_only_new_partitions() {
+    let (backend,runner,disks) = backend("add-members");
+    disks.add_disk("/dev/sda",DISK_SIZE);
+    disks.add_disk("/dev/sdb",DISK_SIZE * 2);
+    disks.add_disk("/dev/sdc",DISK_SIZE * 2);
+    create_hyraid_array(&backend,"test".to_string(),&["/dev/sda","/dev/sdb","/dev/sdc"],5,RedundancyPolicy::RaidLevel,true).unwrap();
+    let array = hyraid_mapper::list_hyraid_arrays(&backend).unwrap().remove(0);
+    let (md,_) = array.raid_map.iter().find(|(_,members)| members.len() == 3).unwrap();
+    let created = runner.commands().len();
+
+    // sdd sorts after sdb and sdc, which have more partitions, so the members of md aren't at the end of its slice group
+    let plan = plan_add(&backend,"test".to_string(),&[("/dev/sdd",DISK_SIZE)]).unwrap();
+
+    assert!(plan.md_create.is_empty());
+    assert_eq!(plan.md_grow.len(),1);
+    assert_eq!(&plan.md_grow[0].device,md);
+    assert_eq!(paths(&plan.md_grow[0].partitions),vec!["/dev/sdd (new partition 1)".to_string()]);
+    assert_eq!(runner.commands().len(),created);
+}
+
+#[test]
+fn plan_create_makes_one_md_device_per_slice() {
+    let disks = [("/dev/sda",DISK_SIZE),("/dev/sdb",DISK_SIZE),("/dev/sdc",DISK_SIZE * 2),("/dev/sdd",DISK_SIZE * 2)];
+
+    let plan = plan_create(&disks,5,RedundancyPolicy::RaidLevel).unwrap();
+
+    assert_eq!(plan.slices,vec![DISK_SIZE,DISK_SIZE]);
+    assert_eq!(paths(&plan.part_map["/dev/sdc"]),vec![
+        "/dev/sdc (new partition 1)".to_string(),
+        "/dev/sdc (new partition 2)".to_string()
+    ]);
+    let mut md_create: Vec<(usize,Vec<String>)> = plan.md_create
+        .iter()
+        .map(|x| (x.raid_level,paths(&x.partitions)))
+        .collect();
+    md_create.sort();
+    assert_eq!(md_create,vec![
+        (1,vec!["/dev/sdc (new partition 2)".to_string(),"/dev/sdd (new partition 2)".to_string()]),
+        (5,vec![
+            "/dev/sda (new partition 1)".to_string(),
+            "/dev/sdb (new partition 1)".to_string(),
+            "/dev/sdc (new partition 1)".to_string(),
+            "/dev/sdd (new partition 1)".to_string()
+        ]),
+    ]);
+    assert!(plan.md_grow.is_empty());
+    match plan.lvm {
+        LvmPlan::Create { mut physical_volumes } => {
+            let mut devices: Vec<String> = plan.md_create.iter().map(|x| x.device.to_string()).collect();
+            physical_volumes.sort();
+            devices.sort();
+            assert_eq!(physical_volumes,devices);
+        },
+        lvm => panic!("unexpected LVM plan: {:?}",lvm)
+    }
+}
+
+#[test]
+fn plan_create_leaves_out_slice_of_one_disk() {
+    let disks = [("/dev/sda",DISK_SIZE),("/dev/sdb",DISK_SIZE),("/dev/sdc",DISK_SIZE * 3)];
+
+    let plan = plan_create(&disks,5,RedundancyPolicy::RaidLevel).unwrap();
+
+    assert_eq!(plan.md_create.len(),1);
+    assert_eq!(plan.md_create[0].partitions.len(),3);
+    assert_eq!(plan.redundancy.unused,vec![("/dev/sdc".to_string(),DISK_SIZE * 2)]);
+}
+
+#[test]
+fn plan_create_mirrors_raid6_slices_of_fewer_than_four_disks() {
+    let disks = [("/dev/sda",DISK_SIZE),("/dev/sdb",DISK_SIZE),("/dev/sdc",DISK_SIZE * 2),("/dev/sdd",DISK_SIZE * 2),("/dev/sde",DISK_SIZE * 2)];
+
+    let plan = plan_create(&disks,6,RedundancyPolicy::RaidLevel).unwrap();
+
+    let mut levels: Vec<(usize,usize)> = plan.md_create.iter().map(|x| (x.partitions.len(),x.raid_level)).collect();
+    levels.sort();
+    assert_eq!(levels,vec![(3,1),(5,6)]);
+
+    let report = estimate_capacity(&[4 * TB,4 * TB,4 * TB],6,RedundancyPolicy::RaidLevel).unwrap();
+    assert_eq!(usable(&report.hyraid),usable(&estimate(4,8,0)));
+}
+
+#[test]
+fn plan_create_refuses_bad_input() {
+    assert!(matches!(plan_create(&[],5,RedundancyPolicy::RaidLevel),Err(HyraidError::Validation(_))));
+    let disks = [("/dev/sda",DISK_SIZE),("/dev/sdb",DISK_SIZE)];
+    assert!(matches!(plan_create(&disks,4,RedundancyPolicy::RaidLevel),Err(HyraidError::Validation(_))));
+}
+
+#[test]
+fn plan_add_creates_md_device_for_new_slice() {
+    let (backend,runner,disks) = backend("add-slice");
+    for disk in ["/dev/sda","/dev/sdb","/dev/sdc"] {
+        disks.add_disk(disk,DISK_SIZE);
+    }
+    create_hyraid_array(&backend,"test".to_string(),&["/dev/sda","/dev/sdb","/dev/sdc"],5,RedundancyPolicy::RaidLevel,false).unwrap();
+    let md = runner.commands()[0][2].to_string();
+    let created = runner.commands().len();
+
+    let plan = plan_add(&backend,"test".to_string(),&[("/dev/sdd",DISK_SIZE * 2),("/dev/sde",DISK_SIZE * 2)]).unwrap();
+
+    assert_eq!(plan.slices,vec![DISK_SIZE,DISK_SIZE]);
+    assert_eq!(plan.md_grow.len(),1);
+    assert_eq!((plan.md_grow[0].device.as_str(),plan.md_grow[0].raid_level),(md.as_str(),5));
+    assert_eq!(paths(&plan.md_grow[0].partitions),vec![
+        "/dev/sdd (new partition 1)".to_string(),
+        "/dev/sde (new partition 1)".to_string()
+    ]);
+    assert_eq!(plan.md_create.len(),1);
+    assert_eq!(plan.md_create[0].raid_level,1);
+    assert_eq!(paths(&plan.md_create[0].partitions),vec![
+        "/dev/sdd (new partition 2)".to_string(),
+        "/dev/sde (new partition 2)".to_string()
+    ]);
+    match plan.lvm {
+        LvmPlan::Extend { created, resized, .. } => {
+            assert_eq!(created,vec![plan.md_create[0].device.to_string()]);
+            assert_eq!(resized,vec![md]);
+        },
+        lvm => panic!("unexpected LVM plan: {:?}",lvm)
+    }
+    assert_eq!(runner.commands().len(),created);
+}
+
+#[test]
+fn plan_add_needs_existing_array() {
+    let (backend,_,_) = backend("add-missing");
+
+    let err = plan_add(&backend,"test".to_string(),&[("/dev/sdd",DISK_SIZE)]).unwrap_err();
+
+    assert_eq!(err.to_string(),"No such HyRAID array: test");
+}
+
+fn estimate(usable: usize, redundancy: usize, unused: usize) -> CapacityEstimate {
+    CapacityEstimate { usable: usable * TB, redundancy: redundancy * TB, unused: unused * TB }
+}
+
+fn usable(estimate: &CapacityEstimate) -> (usize,usize,usize) {
+    (estimate.usable,estimate.redundancy,estimate.unused)
+}
+
+#[test]
+fn capacity_of_mixed_disks() {
+    let report = estimate_capacity(&[4 * TB,4 * TB,8 * TB,12 * TB],5,RedundancyPolicy::RaidLevel).unwrap();
+
+    assert_eq!(report.raw,28 * TB);
+    // RAID5 of 4 x 4T, a mirror of the next 4T of the two larger disks, 4T of the largest left over
+    assert_eq!(usable(&report.hyraid),usable(&estimate(16,8,4)));
+    assert_eq!(usable(report.raid1.as_ref().unwrap()),usable(&estimate(4,12,12)));
+    assert_eq!(usable(report.raid5.as_ref().unwrap()),usable(&estimate(12,4,12)));
+    assert_eq!(usable(report.raid6.as_ref().unwrap()),usable(&estimate(8,8,12)));
+}
+
+#[test]
+fn capacity_without_enough_disks_for_classic_raid() {
+    let report = estimate_capacity(&[4 * TB,8 * TB],5,RedundancyPolicy::RaidLevel).unwrap();
+
+    // two disks only make a mirror, the rest of the larger one is left over
+    assert_eq!(usable(&report.hyraid),usable(&estimate(4,4,4)));
+    assert!(report.raid1.is_some());
+    assert!(report.raid5.is_none());
+    assert!(report.raid6.is_none());
+
+    let report = estimate_capacity(&[4 * TB],5,RedundancyPolicy::RaidLevel).unwrap();
+    assert_eq!(usable(&report.hyraid),usable(&estimate(0,0,4)));
+    assert!(report.raid1.is_none());
+
+    assert!(matches!(estimate_capacity(&[],5,RedundancyPolicy::RaidLevel),Err(HyraidError::Validation(_))));
+}
@@ -0,0 +1,173 @@
+/*!
+    mdadm bindings
+
+    Copyright (C) 2025 LIZARD-OFFICIAL-77
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::process::Command;
+use hyraid_utils::{CommandRunner, HyraidError};
+use hyraid_mdstat::{MdDetail, parse_detail};
+
+/// Create MD RAID array
+pub fn create_raid_array(runner: &dyn CommandRunner, device: &str, partitions: &[&str], raid_level: usize) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--create");
+    output.arg(device);
+    output.arg("--run");
+    output.arg(format!("--level={}",raid_level));
+    output.arg(format!("--raid-devices={}",partitions.len()));
+    output.args(partitions);
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Mark devices of MD RAID array as faulty
+pub fn fail_from_raid_array(runner: &dyn CommandRunner, device: &str, partitions: &[&str]) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--manage");
+    output.arg(device);
+    output.arg("--fail");
+    output.args(partitions);
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Remove devices from MD RAID array
+pub fn remove_from_raid_array(runner: &dyn CommandRunner, device: &str, partitions: &[&str]) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--manage");
+    output.arg(device);
+    output.arg("--remove");
+    output.args(partitions);
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Add devices to MD RAID array
+pub fn add_to_raid_array(runner: &dyn CommandRunner, device: &str, partitions: &[&str]) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--manage");
+    output.arg(device);
+    output.arg("--add");
+    output.args(partitions);
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Read the members and their roles of MD RAID array
+pub fn detail_raid_array(runner: &dyn CommandRunner, device: &str) -> Result<MdDetail,HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--detail");
+    output.arg("--export");
+    output.arg(device);
+    let output = runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(parse_detail(&output.stdout))
+}
+
+/// Stop MD RAID array
+pub fn stop_raid_array(runner: &dyn CommandRunner, device: &str) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--stop");
+    output.arg(device);
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Erase the MD superblock of devices
+pub fn zero_superblock(runner: &dyn CommandRunner, partitions: &[&str]) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--zero-superblock");
+    output.args(partitions);
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Read the MD superblock of a device
+pub fn examine_partition(runner: &dyn CommandRunner, partition: &str) -> Result<MdDetail,HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--examine");
+    output.arg("--export");
+    output.arg(partition);
+    let output = runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(parse_detail(&output.stdout))
+}
+
+/// Assemble MD RAID array from the devices with the given array UUID
+pub fn assemble_raid_array(runner: &dyn CommandRunner, device: &str, uuid: &str, partitions: &[&str]) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--assemble");
+    output.arg(device);
+    output.arg(format!("--uuid={}",uuid));
+    output.args(partitions);
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Limit the size MD RAID array exposes, in KiB, e.g. before reshaping it to fewer devices
+pub fn set_raid_array_size(runner: &dyn CommandRunner, device: &str, size: usize) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--grow");
+    output.arg(device);
+    output.arg(format!("--array-size={}",size));
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Reshape MD RAID array to the given number of devices
+pub fn reshape_raid_array(runner: &dyn CommandRunner, device: &str, raid_devices: usize) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--grow");
+    output.arg(device);
+    output.arg(format!("--raid-devices={}",raid_devices));
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Reshape MD RAID array to fewer devices, keeping the stripes being rewritten in `backup_file`.
+///
+/// md turns the devices left over into spares once the reshape finished.
+pub fn shrink_raid_array(runner: &dyn CommandRunner, device: &str, raid_devices: usize, backup_file: &str) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--grow");
+    output.arg(device);
+    output.arg(format!("--raid-devices={}",raid_devices));
+    output.arg(format!("--backup-file={}",backup_file));
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Convert MD RAID array to another RAID level with the given number of devices
+pub fn migrate_raid_array(runner: &dyn CommandRunner, device: &str, raid_level: usize, raid_devices: usize) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--grow");
+    output.arg(device);
+    output.arg(format!("--level={}",raid_level));
+    output.arg(format!("--raid-devices={}",raid_devices));
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
+
+/// Copy a device of MD RAID array onto a spare, then mark it faulty
+pub fn replace_in_raid_array(runner: &dyn CommandRunner, device: &str, partition: &str, with: &str) -> Result<(),HyraidError> {
+    let mut output = Command::new("mdadm");
+    output.arg("--manage");
+    output.arg(device);
+    output.arg("--replace");
+    output.arg(partition);
+    output.arg("--with");
+    output.arg(with);
+    runner.run(&mut output).map_err(HyraidError::Mdadm)?;
+    Ok(())
+}
@@ -0,0 +1,401 @@
+/*!
+    mdadm bindings
+
+    Copyright (C) 2025 LIZARD-OFFICIAL-77
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{collections::HashMap, fmt, fs};
+use serde::{Serialize, Deserialize};
+use hyraid_utils::HyraidError;
+
+/// Contents of /proc/mdstat
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Mdstat {
+    pub personalities: Vec<String>,
+    pub arrays: Vec<MdArray>,
+}
+
+/// State of an md device as shown on its first line in /proc/mdstat
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ArrayState {
+    Active,
+    ReadOnly,
+    AutoReadOnly,
+    Inactive,
+}
+
+/// Role of a member device
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum MemberRole {
+    Active,
+    Faulty,
+    Spare,
+    /// Being recovered into the array
+    Rebuilding,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MdMember {
+    pub device: String,
+    /// Descriptor number, the number in brackets after the device name
+    pub index: usize,
+    pub role: MemberRole,
+}
+
+/// Background operation running on an md device
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SyncAction {
+    Resync,
+    Recovery,
+    Reshape,
+    Check,
+    Repair,
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncAction::Resync => write!(f,"resync"),
+            SyncAction::Recovery => write!(f,"recovery"),
+            SyncAction::Reshape => write!(f,"reshape"),
+            SyncAction::Check => write!(f,"check"),
+            SyncAction::Repair => write!(f,"repair"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SyncProgress {
+    pub action: SyncAction,
+    pub percent: f32,
+    /// Blocks done and total blocks
+    pub blocks: Option<(u64,u64)>,
+    /// e.g. `1.7min`
+    pub finish: Option<String>,
+    /// e.g. `35724K/sec`
+    pub speed: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Bitmap {
+    pub pages_used: u64,
+    pub pages_total: u64,
+    /// e.g. `4KB`
+    pub size: String,
+    /// e.g. `65536KB`
+    pub chunk: String,
+    /// Set for external bitmaps
+    pub file: Option<String>,
+}
+
+/// An md device in /proc/mdstat
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MdArray {
+    /// Kernel name, e.g. `md127`
+    pub name: String,
+    pub state: ArrayState,
+    /// e.g. `raid5`, `None` for inactive arrays
+    pub level: Option<String>,
+    pub members: Vec<MdMember>,
+    pub blocks: Option<u64>,
+    pub raid_disks: Option<usize>,
+    pub working_disks: Option<usize>,
+    /// One entry per slot, `false` where the slot is missing (`_` in mdstat)
+    pub slots: Vec<bool>,
+    pub progress: Option<SyncProgress>,
+    pub bitmap: Option<Bitmap>,
+}
+
+impl MdArray {
+    /// Whether any slot is missing its device
+    pub fn is_degraded(&self) -> bool {
+        self.slots.contains(&false)
+            || matches!((self.raid_disks,self.working_disks),(Some(raid),Some(working)) if working < raid)
+    }
+}
+
+/// Output of `mdadm --detail --export`, or of `mdadm --examine --export`
+/// which lacks the member devices
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MdDetail {
+    pub level: Option<String>,
+    pub devices: Option<usize>,
+    pub metadata: Option<String>,
+    pub uuid: Option<String>,
+    pub devname: Option<String>,
+    pub name: Option<String>,
+    pub members: Vec<MdDetailMember>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MdDetailMember {
+    pub device: String,
+    pub role: MemberRole,
+    /// Slot in the array for active members
+    pub slot: Option<usize>,
+}
+
+fn parse_sync_action(action: &str) -> Option<SyncAction> {
+    match action {
+        "resync" => Some(SyncAction::Resync),
+        "recovery" => Some(SyncAction::Recovery),
+        "reshape" => Some(SyncAction::Reshape),
+        "check" => Some(SyncAction::Check),
+        "repair" => Some(SyncAction::Repair),
+        _ => None
+    }
+}
+
+/// Parses `sda1[0](F)`
+fn parse_member(member: &str) -> Option<MdMember> {
+    let (device,rest) = member.split_once('[')?;
+    let (index,flags) = rest.split_once(']')?;
+    let role = if flags.contains("(F)") {
+        MemberRole::Faulty
+    } else if flags.contains("(S)") {
+        MemberRole::Spare
+    } else if flags.contains("(R)") {
+        MemberRole::Rebuilding
+    } else {
+        MemberRole::Active
+    };
+
+    Some(MdMember {
+        device: device.to_string(),
+        index: index.parse().ok()?,
+        role
+    })
+}
+
+/// Parses `md127 : active raid5 sdc1[3] sdb1[1] sda1[0]`
+fn parse_array_line(name: &str, line: &str) -> MdArray {
+    let mut words = line.split_whitespace().peekable();
+    let state = match words.next() {
+        Some("inactive") => ArrayState::Inactive,
+        _ => match words.peek() {
+            Some(&"(read-only)") => ArrayState::ReadOnly,
+            Some(&"(auto-read-only)") => ArrayState::AutoReadOnly,
+            _ => ArrayState::Active
+        }
+    };
+    if matches!(state,ArrayState::ReadOnly | ArrayState::AutoReadOnly) {
+        words.next();
+    }
+
+    let level = match state {
+        ArrayState::Inactive => None,
+        _ => words.next_if(|word| !word.contains('[')).map(|word| word.to_string())
+    };
+
+    MdArray {
+        name: name.to_string(),
+        state,
+        level,
+        members: words.filter_map(parse_member).collect(),
+        blocks: None,
+        raid_disks: None,
+        working_disks: None,
+        slots: Vec::new(),
+        progress: None,
+        bitmap: None
+    }
+}
+
+/// Parses `8378368 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [UU_]`
+fn parse_blocks_line(array: &mut MdArray, line: &str) {
+    array.blocks = line.split_whitespace().next().and_then(|x| x.parse().ok());
+
+    for word in line.split_whitespace() {
+        let Some(inner) = word.strip_prefix('[').and_then(|x| x.strip_suffix(']')) else { continue };
+        if let Some((raid,working)) = inner.split_once('/') {
+            array.raid_disks = raid.parse().ok();
+            array.working_disks = working.parse().ok();
+        } else if !inner.is_empty() && inner.chars().all(|x| x == 'U' || x == '_') {
+            array.slots = inner.chars().map(|x| x == 'U').collect();
+        }
+    }
+}
+
+/// Parses `[=>....]  recovery =  8.5% (357248/4189184) finish=1.7min speed=35724K/sec`
+fn parse_progress_line(line: &str) -> Option<SyncProgress> {
+    let (head,rest) = line.split_once(" = ")?;
+    let action = parse_sync_action(head.split_whitespace().last()?)?;
+    let mut progress = SyncProgress {
+        action,
+        percent: 0.0,
+        blocks: None,
+        finish: None,
+        speed: None
+    };
+
+    for word in rest.split_whitespace() {
+        if let Some(percent) = word.strip_suffix('%') {
+            progress.percent = percent.parse().unwrap_or(0.0);
+        } else if let Some(blocks) = word.strip_prefix('(').and_then(|x| x.strip_suffix(')')) {
+            progress.blocks = blocks
+                .split_once('/')
+                .and_then(|(done,total)| Some((done.parse().ok()?,total.parse().ok()?)));
+        } else if let Some(finish) = word.strip_prefix("finish=") {
+            progress.finish = Some(finish.to_string());
+        } else if let Some(speed) = word.strip_prefix("speed=") {
+            progress.speed = Some(speed.to_string());
+        }
+    }
+
+    Some(progress)
+}
+
+/// Parses `bitmap: 1/1 pages [4KB], 65536KB chunk, file: /bitmap`
+fn parse_bitmap_line(line: &str) -> Option<Bitmap> {
+    let rest = line.strip_prefix("bitmap:")?;
+    let mut parts = rest.split(',').map(|x| x.trim());
+
+    let mut pages = parts.next()?.split_whitespace();
+    let (used,total) = pages.next()?.split_once('/')?;
+    let size = pages
+        .find_map(|x| x.strip_prefix('[').and_then(|x| x.strip_suffix(']')))
+        .unwrap_or_default();
+    let chunk = parts.next()?.strip_suffix("chunk")?.trim();
+    let file = parts
+        .find_map(|x| x.strip_prefix("file:"))
+        .map(|x| x.trim().to_string());
+
+    Some(Bitmap {
+        pages_used: used.parse().ok()?,
+        pages_total: total.parse().ok()?,
+        size: size.to_string(),
+        chunk: chunk.to_string(),
+        file
+    })
+}
+
+/// Marks members that are being recovered into the array as rebuilding.
+/// mdstat lists them like any other member, so the newest active members
+/// beyond the working disk count are taken to be the ones rebuilding.
+fn mark_rebuilding(array: &mut MdArray) {
+    let recovering = matches!(&array.progress,Some(progress) if progress.action == SyncAction::Recovery);
+    let Some(working) = array.working_disks else { return };
+    if !recovering {
+        return;
+    }
+
+    let mut active: Vec<&mut MdMember> = array.members
+        .iter_mut()
+        .filter(|x| x.role == MemberRole::Active)
+        .collect();
+    active.sort_by_key(|x| std::cmp::Reverse(x.index));
+    let rebuilding = active.len().saturating_sub(working);
+    for member in active.into_iter().take(rebuilding) {
+        member.role = MemberRole::Rebuilding;
+    }
+}
+
+/// Parses the contents of /proc/mdstat
+pub fn parse_mdstat(mdstat: &str) -> Mdstat {
+    let mut result = Mdstat::default();
+
+    for line in mdstat.lines() {
+        let trimmed = line.trim();
+        if let Some(personalities) = trimmed.strip_prefix("Personalities :") {
+            result.personalities = personalities
+                .split_whitespace()
+                .map(|x| x.trim_matches(|c| c == '[' || c == ']').to_string())
+                .collect();
+            continue;
+        }
+        if let Some((name,rest)) = line.split_once(" : ") && name.starts_with("md") {
+            result.arrays.push(parse_array_line(name.trim(),rest));
+            continue;
+        }
+
+        let Some(array) = result.arrays.last_mut() else { continue };
+        if trimmed.contains(" blocks") {
+            parse_blocks_line(array,trimmed);
+        } else if trimmed.starts_with("bitmap:") {
+            array.bitmap = parse_bitmap_line(trimmed);
+        } else if let Some(progress) = parse_progress_line(trimmed) {
+            array.progress = Some(progress);
+        }
+    }
+
+    for array in &mut result.arrays {
+        mark_rebuilding(array);
+    }
+    result
+}
+
+/// Reads and parses an mdstat file, usually /proc/mdstat
+pub fn read_mdstat(path: &str) -> Result<Mdstat,HyraidError> {
+    let mdstat = fs::read_to_string(path)
+        .map_err(|err| HyraidError::System(format!("Failed to read {}: {}",path,err)))?;
+    Ok(parse_mdstat(&mdstat))
+}
+
+/// Parses the output of `mdadm --detail --export`
+pub fn parse_detail(detail: &str) -> MdDetail {
+    let mut result = MdDetail::default();
+    let mut devices: HashMap<String,(Option<String>,Option<String>)> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+
+    for line in detail.lines() {
+        let Some((key,value)) = line.trim().split_once('=') else { continue };
+        let value = value.trim_matches('\'').to_string();
+        match key {
+            "MD_LEVEL" => result.level = Some(value),
+            "MD_DEVICES" => result.devices = value.parse().ok(),
+            "MD_METADATA" => result.metadata = Some(value),
+            "MD_UUID" => result.uuid = Some(value),
+            "MD_DEVNAME" => result.devname = Some(value),
+            "MD_NAME" => result.name = Some(value),
+            _ => {
+                let Some(device) = key.strip_prefix("MD_DEVICE_") else { continue };
+                let (id,field) = if let Some(id) = device.strip_suffix("_ROLE") {
+                    (id,"ROLE")
+                } else if let Some(id) = device.strip_suffix("_DEV") {
+                    (id,"DEV")
+                } else {
+                    continue
+                };
+                if !devices.contains_key(id) {
+                    order.push(id.to_string());
+                }
+                let entry = devices.entry(id.to_string()).or_default();
+                match field {
+                    "ROLE" => entry.0 = Some(value),
+                    _ => entry.1 = Some(value)
+                }
+            }
+        }
+    }
+
+    result.members = order
+        .into_iter()
+        .map(|id| {
+            let (role,dev) = devices.remove(&id).unwrap_or_default();
+            let role = role.unwrap_or_default();
+            let slot = role.parse::<usize>().ok();
+            MdDetailMember {
+                device: dev.unwrap_or_else(|| format!("/{}",id.replace('_',"/"))),
+                role: match role.as_str() {
+                    "faulty" => MemberRole::Faulty,
+                    "spare" => MemberRole::Spare,
+                    _ => MemberRole::Active
+                },
+                slot
+            }
+        })
+        .collect();
+    result
+}
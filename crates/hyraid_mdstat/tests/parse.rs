@@ -0,0 +1,157 @@
+/*!
+    Tests for the /proc/mdstat and `mdadm --detail --export` parsers, using captured fixtures.
+*/
+
+use hyraid_mdstat::{
+    ArrayState,
+    Bitmap,
+    MemberRole,
+    SyncAction,
+    parse_detail,
+    parse_mdstat,
+    read_mdstat
+};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}",env!("CARGO_MANIFEST_DIR"),name)
+}
+
+fn roles(mdstat: &hyraid_mdstat::MdArray) -> Vec<(&str,MemberRole)> {
+    mdstat.members
+        .iter()
+        .map(|x| (x.device.as_str(),x.role.clone()))
+        .collect()
+}
+
+#[test]
+fn degraded_array() {
+    let mdstat = read_mdstat(&fixture("degraded.txt")).unwrap();
+
+    assert_eq!(mdstat.personalities,vec!["raid1","raid6","raid5","raid4"]);
+    assert_eq!(mdstat.arrays.len(),2);
+
+    let md = &mdstat.arrays[0];
+    assert_eq!(md.name,"md127");
+    assert_eq!(md.state,ArrayState::Active);
+    assert_eq!(md.level.as_deref(),Some("raid5"));
+    assert_eq!(md.blocks,Some(8378368));
+    assert_eq!((md.raid_disks,md.working_disks),(Some(3),Some(2)));
+    assert_eq!(md.slots,vec![true,true,false]);
+    assert!(md.is_degraded());
+    assert_eq!(roles(md),vec![
+        ("sdc1",MemberRole::Faulty),
+        ("sdb1",MemberRole::Active),
+        ("sda1",MemberRole::Active),
+    ]);
+    assert_eq!(md.progress,None);
+    assert_eq!(md.bitmap,Some(Bitmap {
+        pages_used: 1,
+        pages_total: 1,
+        size: "4KB".to_string(),
+        chunk: "65536KB".to_string(),
+        file: None
+    }));
+
+    let md = &mdstat.arrays[1];
+    assert_eq!(md.name,"md126");
+    assert!(!md.is_degraded());
+    assert_eq!(md.bitmap,None);
+}
+
+#[test]
+fn recovering_array() {
+    let mdstat = read_mdstat(&fixture("recovering.txt")).unwrap();
+    let md = &mdstat.arrays[0];
+
+    assert!(md.is_degraded());
+    assert_eq!(roles(md),vec![
+        ("sdd1",MemberRole::Rebuilding),
+        ("sdb1",MemberRole::Active),
+        ("sda1",MemberRole::Active),
+        ("sde1",MemberRole::Spare),
+    ]);
+
+    let progress = md.progress.clone().unwrap();
+    assert_eq!(progress.action,SyncAction::Recovery);
+    assert_eq!(progress.percent,8.5);
+    assert_eq!(progress.blocks,Some((357248,4189184)));
+    assert_eq!(progress.finish.as_deref(),Some("1.7min"));
+    assert_eq!(progress.speed.as_deref(),Some("35724K/sec"));
+}
+
+#[test]
+fn recovering_member_is_rebuilding() {
+    let mdstat = parse_mdstat("\
+md0 : active raid1 sdb1[1] sda1[0]
+      2094080 blocks super 1.2 [2/1] [U_]
+      [====>................]  recovery = 20.0% (418816/2094080) finish=0.2min speed=104704K/sec
+");
+    assert_eq!(roles(&mdstat.arrays[0]),vec![
+        ("sdb1",MemberRole::Rebuilding),
+        ("sda1",MemberRole::Active),
+    ]);
+}
+
+#[test]
+fn reshaping_array() {
+    let mdstat = read_mdstat(&fixture("reshaping.txt")).unwrap();
+    assert_eq!(mdstat.arrays.len(),3);
+
+    let md = &mdstat.arrays[0];
+    assert!(!md.is_degraded());
+    assert_eq!(md.members.len(),4);
+    let progress = md.progress.clone().unwrap();
+    assert_eq!(progress.action,SyncAction::Reshape);
+    assert_eq!(progress.percent,15.3);
+    assert_eq!(progress.finish.as_deref(),Some("5.2min"));
+
+    let md = &mdstat.arrays[1];
+    assert_eq!(md.state,ArrayState::AutoReadOnly);
+    assert_eq!(md.level.as_deref(),Some("raid1"));
+    assert_eq!(md.progress,None);
+
+    let md = &mdstat.arrays[2];
+    let progress = md.progress.clone().unwrap();
+    assert_eq!(progress.action,SyncAction::Check);
+    assert_eq!(progress.percent,12.0);
+    assert_eq!(md.bitmap.clone().unwrap().file.as_deref(),Some("/var/lib/md125.bitmap"));
+}
+
+#[test]
+fn inactive_array() {
+    let mdstat = read_mdstat(&fixture("inactive.txt")).unwrap();
+    let md = &mdstat.arrays[0];
+
+    assert_eq!(md.state,ArrayState::Inactive);
+    assert_eq!(md.level,None);
+    assert_eq!(md.blocks,Some(8378368));
+    assert_eq!(md.raid_disks,None);
+    assert_eq!(roles(md),vec![
+        ("sdb1",MemberRole::Spare),
+        ("sda1",MemberRole::Spare),
+    ]);
+}
+
+#[test]
+fn missing_mdstat_is_an_error() {
+    assert!(read_mdstat(&fixture("missing.txt")).is_err());
+}
+
+#[test]
+fn detail_export() {
+    let detail = parse_detail(&std::fs::read_to_string(fixture("detail_degraded.txt")).unwrap());
+
+    assert_eq!(detail.level.as_deref(),Some("raid5"));
+    assert_eq!(detail.devices,Some(3));
+    assert_eq!(detail.devname.as_deref(),Some("hyraid_md_abcdefghijklmnop"));
+    let members: Vec<(&str,MemberRole,Option<usize>)> = detail.members
+        .iter()
+        .map(|x| (x.device.as_str(),x.role.clone(),x.slot))
+        .collect();
+    assert_eq!(members,vec![
+        ("/dev/sda1",MemberRole::Active,Some(0)),
+        ("/dev/sdb1",MemberRole::Active,Some(1)),
+        ("/dev/sdc1",MemberRole::Faulty,None),
+        ("/dev/sdd1",MemberRole::Spare,None),
+    ]);
+}
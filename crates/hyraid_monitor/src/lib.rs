@@ -0,0 +1,784 @@
+/*!
+    Watches HyRAID arrays, reports changes as events and exports metrics
+
+    Copyright (C) 2025 LIZARD-OFFICIAL-77
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use hyraid_mapper::Backend;
+use hyraid_mdstat::{ArrayState, MdArray, MemberRole, read_mdstat};
+use hyraid_types::{
+    HyraidArray,
+    HyraidEvent,
+    MdScrub,
+    ScrubAction,
+    ScrubSchedule,
+    ScrubState,
+    ScrubStatus
+};
+use hyraid_utils::HyraidError;
+
+static SYSFS_BLOCK_PATH: &str = "/sys/block";
+static HOOKS_PATH: &str = "/etc/hyraid/hooks.d";
+static BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+const CAPACITY_THRESHOLD: u8 = 90;
+const HOOK_TIMEOUT: u64 = 30;
+
+/// Arrays that failed while the others were looked at, with the error
+pub type ArrayErrors = Vec<(String,HyraidError)>;
+
+/// State of an md device at one poll
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MdSnapshot {
+    pub device: String,
+    /// Stopped, or not in /proc/mdstat at all
+    pub inactive: bool,
+    /// Number of members missing
+    pub missing: usize,
+    /// Running recovery or resync, e.g. `recovery`
+    pub rebuild: Option<String>,
+    /// Members in sync or being recovered into the md device
+    pub active: Vec<String>,
+    pub faulty: Vec<String>,
+    pub spares: Vec<String>,
+}
+
+/// State of a HyRAID array at one poll
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArraySnapshot {
+    pub lvm_lv_path: String,
+    pub lv_present: bool,
+    /// How full the file system on the logical volume is, `None` if it isn't mounted
+    pub used_percent: Option<u8>,
+    pub md_devices: Vec<MdSnapshot>,
+}
+
+/// Gauge in the Prometheus text format and its samples
+struct Metric {
+    name: &'static str,
+    help: &'static str,
+    samples: Vec<(Vec<(&'static str,String)>,f64)>,
+}
+
+impl Metric {
+    fn new(name: &'static str, help: &'static str) -> Self {
+        Self { name, help, samples: vec![] }
+    }
+
+    fn add(&mut self, labels: &[(&'static str,&str)], value: f64) {
+        let labels = labels.iter().map(|(name,value)| (*name,value.to_string())).collect();
+        self.samples.push((labels,value));
+    }
+
+    fn render(&self, output: &mut String) {
+        output.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n",self.name,self.help,self.name));
+        for (labels,value) in &self.samples {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(name,value)| {
+                    let value = value.replace('\\',"\\\\").replace('"',"\\\"").replace('\n',"\\n");
+                    format!("{}=\"{}\"",name,value)
+                })
+                .collect();
+            output.push_str(&format!("{}{{{}}} {}\n",self.name,labels.join(","),value));
+        }
+    }
+}
+
+/// Unix time
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+fn role_name(role: &MemberRole) -> &'static str {
+    match role {
+        MemberRole::Active => "active",
+        MemberRole::Faulty => "faulty",
+        MemberRole::Spare => "spare",
+        MemberRole::Rebuilding => "rebuilding",
+    }
+}
+
+/// Polls the arrays in the state file and remembers what it saw last,
+/// so that every change is reported once.
+pub struct Monitor {
+    pub backend: Backend,
+    /// Where the kernel lists md devices, `/sys/block` on a real system
+    pub sysfs_dir: String,
+    /// Executables run for every event, in name order
+    pub hooks_dir: String,
+    /// `CapacityLow` is sent once the file system is fuller than this, in percent
+    pub capacity_threshold: u8,
+    /// Seconds a hook may run before it's killed
+    pub hook_timeout: u64,
+    /// Changes on every boot, tells a scrub interrupted by a reboot from a finished one
+    pub boot_id_file: String,
+    snapshots: HashMap<String,ArraySnapshot>,
+}
+
+impl Monitor {
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            backend,
+            sysfs_dir: SYSFS_BLOCK_PATH.to_string(),
+            hooks_dir: HOOKS_PATH.to_string(),
+            capacity_threshold: CAPACITY_THRESHOLD,
+            hook_timeout: HOOK_TIMEOUT,
+            boot_id_file: BOOT_ID_PATH.to_string(),
+            snapshots: HashMap::new()
+        }
+    }
+
+    /// Reads an attribute in the `md/` directory of an md device in sysfs
+    fn md_attribute(&self, kernel_name: &str, attribute: &str) -> Option<String> {
+        fs::read_to_string(format!("{}/{}/md/{}",self.sysfs_dir,kernel_name,attribute))
+            .ok()
+            .map(|x| x.trim().to_string())
+    }
+
+    /// Looks at an md device in /proc/mdstat, preferring its sysfs attributes where they exist
+    fn md_snapshot(&self, device: &str, mdstat: &[MdArray]) -> MdSnapshot {
+        let kernel_name = self.backend.disks.kernel_name(device);
+        let Some((kernel_name,array)) = kernel_name
+            .and_then(|name| mdstat.iter().find(|x| x.name == name).map(|x| (name,x)))
+        else {
+            return MdSnapshot {
+                device: device.to_string(),
+                inactive: true,
+                ..Default::default()
+            };
+        };
+
+        let members = |roles: &[MemberRole]| -> Vec<String> {
+            array.members
+                .iter()
+                .filter(|x| roles.contains(&x.role))
+                .map(|x| format!("/dev/{}",x.device))
+                .collect()
+        };
+
+        let inactive = match self.md_attribute(&kernel_name,"array_state") {
+            Some(state) => state == "inactive" || state == "clear",
+            None => array.state == ArrayState::Inactive
+        };
+        let missing = self.md_attribute(&kernel_name,"degraded")
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_else(|| {
+                let slots = array.slots.iter().filter(|x| !**x).count();
+                let disks = array.raid_disks
+                    .zip(array.working_disks)
+                    .map(|(raid,working)| raid.saturating_sub(working))
+                    .unwrap_or(0);
+                slots.max(disks)
+            });
+        let rebuild = match self.md_attribute(&kernel_name,"sync_action") {
+            Some(action) if action == "recover" => Some("recovery".to_string()),
+            Some(action) if action == "resync" => Some(action),
+            Some(_) => None,
+            None => array.progress
+                .as_ref()
+                .map(|x| x.action.to_string())
+                .filter(|x| x == "recovery" || x == "resync")
+        };
+
+        MdSnapshot {
+            device: device.to_string(),
+            inactive,
+            missing,
+            rebuild,
+            active: members(&[MemberRole::Active,MemberRole::Rebuilding]),
+            faulty: members(&[MemberRole::Faulty]),
+            spares: members(&[MemberRole::Spare])
+        }
+    }
+
+    /// How full the file system on a logical volume is, `None` if it isn't mounted
+    fn used_percent(&self, lvm_lv_path: &str) -> Result<Option<u8>,HyraidError> {
+        if !self.backend.disks.is_mounted(lvm_lv_path)? {
+            return Ok(None);
+        }
+        let mut df = Command::new("df");
+        df.args(["--output=pcent",lvm_lv_path]);
+        let output = self.backend.runner
+            .run(&mut df)
+            .map_err(|err| HyraidError::System(err.to_string()))?;
+
+        Ok(output.stdout
+            .lines()
+            .nth(1)
+            .and_then(|x| x.trim().trim_end_matches('%').parse().ok()))
+    }
+
+    /// Takes a snapshot of an array
+    pub fn snapshot(&self, entry: &HyraidArray, mdstat: &[MdArray]) -> Result<ArraySnapshot,HyraidError> {
+        let mut devices: Vec<&String> = entry.raid_map.keys().collect();
+        devices.sort();
+
+        Ok(ArraySnapshot {
+            lvm_lv_path: entry.lvm_lv_path.to_string(),
+            lv_present: self.backend.disks.device_exists(&entry.lvm_lv_path),
+            used_percent: self.used_percent(&entry.lvm_lv_path)?,
+            md_devices: devices
+                .into_iter()
+                .map(|device| self.md_snapshot(device,mdstat))
+                .collect()
+        })
+    }
+
+    /// Looks at every array once and returns what changed since the last poll.
+    ///
+    /// The first poll compares against healthy arrays, so problems that were
+    /// there before the monitor started are reported too. An array that can't be
+    /// looked at keeps its last snapshot and is returned with the error, the
+    /// others are still polled.
+    pub fn poll(&mut self) -> Result<(Vec<HyraidEvent>,ArrayErrors),HyraidError> {
+        let arrays = hyraid_mapper::list_hyraid_arrays(&self.backend)?;
+        let mdstat = read_mdstat(&self.backend.mdstat_file)?.arrays;
+
+        let mut events = vec![];
+        let mut errors = vec![];
+        let mut snapshots = HashMap::new();
+        for entry in arrays {
+            let current = match self.snapshot(&entry,&mdstat) {
+                Ok(current) => current,
+                Err(err) => {
+                    if let Some(previous) = self.snapshots.remove(&entry.name) {
+                        snapshots.insert(entry.name.clone(),previous);
+                    }
+                    errors.push((entry.name,err));
+                    continue;
+                }
+            };
+            events.extend(diff_snapshots(
+                &entry.name,
+                self.snapshots.get(&entry.name),
+                &current,
+                self.capacity_threshold
+            ));
+            snapshots.insert(entry.name,current);
+        }
+        self.snapshots = snapshots;
+
+        Ok((events,errors))
+    }
+
+    /// Runs every executable in the hooks directory for an event.
+    ///
+    /// Hooks get the event name, array name and device as arguments, and the same
+    /// in `HYRAID_EVENT`, `HYRAID_ARRAY` and `HYRAID_DEVICE` along with
+    /// `HYRAID_MESSAGE`. A hook still running after [`Monitor::hook_timeout`] seconds
+    /// is killed with `timeout(1)`. Returns the hooks that failed, a missing directory
+    /// has no hooks.
+    pub fn run_hooks(&self, event: &HyraidEvent) -> Vec<HyraidError> {
+        let Ok(entries) = fs::read_dir(&self.hooks_dir) else {
+            return vec![];
+        };
+        let mut hooks: Vec<PathBuf> = entries
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| x.metadata().is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0))
+            .collect();
+        hooks.sort();
+
+        hooks
+            .into_iter()
+            .filter_map(|hook| {
+                let mut cmd = Command::new("timeout");
+                cmd.arg("--kill-after=5")
+                    .arg(self.hook_timeout.to_string())
+                    .arg(&hook)
+                    .args([event.name(),event.array(),event.device()])
+                    .env("HYRAID_EVENT",event.name())
+                    .env("HYRAID_ARRAY",event.array())
+                    .env("HYRAID_DEVICE",event.device())
+                    .env("HYRAID_MESSAGE",event.to_string());
+                self.backend.runner
+                    .run(&mut cmd)
+                    .err()
+                    .map(|err| match err.code {
+                        // timeout(1) exits with 124, or 137 once it had to kill the hook
+                        Some(124 | 137) => HyraidError::System(format!("Hook {} timed out after {}s",hook.display(),self.hook_timeout)),
+                        _ => HyraidError::System(format!("Hook {} failed: {}",hook.display(),err))
+                    })
+            })
+            .collect()
+    }
+
+    /// Gauges of every array, its md devices and disks in the Prometheus text format,
+    /// labelled with the array name and the disk paths of the state file
+    pub fn metrics(&self) -> Result<String,HyraidError> {
+        let mdstat = read_mdstat(&self.backend.mdstat_file)?.arrays;
+
+        let mut raw_capacity = Metric::new("hyraid_array_raw_capacity_bytes","Size of every partition of the array");
+        let mut usable_capacity = Metric::new("hyraid_array_usable_capacity_bytes","Capacity of the array left for data");
+        let mut lv_size = Metric::new("hyraid_array_lv_size_bytes","Size of the logical volume of the array");
+        let mut array_degraded = Metric::new("hyraid_array_degraded","Whether any md device of the array is missing members or not running");
+        let mut md_up = Metric::new("hyraid_md_up","Whether the md device is running");
+        let mut md_missing = Metric::new("hyraid_md_missing_members","Members the md device is missing");
+        let mut md_failed = Metric::new("hyraid_md_failed_members","Members of the md device marked faulty");
+        let mut md_spares = Metric::new("hyraid_md_spare_members","Spare members of the md device");
+        let mut sync_progress = Metric::new("hyraid_md_sync_progress_ratio","Progress of the running resync, recovery, reshape or check");
+        let mut sync_speed = Metric::new("hyraid_md_sync_speed_bytes_per_second","Speed of the running resync, recovery, reshape or check");
+        let mut mismatches = Metric::new("hyraid_md_mismatch_sectors","Sectors found inconsistent by the last check or repair");
+        let mut members = Metric::new("hyraid_disk_member_info","Partition of a disk in an md device, by its role");
+        let mut global_spares = Metric::new("hyraid_global_spare_info","Disk in the global spare pool");
+
+        for entry in hyraid_mapper::list_hyraid_arrays(&self.backend)? {
+            let status = hyraid_mapper::hyraid_array_status(&self.backend,entry.name.to_string())?;
+            let array = entry.name.as_str();
+            raw_capacity.add(&[("array",array)],status.raw_capacity as f64);
+            usable_capacity.add(&[("array",array)],status.usable_capacity as f64);
+            if let Some(size) = status.lv_size {
+                lv_size.add(&[("array",array)],size as f64);
+            }
+
+            // kernel name of every partition, as mdstat lists it, to its path and disk
+            let partitions: HashMap<String,(&String,&String)> = entry.part_map
+                .iter()
+                .chain(entry.spares.iter())
+                .flat_map(|(disk,partitions)| partitions.iter().map(move |x| (x,disk)))
+                .filter_map(|(partition,disk)| {
+                    let path = partition.path.as_ref()?;
+                    Some((self.backend.disks.kernel_name(path)?,(path,disk)))
+                })
+                .collect();
+
+            let mut degraded = false;
+            for md in &status.md_devices {
+                let device = md.device.as_str();
+                let labels = [("array",array),("md",device)];
+                let snapshot = self.md_snapshot(device,&mdstat);
+                degraded |= snapshot.inactive || snapshot.missing > 0;
+
+                md_up.add(&labels,if snapshot.inactive { 0.0 } else { 1.0 });
+                md_missing.add(&labels,snapshot.missing as f64);
+                md_failed.add(&labels,snapshot.faulty.len() as f64);
+                md_spares.add(&labels,snapshot.spares.len() as f64);
+
+                let kernel_name = md.kernel_name.as_deref().unwrap_or_default();
+                let array_state = mdstat.iter().find(|x| x.name == kernel_name);
+                if let Some(progress) = array_state.and_then(|x| x.progress.as_ref()) {
+                    let action = progress.action.to_string();
+                    sync_progress.add(&[("array",array),("md",device),("action",&action)],f64::from(progress.percent) / 100.0);
+
+                    // KiB/s in both
+                    let speed = self.md_attribute(kernel_name,"sync_speed")
+                        .or_else(|| progress.speed.as_ref().map(|x| x.trim_end_matches("K/sec").to_string()))
+                        .and_then(|x| x.parse::<f64>().ok());
+                    if let Some(speed) = speed {
+                        sync_speed.add(&[("array",array),("md",device),("action",&action)],speed * 1024.0);
+                    }
+                }
+                if let Some(count) = self.md_attribute(kernel_name,"mismatch_cnt").and_then(|x| x.parse::<f64>().ok()) {
+                    mismatches.add(&labels,count);
+                }
+
+                let mut seen = vec![];
+                for member in array_state.map(|x| x.members.as_slice()).unwrap_or_default() {
+                    let Some((partition,disk)) = partitions.get(&member.device) else { continue };
+                    members.add(&[
+                        ("array",array),
+                        ("disk",disk),
+                        ("partition",partition),
+                        ("md",device),
+                        ("role",role_name(&member.role))
+                    ],1.0);
+                    seen.push(*partition);
+                }
+                for partition in md.members.iter().filter_map(|x| x.path.as_ref()).filter(|x| !seen.contains(x)) {
+                    let Some((_,disk)) = partitions.values().find(|(path,_)| *path == partition) else { continue };
+                    members.add(&[
+                        ("array",array),
+                        ("disk",disk),
+                        ("partition",partition),
+                        ("md",device),
+                        ("role","missing")
+                    ],1.0);
+                }
+            }
+            array_degraded.add(&[("array",array)],if degraded { 1.0 } else { 0.0 });
+        }
+        for disk in hyraid_mapper::list_global_spares(&self.backend)? {
+            global_spares.add(&[("disk",&disk)],1.0);
+        }
+
+        let mut output = String::new();
+        for metric in [
+            raw_capacity,
+            usable_capacity,
+            lv_size,
+            array_degraded,
+            md_up,
+            md_missing,
+            md_failed,
+            md_spares,
+            sync_progress,
+            sync_speed,
+            mismatches,
+            members,
+            global_spares
+        ] {
+            metric.render(&mut output);
+        }
+        Ok(output)
+    }
+
+    fn find_array(&self, name: &str) -> Result<HyraidArray,HyraidError> {
+        hyraid_mapper::list_hyraid_arrays(&self.backend)?
+            .into_iter()
+            .find(|x| x.name == name)
+            .ok_or(HyraidError::Validation(format!("No such HyRAID array: {}",name)))
+    }
+
+    fn md_kernel_name(&self, device: &str) -> Result<String,HyraidError> {
+        self.backend.disks
+            .kernel_name(device)
+            .ok_or(HyraidError::System(format!("{} is not assembled",device)))
+    }
+
+    /// Writes an attribute in the `md/` directory of an md device in sysfs
+    fn set_md_attribute(&self, kernel_name: &str, attribute: &str, value: &str) -> Result<(),HyraidError> {
+        let path = format!("{}/{}/md/{}",self.sysfs_dir,kernel_name,attribute);
+        fs::write(&path,value)
+            .map_err(|err| HyraidError::System(format!("Failed to write {} to {}: {}",value,path,err)))
+    }
+
+    fn boot_id(&self) -> String {
+        fs::read_to_string(&self.boot_id_file)
+            .map(|x| x.trim().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Sectors done and in total of the sync action running on an md device
+    fn sync_completed(&self, kernel_name: &str) -> Option<(u64,u64)> {
+        let completed = self.md_attribute(kernel_name,"sync_completed")?;
+        let (done,total) = completed.split_once('/')?;
+        Some((done.trim().parse().ok()?,total.trim().parse().ok()?))
+    }
+
+    fn start_md_scrub(&self, action: ScrubAction, speed_limit: Option<usize>, md: &MdScrub) -> Result<(),HyraidError> {
+        let kernel_name = self.md_kernel_name(&md.device)?;
+        // sync_speed_max is in KiB/s
+        let speed_limit = speed_limit
+            .map(|x| (x / 1024).max(1).to_string())
+            .unwrap_or("system".to_string());
+        self.set_md_attribute(&kernel_name,"sync_speed_max",&speed_limit)?;
+        self.set_md_attribute(&kernel_name,"sync_min",&md.resume_from.to_string())?;
+        self.set_md_attribute(&kernel_name,"sync_max","max")?;
+        self.set_md_attribute(&kernel_name,"sync_action",&action.to_string())
+    }
+
+    /// Puts back the range and speed limit a scrub set on an md device
+    fn reset_md_scrub(&self, kernel_name: &str) -> Result<(),HyraidError> {
+        self.set_md_attribute(kernel_name,"sync_min","0")?;
+        self.set_md_attribute(kernel_name,"sync_speed_max","system")
+    }
+
+    /// Moves a running scrub along: records the `mismatch_cnt` of an md device once md
+    /// is done with it and starts the next one, waiting while md is busy with anything else.
+    ///
+    /// Returns whether the scrub changed.
+    fn advance(&self, entry: &mut HyraidArray) -> Result<bool,HyraidError> {
+        let raid_map = &entry.raid_map;
+        let Some(scrub) = entry.scrub.as_mut().filter(|x| x.running) else { return Ok(false) };
+        let boot_id = self.boot_id();
+
+        // md devices the array lost since the scrub started
+        let devices = scrub.devices.len();
+        scrub.devices.retain(|x| raid_map.contains_key(&x.device));
+        let mut changed = scrub.devices.len() != devices;
+
+        loop {
+            let Some(md) = scrub.devices.iter_mut().find(|x| x.mismatches.is_none()) else {
+                scrub.running = false;
+                scrub.finished = Some(now());
+                scrub.boot_id = None;
+                return Ok(true);
+            };
+            let kernel_name = self.md_kernel_name(&md.device)?;
+            let sync_action = self.md_attribute(&kernel_name,"sync_action").unwrap_or_default();
+
+            if scrub.boot_id.as_ref() == Some(&boot_id) {
+                if sync_action == scrub.action.to_string() {
+                    return Ok(changed);
+                }
+                scrub.boot_id = None;
+                if sync_action == "idle" {
+                    md.mismatches = Some(self.md_attribute(&kernel_name,"mismatch_cnt").and_then(|x| x.parse().ok()).unwrap_or(0));
+                    md.resume_from = 0;
+                    self.reset_md_scrub(&kernel_name)?;
+                    changed = true;
+                    continue;
+                }
+                // md dropped the check for a recovery or the like, it starts over once md is idle
+                return Ok(true);
+            }
+
+            if sync_action != "idle" {
+                return Ok(changed);
+            }
+            self.start_md_scrub(scrub.action,scrub.speed_limit,md)?;
+            scrub.boot_id = Some(boot_id);
+            return Ok(true);
+        }
+    }
+
+    /// Starts scrubbing the md devices of an array one at a time, or carries on
+    /// with a stopped scrub, which keeps its action.
+    ///
+    /// `speed_limit` is in bytes per second for each md device.
+    pub fn start_scrub(&self, name: &str, action: ScrubAction, speed_limit: Option<usize>) -> Result<ScrubState,HyraidError> {
+        let mut entry = self.find_array(name)?;
+        match entry.scrub.as_mut().filter(|x| x.finished.is_none()) {
+            Some(scrub) if scrub.running => {
+                return Err(HyraidError::Validation(format!("Array {} is already being scrubbed",name)));
+            },
+            Some(scrub) => {
+                scrub.running = true;
+                scrub.speed_limit = speed_limit.or(scrub.speed_limit);
+            },
+            None => {
+                let mut devices: Vec<&String> = entry.raid_map.keys().collect();
+                devices.sort();
+                entry.scrub = Some(ScrubState {
+                    action,
+                    speed_limit,
+                    running: true,
+                    started: now(),
+                    finished: None,
+                    boot_id: None,
+                    devices: devices
+                        .into_iter()
+                        .map(|device| MdScrub {
+                            device: device.to_string(),
+                            resume_from: 0,
+                            mismatches: None
+                        })
+                        .collect()
+                });
+            }
+        }
+        self.advance(&mut entry)?;
+        hyraid_json::set_scrub(&self.backend.state_file,name,entry.scrub.clone())?;
+        Ok(entry.scrub.unwrap_or_else(|| unreachable!("set above")))
+    }
+
+    /// Stops the scrub of an array, keeping where it got to
+    pub fn stop_scrub(&self, name: &str) -> Result<ScrubState,HyraidError> {
+        let mut entry = self.find_array(name)?;
+        // an md device md just finished counts as done
+        self.advance(&mut entry)?;
+        let boot_id = self.boot_id();
+        let Some(scrub) = entry.scrub.as_mut().filter(|x| x.running) else {
+            return Err(HyraidError::Validation(format!("Array {} is not being scrubbed",name)));
+        };
+
+        if scrub.boot_id.as_ref() == Some(&boot_id)
+            && let Some(md) = scrub.devices.iter_mut().find(|x| x.mismatches.is_none())
+        {
+            let kernel_name = self.md_kernel_name(&md.device)?;
+            if let Some((done,_)) = self.sync_completed(&kernel_name) {
+                // md only starts a sync at a chunk boundary
+                let chunk = self.md_attribute(&kernel_name,"chunk_size")
+                    .and_then(|x| x.parse::<u64>().ok())
+                    .map(|x| x / 512)
+                    .filter(|x| *x > 0)
+                    .unwrap_or(1);
+                md.resume_from = done - done % chunk;
+            }
+            self.set_md_attribute(&kernel_name,"sync_action","idle")?;
+            self.reset_md_scrub(&kernel_name)?;
+        }
+        scrub.running = false;
+        scrub.boot_id = None;
+
+        hyraid_json::set_scrub(&self.backend.state_file,name,entry.scrub.clone())?;
+        Ok(entry.scrub.unwrap_or_else(|| unreachable!("checked above")))
+    }
+
+    /// Moves the scrub of an array along, see [`Monitor::start_scrub`]
+    pub fn advance_scrub(&self, name: &str) -> Result<Option<ScrubState>,HyraidError> {
+        let mut entry = self.find_array(name)?;
+        if self.advance(&mut entry)? {
+            hyraid_json::set_scrub(&self.backend.state_file,name,entry.scrub.clone())?;
+        }
+        Ok(entry.scrub)
+    }
+
+    pub fn scrub_status(&self, name: &str) -> Result<ScrubStatus,HyraidError> {
+        let entry = self.find_array(name)?;
+        let boot_id = self.boot_id();
+
+        let current = entry.scrub
+            .as_ref()
+            .filter(|x| x.running && x.boot_id.as_ref() == Some(&boot_id))
+            .and_then(|x| x.devices.iter().find(|x| x.mismatches.is_none()))
+            .map(|md| {
+                let percent = self.backend.disks
+                    .kernel_name(&md.device)
+                    .and_then(|x| self.sync_completed(&x))
+                    .map(|(done,total)| done as f32 * 100.0 / total.max(1) as f32)
+                    .unwrap_or(0.0);
+                (md.device.to_string(),percent)
+            });
+
+        Ok(ScrubStatus {
+            name: entry.name,
+            scrub: entry.scrub,
+            schedule: entry.scrub_schedule,
+            current
+        })
+    }
+
+    /// Sets how often [`Monitor::advance_scrubs`] scrubs an array, `None` to stop scrubbing it
+    pub fn set_scrub_schedule(&self, name: &str, schedule: Option<ScrubSchedule>) -> Result<(),HyraidError> {
+        self.find_array(name)?;
+        hyraid_json::set_scrub_schedule(&self.backend.state_file,name,schedule)
+    }
+
+    /// Moves every running scrub along and starts the scheduled ones that are due.
+    /// An array that was never scrubbed is due as soon as it has a schedule.
+    ///
+    /// Returns the arrays whose scrub finished, and the arrays that failed along with
+    /// the error, which don't keep the others from being scrubbed.
+    pub fn advance_scrubs(&self) -> Result<(Vec<(String,ScrubState)>,ArrayErrors),HyraidError> {
+        let mut finished = vec![];
+        let mut errors = vec![];
+        for entry in hyraid_mapper::list_hyraid_arrays(&self.backend)? {
+            let name = entry.name.clone();
+            match self.scheduled_scrub(entry) {
+                Ok(Some(scrub)) => finished.push((name,scrub)),
+                Ok(None) => {},
+                Err(err) => errors.push((name,err))
+            }
+        }
+        Ok((finished,errors))
+    }
+
+    /// Starts the scrub of an array if it's due or moves the running one along,
+    /// returns the scrub if it just finished
+    fn scheduled_scrub(&self, mut entry: HyraidArray) -> Result<Option<ScrubState>,HyraidError> {
+        let due = entry.scrub_schedule.is_some_and(|schedule| match &entry.scrub {
+            Some(scrub) => scrub.finished.is_some() && now() >= scrub.started + schedule.interval(),
+            None => true
+        });
+        if due {
+            self.start_scrub(&entry.name,ScrubAction::Check,None)?;
+            return Ok(None);
+        }
+
+        if self.advance(&mut entry)? {
+            hyraid_json::set_scrub(&self.backend.state_file,&entry.name,entry.scrub.clone())?;
+            return Ok(entry.scrub.filter(|x| x.finished.is_some()));
+        }
+        Ok(None)
+    }
+}
+
+/// Events for the changes between two snapshots of an array,
+/// `previous` is `None` on the first poll.
+pub fn diff_snapshots(
+    array: &str,
+    previous: Option<&ArraySnapshot>,
+    current: &ArraySnapshot,
+    capacity_threshold: u8
+) -> Vec<HyraidEvent> {
+    let healthy = ArraySnapshot {
+        lv_present: true,
+        ..Default::default()
+    };
+    let previous = previous.unwrap_or(&healthy);
+    let array = array.to_string();
+    let mut events = vec![];
+
+    for md in &current.md_devices {
+        let before = previous.md_devices
+            .iter()
+            .find(|x| x.device == md.device)
+            .cloned()
+            .unwrap_or_else(|| MdSnapshot {
+                device: md.device.to_string(),
+                ..Default::default()
+            });
+        let device = md.device.to_string();
+
+        if md.inactive {
+            if !before.inactive {
+                events.push(HyraidEvent::ArrayInactive { array: array.to_string(), device });
+            }
+            continue;
+        }
+        for member in md.faulty.iter().filter(|x| !before.faulty.contains(x)) {
+            events.push(HyraidEvent::DeviceFailed {
+                array: array.to_string(),
+                device: device.to_string(),
+                member: member.to_string()
+            });
+        }
+        if md.missing > 0 && (before.missing == 0 || before.inactive) {
+            events.push(HyraidEvent::Degraded {
+                array: array.to_string(),
+                device: device.to_string(),
+                missing: md.missing
+            });
+        }
+        for member in before.spares.iter().filter(|x| md.active.contains(x)) {
+            events.push(HyraidEvent::SpareActivated {
+                array: array.to_string(),
+                device: device.to_string(),
+                member: member.to_string()
+            });
+        }
+        match (&before.rebuild,&md.rebuild) {
+            (None,Some(action)) => events.push(HyraidEvent::RebuildStarted {
+                array: array.to_string(),
+                device,
+                action: action.to_string()
+            }),
+            (Some(_),None) => events.push(HyraidEvent::RebuildFinished { array: array.to_string(), device }),
+            _ => ()
+        }
+    }
+
+    if !current.lv_present && previous.lv_present {
+        events.push(HyraidEvent::ArrayInactive {
+            array: array.to_string(),
+            device: current.lvm_lv_path.to_string()
+        });
+    }
+    if let Some(used_percent) = current.used_percent
+        && used_percent > capacity_threshold
+        && previous.used_percent.is_none_or(|x| x <= capacity_threshold)
+    {
+        events.push(HyraidEvent::CapacityLow {
+            array,
+            device: current.lvm_lv_path.to_string(),
+            used_percent
+        });
+    }
+
+    events
+}
@@ -0,0 +1,189 @@
+/*!
+    Tests for the events the monitor sends, using a fake /proc/mdstat.
+*/
+
+use std::{fs, os::unix::fs::PermissionsExt, sync::Arc};
+
+use hyraid_gpt::FakeDisks;
+use hyraid_mapper::{Backend, create_hyraid_array};
+use hyraid_monitor::Monitor;
+use hyraid_types::{HyraidEvent, RedundancyPolicy};
+use hyraid_utils::{CommandOutput, RecordingRunner};
+
+const DISK_SIZE: usize = 4_000_000;
+const DISKS: [&str; 3] = ["/dev/sda","/dev/sdb","/dev/sdc"];
+
+const HEALTHY: &str = "Personalities : [raid5]
+md127 : active raid5 sdd1[3](S) sdc1[2] sdb1[1] sda1[0]
+      7808 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/3] [UUU]
+
+unused devices: <none>
+";
+const RECOVERING: &str = "Personalities : [raid5]
+md127 : active raid5 sdd1[3] sdc1[2](F) sdb1[1] sda1[0]
+      7808 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [UU_]
+      [=>...................]  recovery =  8.5% (332/3904) finish=1.7min speed=35724K/sec
+
+unused devices: <none>
+";
+const RECOVERED: &str = "Personalities : [raid5]
+md127 : active raid5 sdd1[3] sdc1[2](F) sdb1[1] sda1[0]
+      7808 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/3] [UUU]
+
+unused devices: <none>
+";
+
+/// Monitor of a three disk RAID5 array called `test` with a single md device, `md127`
+fn monitor(test: &str) -> (Monitor,Arc<RecordingRunner>,Arc<FakeDisks>,String) {
+    let state_file = std::env::temp_dir()
+        .join(format!("hyraid-monitor-{}-{}.json",test,std::process::id()))
+        .to_string_lossy()
+        .to_string();
+
+    let (backend,runner,disks) = Backend::fake(&state_file);
+    for disk in DISKS {
+        disks.add_disk(disk,DISK_SIZE);
+    }
+    create_hyraid_array(&backend,"test".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    let md = runner.commands()[0][2].to_string();
+    disks.set_kernel_name(&md,"md127");
+
+    let mut monitor = Monitor::new(backend);
+    monitor.sysfs_dir = format!("{}.sys",state_file);
+    monitor.hooks_dir = format!("{}.hooks",state_file);
+    (monitor,runner,disks,md)
+}
+
+#[test]
+fn failure_and_recovery_onto_spare() {
+    let (mut monitor,_,_,md) = monitor("recovery");
+    fs::write(&monitor.backend.mdstat_file,HEALTHY).unwrap();
+    assert_eq!(monitor.poll().unwrap().0,vec![]);
+
+    fs::write(&monitor.backend.mdstat_file,RECOVERING).unwrap();
+    let events = monitor.poll().unwrap().0;
+    assert_eq!(events,vec![
+        HyraidEvent::DeviceFailed { array: "test".to_string(), device: md.to_string(), member: "/dev/sdc1".to_string() },
+        HyraidEvent::Degraded { array: "test".to_string(), device: md.to_string(), missing: 1 },
+        HyraidEvent::SpareActivated { array: "test".to_string(), device: md.to_string(), member: "/dev/sdd1".to_string() },
+        HyraidEvent::RebuildStarted { array: "test".to_string(), device: md.to_string(), action: "recovery".to_string() },
+    ]);
+    assert_eq!((events[0].name(),events[0].array(),events[0].device()),("DeviceFailed","test",md.as_str()));
+
+    // nothing changed
+    assert_eq!(monitor.poll().unwrap().0,vec![]);
+
+    fs::write(&monitor.backend.mdstat_file,RECOVERED).unwrap();
+    assert_eq!(monitor.poll().unwrap().0,vec![
+        HyraidEvent::RebuildFinished { array: "test".to_string(), device: md.to_string() },
+    ]);
+}
+
+#[test]
+fn first_poll_reports_existing_problems() {
+    let (mut monitor,runner,disks,md) = monitor("first-poll");
+    let lv = hyraid_mapper::list_hyraid_arrays(&monitor.backend).unwrap()[0].lvm_lv_path.to_string();
+    disks.mount(&lv);
+    runner.respond(&["df"],Ok(CommandOutput {
+        stdout: "Use%\n 95%\n".to_string(),
+        stderr: String::new()
+    }));
+    fs::write(&monitor.backend.mdstat_file,"Personalities : [raid5]\n\nunused devices: <none>\n").unwrap();
+
+    assert_eq!(monitor.poll().unwrap().0,vec![
+        HyraidEvent::ArrayInactive { array: "test".to_string(), device: md },
+        HyraidEvent::CapacityLow { array: "test".to_string(), device: lv.to_string(), used_percent: 95 },
+    ]);
+    assert!(runner.commands().contains(&vec!["df".to_string(),"--output=pcent".to_string(),lv]));
+    assert_eq!(monitor.poll().unwrap().0,vec![]);
+}
+
+#[test]
+fn failing_array_does_not_stop_the_others() {
+    let (mut monitor,runner,disks,_) = monitor("failing-array");
+    let other_disks = ["/dev/sdd","/dev/sde","/dev/sdf"];
+    for disk in other_disks {
+        disks.add_disk(disk,DISK_SIZE);
+    }
+    create_hyraid_array(&monitor.backend,"other".to_string(),&other_disks,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    let arrays = hyraid_mapper::list_hyraid_arrays(&monitor.backend).unwrap();
+    let lv = arrays.iter().find(|x| x.name == "test").unwrap().lvm_lv_path.to_string();
+    let other_md = arrays.iter().find(|x| x.name == "other").unwrap().raid_map.keys().next().unwrap().to_string();
+    fs::write(&monitor.backend.mdstat_file,HEALTHY).unwrap();
+    assert_eq!(monitor.poll().unwrap().0,vec![
+        HyraidEvent::ArrayInactive { array: "other".to_string(), device: other_md.to_string() },
+    ]);
+
+    disks.mount(&lv);
+    runner.respond(&["df"],Err(Some(1)));
+    fs::write(&monitor.backend.mdstat_file,"Personalities : [raid5]\n\nunused devices: <none>\n").unwrap();
+    let (events,errors) = monitor.poll().unwrap();
+    assert_eq!(events,vec![]);
+    assert_eq!(errors.iter().map(|x| x.0.as_str()).collect::<Vec<&str>>(),vec!["test"]);
+
+    // the failed array kept its last snapshot
+    runner.respond(&["df"],Ok(CommandOutput { stdout: "Use%\n 10%\n".to_string(), stderr: String::new() }));
+    fs::write(&monitor.backend.mdstat_file,HEALTHY).unwrap();
+    assert_eq!(monitor.poll().unwrap(),(vec![],vec![]));
+}
+
+#[test]
+fn sysfs_attributes_take_precedence() {
+    let (mut monitor,_,_,md) = monitor("sysfs");
+    fs::write(&monitor.backend.mdstat_file,HEALTHY).unwrap();
+    let attributes = format!("{}/md127/md",monitor.sysfs_dir);
+    fs::create_dir_all(&attributes).unwrap();
+    fs::write(format!("{}/degraded",attributes),"1\n").unwrap();
+    fs::write(format!("{}/sync_action",attributes),"recover\n").unwrap();
+
+    assert_eq!(monitor.poll().unwrap().0,vec![
+        HyraidEvent::Degraded { array: "test".to_string(), device: md.to_string(), missing: 1 },
+        HyraidEvent::RebuildStarted { array: "test".to_string(), device: md, action: "recovery".to_string() },
+    ]);
+    fs::remove_dir_all(&monitor.sysfs_dir).unwrap();
+}
+
+#[test]
+fn hooks_run_in_order_with_the_event() {
+    let (monitor,runner,_,md) = monitor("hooks");
+    fs::create_dir_all(&monitor.hooks_dir).unwrap();
+    for (name,mode) in [("20-mail",0o755),("10-log",0o755),("README",0o644)] {
+        let path = format!("{}/{}",monitor.hooks_dir,name);
+        fs::write(&path,"#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path,fs::Permissions::from_mode(mode)).unwrap();
+    }
+    runner.respond(&["timeout","--kill-after=5","30",&format!("{}/20-mail",monitor.hooks_dir)],Err(Some(1)));
+
+    let event = HyraidEvent::Degraded { array: "test".to_string(), device: md.to_string(), missing: 1 };
+    let failed = monitor.run_hooks(&event);
+
+    let hooks: Vec<Vec<String>> = runner.commands()
+        .into_iter()
+        .filter(|x| x[0] == "timeout")
+        .map(|x| x[3..].to_vec())
+        .collect();
+    assert_eq!(hooks,vec![
+        vec![format!("{}/10-log",monitor.hooks_dir),"Degraded".to_string(),"test".to_string(),md.to_string()],
+        vec![format!("{}/20-mail",monitor.hooks_dir),"Degraded".to_string(),"test".to_string(),md],
+    ]);
+    assert_eq!(failed.len(),1);
+    fs::remove_dir_all(&monitor.hooks_dir).unwrap();
+}
+
+#[test]
+fn hooks_are_killed_after_the_timeout() {
+    let (mut monitor,runner,_,md) = monitor("hook-timeout");
+    monitor.hook_timeout = 2;
+    fs::create_dir_all(&monitor.hooks_dir).unwrap();
+    let hook = format!("{}/10-hang",monitor.hooks_dir);
+    fs::write(&hook,"#!/bin/sh\n").unwrap();
+    fs::set_permissions(&hook,fs::Permissions::from_mode(0o755)).unwrap();
+    runner.respond(&["timeout","--kill-after=5","2",&hook],Err(Some(124)));
+
+    let event = HyraidEvent::Degraded { array: "test".to_string(), device: md, missing: 1 };
+    let failed = monitor.run_hooks(&event);
+
+    assert_eq!(failed.len(),1);
+    assert!(failed[0].to_string().contains("timed out after 2s"));
+    fs::remove_dir_all(&monitor.hooks_dir).unwrap();
+}
@@ -0,0 +1,75 @@
+/*!
+    Tests for the Prometheus metrics of HyRAID arrays.
+*/
+
+use std::fs;
+
+use hyraid_mapper::{Backend, create_hyraid_array, add_global_spare};
+use hyraid_monitor::Monitor;
+use hyraid_types::RedundancyPolicy;
+use hyraid_utils::CommandOutput;
+
+const DISK_SIZE: usize = 4_000_000;
+const DISKS: [&str; 3] = ["/dev/sda","/dev/sdb","/dev/sdc"];
+
+const RECOVERING: &str = "Personalities : [raid5]
+md127 : active raid5 sdd1[3] sdc1[2](F) sdb1[1] sda1[0]
+      7808 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [UU_]
+      [=>...................]  recovery =  8.5% (332/3904) finish=1.7min speed=35724K/sec
+
+unused devices: <none>
+";
+
+#[test]
+fn exports_array_md_and_disk_gauges() {
+    let state_file = std::env::temp_dir()
+        .join(format!("hyraid-metrics-{}.json",std::process::id()))
+        .to_string_lossy()
+        .to_string();
+
+    let (backend,runner,disks) = Backend::fake(&state_file);
+    for disk in DISKS.iter().chain(&["/dev/sde"]) {
+        disks.add_disk(disk,DISK_SIZE);
+    }
+    create_hyraid_array(&backend,"storage".to_string(),&DISKS,5,RedundancyPolicy::RaidLevel,false).unwrap();
+    add_global_spare(&backend,"/dev/sde").unwrap();
+    let md = runner.commands()[0][2].to_string();
+    disks.set_kernel_name(&md,"md127");
+    for disk in ["sda","sdb","sdc"] {
+        disks.set_kernel_name(&format!("/dev/disk/by-partuuid/{}-part1",disk),&format!("{}1",disk));
+    }
+    fs::write(&backend.mdstat_file,RECOVERING).unwrap();
+    runner.respond(&["lvs"],Ok(CommandOutput {
+        stdout: "  7995392\n".to_string(),
+        stderr: String::new()
+    }));
+
+    let mut monitor = Monitor::new(backend);
+    monitor.sysfs_dir = format!("{}.sys",state_file);
+    fs::create_dir_all(format!("{}/md127/md",monitor.sysfs_dir)).unwrap();
+    fs::write(format!("{}/md127/md/mismatch_cnt",monitor.sysfs_dir),"16\n").unwrap();
+
+    let metrics = monitor.metrics().unwrap();
+    let labels = format!("array=\"storage\",md=\"{}\"",md);
+    for line in [
+        "# TYPE hyraid_array_usable_capacity_bytes gauge".to_string(),
+        "hyraid_array_lv_size_bytes{array=\"storage\"} 7995392".to_string(),
+        "hyraid_array_degraded{array=\"storage\"} 1".to_string(),
+        format!("hyraid_md_up{{{}}} 1",labels),
+        format!("hyraid_md_missing_members{{{}}} 1",labels),
+        format!("hyraid_md_failed_members{{{}}} 1",labels),
+        format!("hyraid_md_sync_progress_ratio{{{},action=\"recovery\"}} 0.085",labels),
+        format!("hyraid_md_sync_speed_bytes_per_second{{{},action=\"recovery\"}} 36581376",labels),
+        format!("hyraid_md_mismatch_sectors{{{}}} 16",labels),
+        format!("hyraid_disk_member_info{{array=\"storage\",disk=\"/dev/sdc\",partition=\"/dev/disk/by-partuuid/sdc-part1\",md=\"{}\",role=\"faulty\"}} 1",md),
+        format!("hyraid_disk_member_info{{array=\"storage\",disk=\"/dev/sda\",partition=\"/dev/disk/by-partuuid/sda-part1\",md=\"{}\",role=\"active\"}} 1",md),
+        "hyraid_global_spare_info{disk=\"/dev/sde\"} 1".to_string(),
+    ] {
+        assert!(metrics.lines().any(|x| x == line),"{} missing from\n{}",line,metrics);
+    }
+    // sdd1 is no partition of the array
+    assert!(!metrics.contains("sdd"));
+    assert!(!metrics.contains("role=\"missing\""));
+
+    fs::remove_dir_all(&monitor.sysfs_dir).unwrap();
+}
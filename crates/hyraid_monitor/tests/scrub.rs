@@ -0,0 +1,141 @@
+/*!
+    Tests for scrubbing arrays, using a fake sysfs.
+*/
+
+use std::fs;
+
+use hyraid_mapper::{Backend, create_hyraid_array};
+use hyraid_monitor::Monitor;
+use hyraid_types::{RedundancyPolicy, ScrubAction, ScrubSchedule};
+
+const DISKS: [(&str,usize); 4] = [("/dev/sda",4_000_000),("/dev/sdb",4_000_000),("/dev/sdc",8_000_000),("/dev/sdd",8_000_000)];
+
+/// Monitor of an array called `test` with two md devices, `md126` and `md127`, both idle
+fn monitor(test: &str) -> (Monitor,Vec<String>) {
+    let state_file = std::env::temp_dir()
+        .join(format!("hyraid-scrub-{}-{}.json",test,std::process::id()))
+        .to_string_lossy()
+        .to_string();
+
+    let (backend,_,disks) = Backend::fake(&state_file);
+    for (disk,size) in DISKS {
+        disks.add_disk(disk,size);
+    }
+    let names: Vec<&str> = DISKS.iter().map(|(disk,_)| *disk).collect();
+    create_hyraid_array(&backend,"test".to_string(),&names,5,RedundancyPolicy::RaidLevel,false).unwrap();
+
+    let mut md_devices: Vec<String> = hyraid_mapper::list_hyraid_arrays(&backend).unwrap()[0]
+        .raid_map
+        .keys()
+        .cloned()
+        .collect();
+    md_devices.sort();
+    assert_eq!(md_devices.len(),2);
+
+    let mut monitor = Monitor::new(backend);
+    monitor.sysfs_dir = format!("{}.sys",state_file);
+    monitor.boot_id_file = format!("{}.boot_id",state_file);
+    fs::write(&monitor.boot_id_file,"first\n").unwrap();
+    for (md,kernel_name) in md_devices.iter().zip(["md126","md127"]) {
+        disks.set_kernel_name(md,kernel_name);
+        fs::create_dir_all(format!("{}/{}/md",monitor.sysfs_dir,kernel_name)).unwrap();
+        set(&monitor,kernel_name,"sync_action","idle");
+        set(&monitor,kernel_name,"mismatch_cnt","0");
+    }
+    (monitor,md_devices)
+}
+
+fn set(monitor: &Monitor, kernel_name: &str, attribute: &str, value: &str) {
+    fs::write(format!("{}/{}/md/{}",monitor.sysfs_dir,kernel_name,attribute),format!("{}\n",value)).unwrap();
+}
+
+fn get(monitor: &Monitor, kernel_name: &str, attribute: &str) -> String {
+    fs::read_to_string(format!("{}/{}/md/{}",monitor.sysfs_dir,kernel_name,attribute))
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+fn clean_up(monitor: &Monitor) {
+    fs::remove_dir_all(&monitor.sysfs_dir).unwrap();
+    let _ = fs::remove_file(&monitor.boot_id_file);
+}
+
+#[test]
+fn scrubs_md_devices_one_at_a_time() {
+    let (monitor,md_devices) = monitor("one-at-a-time");
+
+    monitor.start_scrub("test",ScrubAction::Check,Some(10 * 1024 * 1024)).unwrap();
+    assert_eq!(get(&monitor,"md126","sync_action"),"check");
+    assert_eq!(get(&monitor,"md126","sync_speed_max"),"10240");
+    assert_eq!(get(&monitor,"md127","sync_action"),"idle");
+    assert!(monitor.start_scrub("test",ScrubAction::Check,None).is_err());
+
+    // md is still checking md126
+    assert_eq!(monitor.advance_scrubs().unwrap().0,vec![]);
+    assert_eq!(get(&monitor,"md127","sync_action"),"idle");
+
+    set(&monitor,"md126","sync_action","idle");
+    set(&monitor,"md126","mismatch_cnt","8");
+    assert_eq!(monitor.advance_scrubs().unwrap().0,vec![]);
+    assert_eq!(get(&monitor,"md126","sync_speed_max"),"system");
+    assert_eq!(get(&monitor,"md127","sync_action"),"check");
+    let status = monitor.scrub_status("test").unwrap();
+    assert_eq!(status.current.map(|(device,_)| device),Some(md_devices[1].to_string()));
+
+    set(&monitor,"md127","sync_action","idle");
+    let finished = monitor.advance_scrubs().unwrap().0;
+    assert_eq!(finished.len(),1);
+    let scrub = &finished[0].1;
+    assert!(!scrub.running && scrub.finished.is_some());
+    let mismatches: Vec<(&str,Option<u64>)> = scrub.devices.iter().map(|x| (x.device.as_str(),x.mismatches)).collect();
+    assert_eq!(mismatches,vec![(md_devices[0].as_str(),Some(8)),(md_devices[1].as_str(),Some(0))]);
+    clean_up(&monitor);
+}
+
+#[test]
+fn stopped_scrub_resumes_where_it_stopped() {
+    let (monitor,_) = monitor("resume");
+    monitor.start_scrub("test",ScrubAction::Repair,None).unwrap();
+    assert_eq!(get(&monitor,"md126","sync_action"),"repair");
+
+    // 512KiB chunks are 1024 sectors
+    set(&monitor,"md126","sync_completed","2500 / 7808");
+    set(&monitor,"md126","chunk_size","524288");
+    let scrub = monitor.stop_scrub("test").unwrap();
+    assert!(!scrub.running);
+    assert_eq!(scrub.devices[0].resume_from,2048);
+    assert_eq!(get(&monitor,"md126","sync_action"),"idle");
+    assert!(monitor.stop_scrub("test").is_err());
+
+    let scrub = monitor.start_scrub("test",ScrubAction::Check,None).unwrap();
+    assert_eq!(scrub.action,ScrubAction::Repair);
+    assert_eq!(get(&monitor,"md126","sync_action"),"repair");
+    assert_eq!(get(&monitor,"md126","sync_min"),"2048");
+
+    // md forgets the scrub on reboot
+    fs::write(&monitor.boot_id_file,"second\n").unwrap();
+    set(&monitor,"md126","sync_action","idle");
+    set(&monitor,"md126","sync_min","0");
+    assert_eq!(monitor.advance_scrubs().unwrap().0,vec![]);
+    assert_eq!(get(&monitor,"md126","sync_action"),"repair");
+    assert_eq!(get(&monitor,"md126","sync_min"),"2048");
+    clean_up(&monitor);
+}
+
+#[test]
+fn scheduled_scrub_starts_when_due() {
+    let (monitor,_) = monitor("schedule");
+    assert!(monitor.set_scrub_schedule("missing",Some(ScrubSchedule::Monthly)).is_err());
+
+    monitor.advance_scrubs().unwrap();
+    assert!(monitor.scrub_status("test").unwrap().scrub.is_none());
+
+    monitor.set_scrub_schedule("test",Some(ScrubSchedule::Monthly)).unwrap();
+    monitor.advance_scrubs().unwrap();
+    let status = monitor.scrub_status("test").unwrap();
+    assert_eq!(status.schedule,Some(ScrubSchedule::Monthly));
+    assert!(status.scrub.unwrap().running);
+    assert_eq!(get(&monitor,"md126","sync_action"),"check");
+    clean_up(&monitor);
+}
@@ -18,8 +18,8 @@
 
 use gpt::partition::Partition;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use hyraid_gpt;
+use std::{collections::HashMap, fmt, str::FromStr};
+use hyraid_utils::HyraidError;
 
 /**
 Raid Map
@@ -53,57 +53,564 @@ pub struct DiskPartition {
 }
 
 impl DiskPartition {
-    pub fn from(partition: &Partition) -> Self {
-        Self {
-            path: Some(hyraid_gpt::get_path_of_partition(&partition)),
-            size: (
-                partition
-                    .sectors_len()
-                    .unwrap() 
-                    * 
-                TryInto::<u64>::try_into(
-                    hyraid_gpt::get_sector_size(
-                        &hyraid_gpt::get_path_of_partition(&partition))
-                    ).unwrap()
-            ).try_into().unwrap()
-        }
+    pub fn from(partition: &Partition) -> Result<Self,HyraidError> {
+        let path = hyraid_gpt::get_path_of_partition(partition);
+        let sector_size = hyraid_gpt::get_sector_size(&path)?;
+
+        Ok(Self {
+            size: partition_size(partition,sector_size)?,
+            path: Some(path)
+        })
     }
 }
 
+/// Size of a partition in bytes
+fn partition_size(partition: &Partition, sector_size: usize) -> Result<usize,HyraidError> {
+    let sectors = partition
+        .sectors_len()
+        .map_err(|err| HyraidError::Gpt(format!("Invalid partition {}: {}",partition.part_guid,err)))?;
+
+    Ok(sectors as usize * sector_size)
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Disk {
     pub partitions: Vec<DiskPartition>
 }
 
 impl Disk {
-    pub fn from(disk: gpt::GptDisk<std::fs::File>,sector_size: usize) -> Self {
+    pub fn from(disk: gpt::GptDisk<std::fs::File>,sector_size: usize) -> Result<Self,HyraidError> {
         let mut parts: Vec<DiskPartition> = vec![];
         for partition in disk.partitions().values() {
             parts.push(
                 DiskPartition { 
-                    path: Some(hyraid_gpt::get_path_of_partition(&partition)),
-                    size: (
-                        partition.sectors_len().unwrap() 
-                        * 
-                        TryInto::<u64>::try_into(sector_size).unwrap()
-                    ).try_into().unwrap()
+                    path: Some(hyraid_gpt::get_path_of_partition(partition)),
+                    size: partition_size(partition,sector_size)?
                 }
             )
         };
-        Self { partitions: parts }
+        Ok(Self { partitions: parts })
+    }
+}
+
+/// How the RAID level of every slice group is picked.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum RedundancyPolicy {
+    /// The RAID level of the array, falling back to RAID1 on groups too small for it
+    #[default]
+    RaidLevel,
+    /// Survive any two disk failures: RAID6 on groups of 4 or more, a 3-way RAID1 otherwise
+    TwoDisk,
+}
+
+impl fmt::Display for RedundancyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RedundancyPolicy::RaidLevel => write!(f,"raid-level"),
+            RedundancyPolicy::TwoDisk => write!(f,"two-disk"),
+        }
+    }
+}
+
+impl FromStr for RedundancyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self,Self::Err> {
+        match s {
+            "raid-level" => Ok(RedundancyPolicy::RaidLevel),
+            "two-disk" => Ok(RedundancyPolicy::TwoDisk),
+            _ => Err(format!("unknown redundancy policy \"{}\", expected raid-level or two-disk",s))
+        }
+    }
+}
+
+/// What md does to the md devices of a scrub
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum ScrubAction {
+    /// Count inconsistencies without fixing them
+    #[default]
+    Check,
+    /// Rewrite parity or mirrors where they're inconsistent
+    Repair,
+}
+
+impl fmt::Display for ScrubAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScrubAction::Check => write!(f,"check"),
+            ScrubAction::Repair => write!(f,"repair"),
+        }
+    }
+}
+
+/// How often the monitor scrubs an array
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ScrubSchedule {
+    Weekly,
+    Monthly,
+}
+
+impl ScrubSchedule {
+    /// Seconds between the starts of two scrubs
+    pub fn interval(&self) -> u64 {
+        match self {
+            ScrubSchedule::Weekly => 7 * 24 * 3600,
+            ScrubSchedule::Monthly => 30 * 24 * 3600,
+        }
+    }
+}
+
+impl fmt::Display for ScrubSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScrubSchedule::Weekly => write!(f,"weekly"),
+            ScrubSchedule::Monthly => write!(f,"monthly"),
+        }
     }
 }
 
+impl FromStr for ScrubSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self,Self::Err> {
+        match s {
+            "weekly" => Ok(ScrubSchedule::Weekly),
+            "monthly" => Ok(ScrubSchedule::Monthly),
+            _ => Err(format!("unknown scrub schedule \"{}\", expected weekly or monthly",s))
+        }
+    }
+}
+
+/// Scrub of one md device
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MdScrub {
+    pub device: String,
+    /// Sector to carry on from, kept when the scrub is stopped
+    pub resume_from: u64,
+    /// `mismatch_cnt` of the md device once it's been scrubbed
+    pub mismatches: Option<u64>,
+}
+
+/// Scrub of an array, going through its md devices one at a time
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScrubState {
+    pub action: ScrubAction,
+    /// Bytes per second, `None` for the limit md has by default
+    pub speed_limit: Option<usize>,
+    /// Unset once stopped, starting it again carries on where it stopped
+    pub running: bool,
+    /// Unix time
+    pub started: u64,
+    /// Unix time
+    pub finished: Option<u64>,
+    /// Boot the md device being scrubbed was started in, since md forgets a check on reboot
+    pub boot_id: Option<String>,
+    pub devices: Vec<MdScrub>,
+}
+
+/// Scrub of an array as `hyraid scrub status` shows it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScrubStatus {
+    pub name: String,
+    pub scrub: Option<ScrubState>,
+    pub schedule: Option<ScrubSchedule>,
+    /// md device being scrubbed and how far along it is, in percent
+    pub current: Option<(String,f32)>,
+}
+
 /// Struct representing a HyRAID array.
 /// 
 /// Can be (de)serialized with serde
 #[derive(Serialize, Deserialize, Clone)]
 pub struct HyraidArray {
     pub name: String,
+    /// Stored in the GPT name of every partition, `None` for arrays created before it was
+    pub uuid: Option<String>,
+    /// Bumped on every change to the layout, stored in the partitions created by it
+    pub generation: usize,
     pub lvm_lv_path: String,
     pub raid_level: usize,
+    pub policy: RedundancyPolicy,
     pub disks: Vec<Disk>,
     pub raid_map: RaidMap,
     pub slices: PartitionSlices,
     pub part_map: PartitionMap,
+    /// Disks attached as md spares and their partitions, kept out of `disks` and `part_map`
+    pub spares: PartitionMap,
+    /// Last or running scrub
+    pub scrub: Option<ScrubState>,
+    pub scrub_schedule: Option<ScrubSchedule>,
+}
+
+/// Operation recorded in the journal, with what it was called with
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum JournalOperation {
+    Create {
+        disks: Vec<String>,
+        raid_level: usize,
+        policy: RedundancyPolicy,
+        allow_degraded: bool
+    },
+    Add {
+        disks: Vec<String>,
+        allow_degraded: bool
+    },
+}
+
+impl fmt::Display for JournalOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JournalOperation::Create { .. } => write!(f,"create"),
+            JournalOperation::Add { .. } => write!(f,"add"),
+        }
+    }
+}
+
+/// One change an operation makes to the disks, md, LVM or the state file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum JournalStep {
+    /// Make sure the disk has a GPT and delete its partitions
+    WipeDisk { disk: String },
+    AddPartitions {
+        disk: String,
+        sizes: Vec<usize>,
+        array_uuid: String,
+        generation: usize
+    },
+    CreateMd {
+        device: String,
+        partitions: Vec<String>,
+        raid_level: usize
+    },
+    CreatePv { devices: Vec<String> },
+    CreateVg { volume_group: String, devices: Vec<String> },
+    ExtendVg { volume_group: String, devices: Vec<String> },
+    CreateLv { volume_group: String, devices: Vec<String> },
+    /// Add partitions to an md device of `members` members and reshape it, can't be rolled back
+    GrowMd {
+        device: String,
+        members: usize,
+        partitions: Vec<String>
+    },
+    /// Record the array in the state file
+    WriteState,
+}
+
+impl fmt::Display for JournalStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JournalStep::WipeDisk { disk } => write!(f,"wipe {}",disk),
+            JournalStep::AddPartitions { disk, sizes, .. } => write!(f,"add {} partitions to {}",sizes.len(),disk),
+            JournalStep::CreateMd { device, raid_level, .. } => write!(f,"create RAID{} md device {}",raid_level,device),
+            JournalStep::CreatePv { devices } => write!(f,"create physical volumes on {}",devices.join(", ")),
+            JournalStep::CreateVg { volume_group, .. } => write!(f,"create volume group {}",volume_group),
+            JournalStep::ExtendVg { volume_group, devices } => write!(f,"extend volume group {} with {}",volume_group,devices.join(", ")),
+            JournalStep::CreateLv { volume_group, .. } => write!(f,"create logical volume in {}",volume_group),
+            JournalStep::GrowMd { device, partitions, .. } => write!(f,"grow md device {} with {}",device,partitions.join(", ")),
+            JournalStep::WriteState => write!(f,"write the state file"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum StepState {
+    Planned,
+    /// Recorded as about to run, an interrupted step may or may not have happened
+    Started,
+    Done,
+}
+
+impl fmt::Display for StepState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepState::Planned => write!(f,"planned"),
+            StepState::Started => write!(f,"started"),
+            StepState::Done => write!(f,"done"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JournalEntry {
+    pub step: JournalStep,
+    pub state: StepState,
+}
+
+/// Write-ahead journal of an operation changing an array, every step is
+/// recorded before it runs so `hyraid recover` can resume or roll it back.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Journal {
+    pub name: String,
+    pub operation: JournalOperation,
+    /// Entry of the array before the operation, `None` for a new array
+    pub previous: Option<HyraidArray>,
+    /// Entry of the array once the operation is done, settled once the disks are partitioned
+    pub entry: Option<HyraidArray>,
+    pub steps: Vec<JournalEntry>,
+}
+
+/// Top-level document of the state file.
+///
+/// Older documents are upgraded by `hyraid_json` when read.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StateDocument {
+    pub schema_version: u32,
+    pub arrays: Vec<HyraidArray>,
+    /// Unpartitioned disks any array can take as a spare once it degrades
+    pub global_spares: Vec<String>,
+}
+
+/// One `mdadm --grow` taking an md device to another RAID level or number of devices.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MigrationStep {
+    /// RAID level converted to, `None` if it stays the same
+    pub raid_level: Option<usize>,
+    pub raid_devices: usize,
+    /// disks the md device can lose once the step is done
+    pub failures_tolerated: usize,
+}
+
+/// md device that a plan intends to create or grow.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MdPlan {
+    pub device: String,
+    pub raid_level: usize,
+    pub partitions: Vec<DiskPartition>,
+    /// Reshapes taking a grown md device to its new RAID level and size, empty for created ones
+    pub migration: Vec<MigrationStep>,
+}
+
+/// Changes a plan intends to make to the LVM volume group.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum LvmPlan {
+    /// Create a new volume group and a logical volume spanning all of it.
+    Create {
+        physical_volumes: Vec<String>
+    },
+    /// Extend the volume group of an existing array.
+    Extend {
+        volume_group: String,
+        /// md devices that become new physical volumes
+        created: Vec<String>,
+        /// md devices whose physical volumes are resized
+        resized: Vec<String>
+    },
+}
+
+/// Struct representing what HyRAID intends to do to the disks.
+///
+/// Partitions that do not exist yet carry a placeholder path
+/// naming the disk they will be created on.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HyraidPlan {
+    pub slices: PartitionSlices,
+    pub part_map: PartitionMap,
+    pub md_create: Vec<MdPlan>,
+    pub md_grow: Vec<MdPlan>,
+    pub lvm: LvmPlan,
+    pub redundancy: RedundancyReport,
+}
+
+/// Fault tolerance of the md device of one slice group.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SliceGroupRedundancy {
+    pub device: String,
+    pub raid_level: usize,
+    pub members: usize,
+    /// size of the smallest member in bytes
+    pub size: usize,
+    /// disks the group can lose without losing data, at the least while it's reshaped
+    pub failures_tolerated: usize,
+}
+
+/// What a layout survives, compared with what its RAID level and policy ask for.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RedundancyReport {
+    /// disk failures every slice group should survive
+    pub target: usize,
+    pub groups: Vec<SliceGroupRedundancy>,
+    /// bytes of every disk that aren't part of any md device
+    pub unused: Vec<(String,usize)>,
+}
+
+impl RedundancyReport {
+    /// Slice groups surviving fewer disk failures than the target
+    pub fn degraded(&self) -> Vec<&SliceGroupRedundancy> {
+        self.groups
+            .iter()
+            .filter(|x| x.failures_tolerated < self.target)
+            .collect()
+    }
+}
+
+/// Capacity of a layout in bytes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CapacityEstimate {
+    pub usable: usize,
+    /// space used for parity or mirroring
+    pub redundancy: usize,
+    /// space not part of any RAID array
+    pub unused: usize,
+}
+
+/// Capacity of HyRAID compared with classic RAID on the same disks.
+///
+/// Classic RAID levels are `None` when there aren't enough disks for them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CapacityReport {
+    pub raw: usize,
+    pub hyraid: CapacityEstimate,
+    pub raid1: Option<CapacityEstimate>,
+    pub raid5: Option<CapacityEstimate>,
+    pub raid6: Option<CapacityEstimate>,
+}
+
+/// Live state of an md device
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum MdHealth {
+    Clean,
+    /// One or more members are missing or faulty
+    Degraded,
+    /// Resync, recovery, reshape or check in progress
+    Syncing {
+        action: String,
+        percent: f32,
+        finish: Option<String>
+    },
+    Inactive,
+    /// Not listed in /proc/mdstat
+    Missing,
+}
+
+/// Status of an md device belonging to a HyRAID array
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MdDeviceStatus {
+    pub device: String,
+    /// Kernel name such as `md127`
+    pub kernel_name: Option<String>,
+    pub raid_level: usize,
+    pub health: MdHealth,
+    pub members: Vec<DiskPartition>,
+}
+
+/// Status of a HyRAID array
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArrayStatus {
+    pub name: String,
+    pub lvm_lv_path: String,
+    /// `None` if lvm couldn't report it
+    pub lv_size: Option<usize>,
+    pub raw_capacity: usize,
+    pub usable_capacity: usize,
+    pub md_devices: Vec<MdDeviceStatus>,
+    pub part_map: PartitionMap,
+}
+
+/// HyRAID arrays found from the metadata on the disks
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScanReport {
+    /// One per volume group, named after it
+    pub arrays: Vec<HyraidArray>,
+    /// md devices of HyRAID partitions that aren't in a volume group,
+    /// or whose volume group couldn't be read
+    pub orphan_md_devices: Vec<String>,
+    /// HyRAID partitions without an md superblock, on disks of no array
+    pub orphan_partitions: Vec<String>,
+}
+
+/// What swapping the smallest disk of an array for a larger one would unlock
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UpgradeReport {
+    /// Usable capacity with the disks as they are
+    pub usable: usize,
+    /// Smallest disk, the one to swap next
+    pub next_swap: String,
+    /// Size of the largest disk, which the next disk should at least match
+    pub swap_size: usize,
+    /// Usable capacity once the smallest disk is swapped for one of `swap_size`
+    pub usable_after_swap: usize,
+}
+
+/// Change in the state of a HyRAID array seen by the monitor.
+///
+/// `device` is the md device, or the logical volume for `ArrayInactive` and `CapacityLow`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum HyraidEvent {
+    /// A member partition was marked faulty
+    DeviceFailed { array: String, device: String, member: String },
+    /// An md device lost one or more members
+    Degraded { array: String, device: String, missing: usize },
+    /// Recovery or resync started on an md device
+    RebuildStarted { array: String, device: String, action: String },
+    RebuildFinished { array: String, device: String },
+    /// A spare started being recovered into an md device
+    SpareActivated { array: String, device: String, member: String },
+    /// An md device or the logical volume stopped or went missing
+    ArrayInactive { array: String, device: String },
+    /// The file system on the logical volume is fuller than the configured threshold
+    CapacityLow { array: String, device: String, used_percent: u8 },
+}
+
+impl HyraidEvent {
+    /// Name of the event, e.g. `DeviceFailed`
+    pub fn name(&self) -> &'static str {
+        match self {
+            HyraidEvent::DeviceFailed {..} => "DeviceFailed",
+            HyraidEvent::Degraded {..} => "Degraded",
+            HyraidEvent::RebuildStarted {..} => "RebuildStarted",
+            HyraidEvent::RebuildFinished {..} => "RebuildFinished",
+            HyraidEvent::SpareActivated {..} => "SpareActivated",
+            HyraidEvent::ArrayInactive {..} => "ArrayInactive",
+            HyraidEvent::CapacityLow {..} => "CapacityLow",
+        }
+    }
+
+    /// Name of the HyRAID array the event is about
+    pub fn array(&self) -> &str {
+        match self {
+            HyraidEvent::DeviceFailed { array,.. }
+            | HyraidEvent::Degraded { array,.. }
+            | HyraidEvent::RebuildStarted { array,.. }
+            | HyraidEvent::RebuildFinished { array,.. }
+            | HyraidEvent::SpareActivated { array,.. }
+            | HyraidEvent::ArrayInactive { array,.. }
+            | HyraidEvent::CapacityLow { array,.. } => array,
+        }
+    }
+
+    pub fn device(&self) -> &str {
+        match self {
+            HyraidEvent::DeviceFailed { device,.. }
+            | HyraidEvent::Degraded { device,.. }
+            | HyraidEvent::RebuildStarted { device,.. }
+            | HyraidEvent::RebuildFinished { device,.. }
+            | HyraidEvent::SpareActivated { device,.. }
+            | HyraidEvent::ArrayInactive { device,.. }
+            | HyraidEvent::CapacityLow { device,.. } => device,
+        }
+    }
+
+    /// Whether the event needs someone's attention, as opposed to being informational
+    pub fn is_critical(&self) -> bool {
+        matches!(self,
+            HyraidEvent::DeviceFailed {..}
+            | HyraidEvent::Degraded {..}
+            | HyraidEvent::ArrayInactive {..}
+            | HyraidEvent::CapacityLow {..}
+        )
+    }
+}
+
+impl fmt::Display for HyraidEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{}: {} on {}",self.array(),self.name(),self.device())?;
+        match self {
+            HyraidEvent::DeviceFailed { member,.. } => write!(f,", {} failed",member),
+            HyraidEvent::Degraded { missing,.. } => write!(f,", {} member(s) missing",missing),
+            HyraidEvent::RebuildStarted { action,.. } => write!(f,", {}",action),
+            HyraidEvent::SpareActivated { member,.. } => write!(f,", rebuilding onto {}",member),
+            HyraidEvent::CapacityLow { used_percent,.. } => write!(f,", {}% used",used_percent),
+            HyraidEvent::RebuildFinished {..} | HyraidEvent::ArrayInactive {..} => Ok(()),
+        }
+    }
 }
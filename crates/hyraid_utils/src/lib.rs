@@ -16,62 +16,266 @@
     51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 */
 
+use std::{
+    fmt,
+    process::Command,
+    sync::{
+        Arc,
+        Mutex,
+        atomic::{AtomicBool, Ordering}
+    }
+};
 use nix::unistd::{getuid,ROOT};
 
-pub fn is_root() -> bool {
-    return getuid() == ROOT;
-}
-
-#[macro_export]
-/// Macro to run command and return result.
-macro_rules! run_cmd {
-    ($cmd:expr) => {
-        match $cmd.output() {
-            Ok(_) => {
-                return Ok(())
-            }
-            Err(err) => {
-                return Err(err.to_string())
-            }
+/// Failure of an external command such as mdadm or lvm2
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandError {
+    pub argv: Vec<String>,
+    /// `None` if the command couldn't be run or was killed by a signal
+    pub code: Option<i32>,
+    pub stderr: String,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f,"`{}` exited with code {}",self.argv.join(" "),code)?,
+            None => write!(f,"`{}` failed",self.argv.join(" "))?
+        };
+        if !self.stderr.trim().is_empty() {
+            write!(f,": {}",self.stderr.trim())?;
         }
-    };
+        Ok(())
+    }
+}
+
+/// Output of a successful external command
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Errors returned by HyRAID crates.
+///
+/// Library code never exits the process, it returns this instead
+/// and leaves it to the caller to decide what to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HyraidError {
+    /// Reading or writing a disk or its partition table failed
+    Gpt(String),
+    /// mdadm failed
+    Mdadm(CommandError),
+    /// lvm2 failed
+    Lvm(CommandError),
+    /// Reading or writing the HyRAID state file failed
+    StateFile(String),
+    /// Invalid input, such as an unsupported RAID level or an unknown array
+    Validation(String),
+    /// Reading system state such as /proc/mdstat failed
+    System(String),
+    /// Stopped through a `CancelToken`
+    Cancelled,
 }
 
-/// Unwrap result but quit with exit code 1 instead of panicking
-#[macro_export]
-macro_rules! unwrap_or_exit {
-    ($result:expr,$expect:expr) => {{
-        match $result {
-            Ok(val) => val,
-            Err(_) => {
-                error_exit!($expect);
-            }
+impl fmt::Display for HyraidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HyraidError::Gpt(err) => write!(f,"Disk error: {}",err),
+            HyraidError::Mdadm(err) => write!(f,"mdadm error: {}",err),
+            HyraidError::Lvm(err) => write!(f,"LVM error: {}",err),
+            HyraidError::StateFile(err) => write!(f,"State file error: {}",err),
+            HyraidError::Validation(err) => write!(f,"{}",err),
+            HyraidError::System(err) => write!(f,"System error: {}",err),
+            HyraidError::Cancelled => write!(f,"Operation cancelled"),
         }
-    }};
-}
-
-/// Unwrap result but quit with exit code 1 instead of panicking, printing the error stored in result.
-#[macro_export]
-macro_rules! unwrap_or_exit_verbose {
-    ($result:expr,$expect:expr) => {{
-        match $result {
-            Ok(val) => val,
-            Err(err) => {
-                error_exit!($expect,err);
-            }
+    }
+}
+
+impl std::error::Error for HyraidError {}
+
+/// Asks a long running operation to stop.
+///
+/// Operations only check it where stopping leaves the array consistent,
+/// so one that already started changing the disks carries on until the next such point.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true,Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Returns `HyraidError::Cancelled` once cancelled
+    pub fn check(&self) -> Result<(),HyraidError> {
+        if self.is_cancelled() {
+            return Err(HyraidError::Cancelled);
         }
-    }}
-}
-/// Quit with exit code 1
-#[macro_export]
-macro_rules! error_exit {
-    ($error:expr) => {
-        eprintln!("{}",$error);
-        exit(1);
-    };
-    ($description:expr,$error2:expr) => {
-        eprintln!("{}",$description);
-        eprintln!("{}",$error2);
-        exit(1);
+        Ok(())
+    }
+}
+
+pub fn is_root() -> bool {
+    getuid() == ROOT
+}
+
+/// Parses a human readable size such as `4T`, `500G` or `3.5TiB` into bytes.
+///
+/// Suffixes without `i` are decimal, like the sizes printed on drives.
+pub fn parse_size(size: &str) -> Result<usize,HyraidError> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (number,suffix) = size.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| HyraidError::Validation(format!("Invalid size: {}",size)))?;
+    let multiplier: f64 = match suffix.to_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1e3,
+        "M" | "MB" => 1e6,
+        "G" | "GB" => 1e9,
+        "T" | "TB" => 1e12,
+        "P" | "PB" => 1e15,
+        "KIB" => 1024_f64,
+        "MIB" => 1024_f64.powi(2),
+        "GIB" => 1024_f64.powi(3),
+        "TIB" => 1024_f64.powi(4),
+        "PIB" => 1024_f64.powi(5),
+        _ => return Err(HyraidError::Validation(format!("Invalid size suffix: {}",suffix)))
     };
-}
\ No newline at end of file
+
+    Ok((number * multiplier) as usize)
+}
+
+/// Formats bytes as a human readable decimal size, e.g. `4.00 TB`
+pub fn format_size(bytes: usize) -> String {
+    let units = ["B","KB","MB","GB","TB","PB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1000.0 && unit < units.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}",bytes,units[0])
+    } else {
+        format!("{:.2} {}",size,units[unit])
+    }
+}
+
+/// Program and arguments of a command
+pub fn command_argv(cmd: &Command) -> Vec<String> {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy().to_string())
+        .collect()
+}
+
+/// Runs a command, capturing its output.
+///
+/// Returns an error if the command couldn't be run or exited unsuccessfully.
+pub fn run_command(cmd: &mut Command) -> Result<CommandOutput,CommandError> {
+    let argv = command_argv(cmd);
+
+    let output = cmd.output().map_err(|err| CommandError {
+        argv: argv.clone(),
+        code: None,
+        stderr: err.to_string()
+    })?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+    if !output.status.success() {
+        return Err(CommandError {
+            argv,
+            code: output.status.code(),
+            stderr
+        });
+    }
+
+    Ok(CommandOutput { stdout, stderr })
+}
+
+/// Runs external tools such as mdadm and lvm2.
+///
+/// Lets the callers be tested without root by swapping in `RecordingRunner`.
+pub trait CommandRunner: Send + Sync {
+    fn run(&self, cmd: &mut Command) -> Result<CommandOutput,CommandError>;
+}
+
+/// Runs commands on the system
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, cmd: &mut Command) -> Result<CommandOutput,CommandError> {
+        run_command(cmd)
+    }
+}
+
+/// Canned result of a command, `Err` holds the exit code
+pub type CommandResponse = Result<CommandOutput,Option<i32>>;
+
+/// Fake `CommandRunner` that records every command instead of running it.
+///
+/// Every command succeeds with empty output unless a response was set with `respond`.
+#[derive(Default)]
+pub struct RecordingRunner {
+    commands: Mutex<Vec<Vec<String>>>,
+    responses: Mutex<Vec<(Vec<String>,CommandResponse)>>,
+}
+
+impl RecordingRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers every command starting with `prefix` with `response`.
+    pub fn respond(&self, prefix: &[&str], response: CommandResponse) {
+        self.responses
+            .lock()
+            .unwrap()
+            .push((prefix.iter().map(|x| x.to_string()).collect(),response));
+    }
+
+    /// Every command run so far, as argv
+    pub fn commands(&self) -> Vec<Vec<String>> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn run(&self, cmd: &mut Command) -> Result<CommandOutput,CommandError> {
+        let argv = command_argv(cmd);
+        self.commands.lock().unwrap().push(argv.clone());
+
+        let responses = self.responses.lock().unwrap();
+        let response = responses
+            .iter()
+            .rev()
+            .find(|(prefix,_)| argv.starts_with(prefix));
+
+        match response {
+            Some((_,Ok(output))) => Ok(output.clone()),
+            Some((_,Err(code))) => Err(CommandError {
+                argv,
+                code: *code,
+                stderr: String::new()
+            }),
+            None => Ok(CommandOutput::default())
+        }
+    }
+}
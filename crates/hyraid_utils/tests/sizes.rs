@@ -0,0 +1,31 @@
+/*!
+    Tests for parsing and formatting sizes.
+*/
+
+use hyraid_utils::{HyraidError, format_size, parse_size};
+
+#[test]
+fn parses_decimal_and_binary_suffixes() {
+    assert_eq!(parse_size("4T").unwrap(),4_000_000_000_000);
+    assert_eq!(parse_size("4tb").unwrap(),4_000_000_000_000);
+    assert_eq!(parse_size("4TiB").unwrap(),4 * 1024_usize.pow(4));
+    assert_eq!(parse_size("1.5G").unwrap(),1_500_000_000);
+    assert_eq!(parse_size("512MiB").unwrap(),512 * 1024 * 1024);
+    assert_eq!(parse_size(" 4096 ").unwrap(),4096);
+    assert_eq!(parse_size("4096B").unwrap(),4096);
+}
+
+#[test]
+fn refuses_invalid_sizes() {
+    for size in ["","T","-4T","4X","4 TB","four"] {
+        assert!(matches!(parse_size(size),Err(HyraidError::Validation(_))),"{} was accepted",size);
+    }
+}
+
+#[test]
+fn formats_decimal_sizes() {
+    assert_eq!(format_size(512),"512 B");
+    assert_eq!(format_size(1500),"1.50 KB");
+    assert_eq!(format_size(4_000_000_000_000),"4.00 TB");
+    assert_eq!(format_size(parse_size("4TiB").unwrap()),"4.40 TB");
+}